@@ -0,0 +1,11 @@
+#!/bin/bash -e
+# リンカの map ファイルからバックトレース用のシンボル表を生成する
+#
+# 出力形式: 1 行 1 シンボルで "<16 桁の 16 進アドレス> <シンボル名>" (アドレス昇順)
+# usage: gen_symbols.sh <ferr_os.map> <KERNEL.SYM>
+MAP="$1"
+OUT="$2"
+
+awk '$1 ~ /^[0-9a-f]{4}:[0-9a-f]{8}$/ && $3 ~ /^[0-9a-f]{16}$/ { print $3, $2 }' "${MAP}" \
+  | if command -v rustfilt > /dev/null; then rustfilt; else cat; fi \
+  | sort > "${OUT}"
@@ -6,12 +6,28 @@ PATH_TO_EFI="$1"
 rm -rf mnt
 mkdir -p mnt/EFI/BOOT/
 cp ${PATH_TO_EFI} mnt/EFI/BOOT/BOOTX64.EFI
+# カーネルコマンドライン (例: KERNEL_CMDLINE="clocksource=hpet")
+if [ -n "${KERNEL_CMDLINE}" ]; then
+  echo "${KERNEL_CMDLINE}" > mnt/EFI/BOOT/CMDLINE.TXT
+fi
+# かな漢字変換辞書 (SKK 形式・UTF-8、例: IME_DICTIONARY=SKK-JISYO.utf8)
+if [ -n "${IME_DICTIONARY}" ]; then
+  cp "${IME_DICTIONARY}" mnt/EFI/BOOT/IME.DIC
+fi
+# かな・漢字のフォント (Unicode の 8x8 BDF、例: IME_FONT=misaki_gothic_unicode.bdf)
+if [ -n "${IME_FONT}" ]; then
+  cp "${IME_FONT}" mnt/EFI/BOOT/FONT.BDF
+fi
+if [ -f target/ferr_os.map ]; then
+  bash scripts/gen_symbols.sh target/ferr_os.map mnt/EFI/BOOT/KERNEL.SYM
+fi
 qemu-system-x86_64 \
   -m 4G \
   -drive if=pflash,format=raw,readonly=on,file=third_party/ovmf/OVMF_CODE.fd \
   -drive if=pflash,format=raw,file=third_party/ovmf/OVMF_VARS.fd \
   -drive format=raw,file=fat:rw:mnt \
   -vga std \
+  -serial stdio \
   -net none \
   -global driver=cfi.pflash01,property=secure,value=on \
   -device isa-debug-exit,iobase=0xf4,iosize=0x01 
\ No newline at end of file
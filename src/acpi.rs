@@ -0,0 +1,393 @@
+#![allow(dead_code)]
+//! ACPI テーブル
+//!
+//! - RSDP は UEFI の構成テーブルから ExitBootServices 前に取得しておく。
+//! - RSDP → XSDT を辿り、署名でテーブルを引けるようにする (チェックサムを検証する)。
+//! - MADT を解析し、ローカル APIC・IOAPIC・割り込みソースオーバーライド・NMI の情報を保持する。
+//! - FADT (PM タイマ・RTC の世紀レジスタ) と HPET テーブルから必要な値を取り出す。
+//! - テーブルはダイレクトマップ経由で参照する (ACPI 領域はフレームアロケータが払い出さない)。
+
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::ptr::read_unaligned;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Once;
+use x86_64::PhysAddr;
+use crate::efi::{self, EfiSystemTable};
+use crate::memory::phys_to_virt;
+
+pub type Result<T> = core::result::Result<T, &'static str>;
+
+static RSDP_ADDR: AtomicU64 = AtomicU64::new(0);
+static MADT: Once<Madt> = Once::new();
+static FADT: Once<Fadt> = Once::new();
+static HPET: Once<HpetInfo> = Once::new();
+
+/// RSDP (ACPI 2.0 以降)
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    _reserved: [u8; 3],
+}
+const _: () = assert!(size_of::<Rsdp>() == 36);
+
+/// 全 SDT 共通のヘッダ
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+const _: () = assert!(size_of::<SdtHeader>() == 36);
+
+/// UEFI の構成テーブルから RSDP の位置を記録する (ExitBootServices 前に呼ぶこと)
+pub fn find_rsdp(system_table: &EfiSystemTable) -> bool {
+    match efi::find_configuration_table(system_table, &efi::EFI_ACPI_20_TABLE_GUID) {
+        Some(addr) => {
+            RSDP_ADDR.store(addr, Ordering::Relaxed);
+            true
+        }
+        None => false,
+    }
+}
+
+/// RSDP・XSDT を検証し、FADT・HPET・MADT を解析する (ヒープ・ダイレクトマップ初期化後に呼ぶ)
+pub fn init() -> Result<()> {
+    let xsdt = xsdt()?;
+    if !checksum_ok(xsdt, table_len(xsdt)) {
+        return Err("XSDT checksum mismatch");
+    }
+    if let Some(fadt) = find_table(b"FACP") {
+        FADT.call_once(|| Fadt::parse(fadt));
+    }
+    if let Some(hpet) = find_table(b"HPET") {
+        HPET.call_once(|| HpetInfo::parse(hpet));
+    }
+    let madt = find_table(b"APIC").ok_or("MADT not found")?;
+    MADT.call_once(|| Madt::parse(madt));
+    Ok(())
+}
+
+/// 解析済みの MADT (`init` 前または MADT が無ければ `None`)
+pub fn madt() -> Option<&'static Madt> {
+    MADT.get()
+}
+
+/// 解析済みの FADT
+pub fn fadt() -> Option<&'static Fadt> {
+    FADT.get()
+}
+
+/// HPET テーブルの情報
+pub fn hpet() -> Option<&'static HpetInfo> {
+    HPET.get()
+}
+
+fn xsdt() -> Result<PhysAddr> {
+    let addr = RSDP_ADDR.load(Ordering::Relaxed);
+    if addr == 0 {
+        return Err("RSDP not found");
+    }
+    let rsdp_addr = PhysAddr::new(addr);
+    let rsdp: Rsdp = unsafe { read_phys(rsdp_addr, 0) };
+    if &rsdp.signature != b"RSD PTR " {
+        return Err("invalid RSDP signature");
+    }
+    if rsdp.revision < 2 || !checksum_ok(rsdp_addr, rsdp.length as usize) {
+        return Err("invalid RSDP");
+    }
+    Ok(PhysAddr::new(rsdp.xsdt_address))
+}
+
+/// 署名 `signature` を持つ最初のテーブルの物理アドレス
+pub fn find_table(signature: &[u8; 4]) -> Option<PhysAddr> {
+    let xsdt = xsdt().ok()?;
+    let count = (table_len(xsdt) - size_of::<SdtHeader>()) / size_of::<u64>();
+    (0..count)
+        .map(|i| {
+            let entry: u64 = unsafe { read_phys(xsdt, size_of::<SdtHeader>() + i * size_of::<u64>()) };
+            PhysAddr::new(entry)
+        })
+        .find(|&table| {
+            let header: SdtHeader = unsafe { read_phys(table, 0) };
+            &header.signature == signature && checksum_ok(table, header.length as usize)
+        })
+}
+
+fn table_len(table: PhysAddr) -> usize {
+    let header: SdtHeader = unsafe { read_phys(table, 0) };
+    header.length as usize
+}
+
+fn checksum_ok(table: PhysAddr, len: usize) -> bool {
+    let bytes = unsafe { core::slice::from_raw_parts(phys_to_virt(table).as_ptr::<u8>(), len) };
+    bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+}
+
+/// ACPI 領域内の `offset` にある値を読む
+///
+/// Safety: `base + offset` から `T` の大きさの範囲がファームウェアの ACPI テーブル内であること。
+pub(crate) unsafe fn read_phys<T: Copy>(base: PhysAddr, offset: usize) -> T {
+    read_unaligned(phys_to_virt(base + offset as u64).as_ptr::<T>())
+}
+
+/// 割り込み信号の極性
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+/// 割り込み信号のトリガモード
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TriggerMode {
+    Edge,
+    Level,
+}
+
+/// MPS INTI フラグ (極性・トリガモード、00 はバス既定)
+#[derive(Clone, Copy, Debug)]
+pub struct IntiFlags(pub u16);
+
+impl IntiFlags {
+    /// 極性 (バス既定なら `default`)
+    pub fn polarity(self, default: Polarity) -> Polarity {
+        match self.0 & 0b11 {
+            0b01 => Polarity::ActiveHigh,
+            0b11 => Polarity::ActiveLow,
+            _ => default,
+        }
+    }
+
+    /// トリガモード (バス既定なら `default`)
+    pub fn trigger_mode(self, default: TriggerMode) -> TriggerMode {
+        match (self.0 >> 2) & 0b11 {
+            0b01 => TriggerMode::Edge,
+            0b11 => TriggerMode::Level,
+            _ => default,
+        }
+    }
+}
+
+/// プロセッサ (ローカル APIC / x2APIC エントリ)
+#[derive(Clone, Copy, Debug)]
+pub struct Processor {
+    pub processor_uid: u32,
+    pub apic_id: u32,
+    /// 有効、または後からオンラインにできる
+    pub usable: bool,
+}
+
+/// IOAPIC エントリ
+#[derive(Clone, Copy, Debug)]
+pub struct IoApicEntry {
+    pub id: u8,
+    pub address: u32,
+    pub gsi_base: u32,
+}
+
+/// 割り込みソースオーバーライド (ISA IRQ → GSI の付け替え)
+#[derive(Clone, Copy, Debug)]
+pub struct InterruptOverride {
+    pub bus: u8,
+    pub source: u8,
+    pub gsi: u32,
+    pub flags: IntiFlags,
+}
+
+/// NMI に接続された GSI
+#[derive(Clone, Copy, Debug)]
+pub struct NmiSource {
+    pub gsi: u32,
+    pub flags: IntiFlags,
+}
+
+/// ローカル APIC の LINT ピンに接続された NMI
+#[derive(Clone, Copy, Debug)]
+pub struct LocalApicNmi {
+    /// 対象プロセッサの UID (`u32::MAX` は全プロセッサ)
+    pub processor_uid: u32,
+    pub lint: u8,
+    pub flags: IntiFlags,
+}
+
+/// 解析済みの MADT
+#[derive(Debug)]
+pub struct Madt {
+    pub local_apic_address: u64,
+    /// 8259 PIC が存在する (PCAT_COMPAT)
+    pub pcat_compat: bool,
+    pub processors: Vec<Processor>,
+    pub io_apics: Vec<IoApicEntry>,
+    pub overrides: Vec<InterruptOverride>,
+    pub nmi_sources: Vec<NmiSource>,
+    pub local_apic_nmis: Vec<LocalApicNmi>,
+}
+
+impl Madt {
+    fn parse(table: PhysAddr) -> Self {
+        let len = table_len(table);
+        // Safety: 各フィールドはテーブル長の範囲内で読む
+        let read_u8 = |off: usize| unsafe { read_phys::<u8>(table, off) };
+        let read_u16 = |off: usize| unsafe { read_phys::<u16>(table, off) };
+        let read_u32 = |off: usize| unsafe { read_phys::<u32>(table, off) };
+        let read_u64 = |off: usize| unsafe { read_phys::<u64>(table, off) };
+
+        let mut madt = Self {
+            local_apic_address: read_u32(36) as u64,
+            pcat_compat: read_u32(40) & 1 != 0,
+            processors: Vec::new(),
+            io_apics: Vec::new(),
+            overrides: Vec::new(),
+            nmi_sources: Vec::new(),
+            local_apic_nmis: Vec::new(),
+        };
+
+        let mut off = 44;
+        while off + 2 <= len {
+            let (kind, entry_len) = (read_u8(off), read_u8(off + 1) as usize);
+            if entry_len < 2 || off + entry_len > len {
+                break;
+            }
+            match kind {
+                0 => madt.processors.push(Processor {
+                    processor_uid: read_u8(off + 2) as u32,
+                    apic_id: read_u8(off + 3) as u32,
+                    usable: read_u32(off + 4) & 0b11 != 0,
+                }),
+                1 => madt.io_apics.push(IoApicEntry {
+                    id: read_u8(off + 2),
+                    address: read_u32(off + 4),
+                    gsi_base: read_u32(off + 8),
+                }),
+                2 => madt.overrides.push(InterruptOverride {
+                    bus: read_u8(off + 2),
+                    source: read_u8(off + 3),
+                    gsi: read_u32(off + 4),
+                    flags: IntiFlags(read_u16(off + 8)),
+                }),
+                3 => madt.nmi_sources.push(NmiSource {
+                    flags: IntiFlags(read_u16(off + 2)),
+                    gsi: read_u32(off + 4),
+                }),
+                4 => madt.local_apic_nmis.push(LocalApicNmi {
+                    processor_uid: match read_u8(off + 2) {
+                        0xFF => u32::MAX,
+                        uid => uid as u32,
+                    },
+                    flags: IntiFlags(read_u16(off + 3)),
+                    lint: read_u8(off + 5),
+                }),
+                5 => madt.local_apic_address = read_u64(off + 4),
+                9 => madt.processors.push(Processor {
+                    apic_id: read_u32(off + 4),
+                    usable: read_u32(off + 8) & 0b11 != 0,
+                    processor_uid: read_u32(off + 12),
+                }),
+                0xA => madt.local_apic_nmis.push(LocalApicNmi {
+                    flags: IntiFlags(read_u16(off + 2)),
+                    processor_uid: read_u32(off + 4),
+                    lint: read_u8(off + 8),
+                }),
+                _ => {}
+            }
+            off += entry_len;
+        }
+        madt
+    }
+
+    /// ISA IRQ `irq` に対応する GSI と極性・トリガモード
+    ///
+    /// オーバーライドが無ければ GSI = IRQ、アクティブハイ・エッジトリガ (ISA の既定)。
+    pub fn isa_irq(&self, irq: u8) -> (u32, Polarity, TriggerMode) {
+        match self.overrides.iter().find(|o| o.bus == 0 && o.source == irq) {
+            Some(o) => (
+                o.gsi,
+                o.flags.polarity(Polarity::ActiveHigh),
+                o.flags.trigger_mode(TriggerMode::Edge),
+            ),
+            None => (irq as u32, Polarity::ActiveHigh, TriggerMode::Edge),
+        }
+    }
+}
+
+/// Generic Address Structure のアドレス空間 ID
+const GAS_SYSTEM_MEMORY: u8 = 0;
+const GAS_SYSTEM_IO: u8 = 1;
+
+/// FADT から取り出した値
+#[derive(Clone, Copy, Debug)]
+pub struct Fadt {
+    /// PM タイマの I/O ポート
+    pub pm_timer_port: Option<u16>,
+    /// PM タイマが 32bit (TMR_VAL_EXT)。偽なら 24bit
+    pub pm_timer_32bit: bool,
+    /// CMOS の世紀レジスタの番号 (0 は無し)
+    pub century: u8,
+}
+
+impl Fadt {
+    fn parse(table: PhysAddr) -> Self {
+        let len = table_len(table);
+        // Safety: 各フィールドはテーブル長の範囲内で読む
+        let pm_tmr_blk = unsafe { read_phys::<u32>(table, 76) };
+        let pm_tmr_len = unsafe { read_phys::<u8>(table, 91) };
+        let century = unsafe { read_phys::<u8>(table, 108) };
+        let flags = unsafe { read_phys::<u32>(table, 112) };
+
+        // ACPI 2.0 以降は X_PM_TMR_BLK を優先する
+        let x_pm_tmr = if len >= 220 {
+            let space = unsafe { read_phys::<u8>(table, 208) };
+            let address = unsafe { read_phys::<u64>(table, 212) };
+            (space == GAS_SYSTEM_IO && address != 0).then_some(address as u16)
+        } else {
+            None
+        };
+        let legacy = (pm_tmr_blk != 0 && pm_tmr_len == 4).then_some(pm_tmr_blk as u16);
+        Self {
+            pm_timer_port: x_pm_tmr.or(legacy),
+            pm_timer_32bit: flags & (1 << 8) != 0,
+            century,
+        }
+    }
+}
+
+/// HPET テーブルから取り出した値
+#[derive(Clone, Copy, Debug)]
+pub struct HpetInfo {
+    /// レジスタ領域の物理アドレス
+    pub base_address: u64,
+    pub number: u8,
+    /// 周期モードで割り込みを出せる最小のカウント数
+    pub minimum_tick: u16,
+}
+
+impl HpetInfo {
+    fn parse(table: PhysAddr) -> Self {
+        // Safety: HPET テーブルは固定長 (56 バイト)
+        unsafe {
+            let space = read_phys::<u8>(table, 40);
+            let address = read_phys::<u64>(table, 44);
+            Self {
+                base_address: if space == GAS_SYSTEM_MEMORY { address } else { 0 },
+                number: read_phys::<u8>(table, 52),
+                minimum_tick: read_phys::<u16>(table, 53),
+            }
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//! IOAPIC
+//!
+//! - MADT に列挙された IOAPIC をすべてマップし、担当する GSI の範囲を記録する。
+//! - 初期化時に全リダイレクションエントリをマスクし、`irq` モジュールからの要求に応じて
+//!   ベクタ・極性・トリガモード・宛先を設定する。
+//! - レジスタは IOREGSEL / IOWIN の間接アクセスなので、IOAPIC ごとにロックを取る。
+
+use alloc::vec::Vec;
+use spin::{Mutex, Once};
+use x86_64::PhysAddr;
+use crate::acpi::{Madt, Polarity, TriggerMode};
+use crate::memory::vmalloc;
+
+const REG_SELECT: u64 = 0x00;
+const REG_WINDOW: u64 = 0x10;
+
+const IOAPIC_ID: u32 = 0x00;
+const IOAPIC_VERSION: u32 = 0x01;
+const IOAPIC_REDIRECTION: u32 = 0x10;
+
+const ENTRY_ACTIVE_LOW: u64 = 1 << 13;
+const ENTRY_LEVEL: u64 = 1 << 15;
+const ENTRY_MASKED: u64 = 1 << 16;
+
+static IO_APICS: Once<Vec<IoApic>> = Once::new();
+
+/// リダイレクションエントリの設定
+#[derive(Clone, Copy, Debug)]
+pub struct Redirection {
+    pub vector: u8,
+    pub polarity: Polarity,
+    pub trigger: TriggerMode,
+    /// 宛先の APIC ID (物理宛先モード)
+    pub dest: u8,
+    pub masked: bool,
+}
+
+impl Redirection {
+    fn encode(&self) -> u64 {
+        let mut entry = self.vector as u64 | (self.dest as u64) << 56;
+        if self.polarity == Polarity::ActiveLow {
+            entry |= ENTRY_ACTIVE_LOW;
+        }
+        if self.trigger == TriggerMode::Level {
+            entry |= ENTRY_LEVEL;
+        }
+        if self.masked {
+            entry |= ENTRY_MASKED;
+        }
+        entry
+    }
+}
+
+/// 1 つの IOAPIC
+pub struct IoApic {
+    pub id: u8,
+    pub gsi_base: u32,
+    /// リダイレクションエントリの数
+    pub entries: u32,
+    /// レジスタ領域の仮想アドレス
+    regs: Mutex<u64>,
+}
+
+impl IoApic {
+    fn read(base: u64, reg: u32) -> u32 {
+        // Safety: init でマップした IOAPIC のレジスタ領域
+        unsafe {
+            core::ptr::write_volatile((base + REG_SELECT) as *mut u32, reg);
+            core::ptr::read_volatile((base + REG_WINDOW) as *const u32)
+        }
+    }
+
+    fn write(base: u64, reg: u32, value: u32) {
+        unsafe {
+            core::ptr::write_volatile((base + REG_SELECT) as *mut u32, reg);
+            core::ptr::write_volatile((base + REG_WINDOW) as *mut u32, value);
+        }
+    }
+
+    /// この IOAPIC が `gsi` を担当しているか
+    pub fn handles(&self, gsi: u32) -> bool {
+        (self.gsi_base..self.gsi_base + self.entries).contains(&gsi)
+    }
+
+    fn write_entry(&self, index: u32, entry: u64) {
+        self.update_entry(index, |_| entry);
+    }
+
+    /// エントリを読み出し、`f` で書き換えた値を書き戻す
+    ///
+    /// 割り込みハンドラからも呼ばれるため、ロック中は割り込みを禁止する。
+    fn update_entry(&self, index: u32, f: impl FnOnce(u64) -> u64) {
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            let regs = self.regs.lock();
+            let base = *regs;
+            let (low_reg, high_reg) = (IOAPIC_REDIRECTION + index * 2, IOAPIC_REDIRECTION + index * 2 + 1);
+            let old = (Self::read(base, high_reg) as u64) << 32 | Self::read(base, low_reg) as u64;
+            let entry = f(old);
+            // 設定途中で配送されないよう、先にマスクしてから上位 → 下位の順に書く
+            Self::write(base, low_reg, old as u32 | ENTRY_MASKED as u32);
+            Self::write(base, high_reg, (entry >> 32) as u32);
+            Self::write(base, low_reg, entry as u32);
+        })
+    }
+}
+
+/// MADT の IOAPIC をすべてマップし、全エントリをマスクする
+pub fn init(madt: &Madt) -> Result<usize, &'static str> {
+    let mut io_apics = Vec::new();
+    for entry in &madt.io_apics {
+        // Safety: IOAPIC の MMIO 領域
+        let base = unsafe { vmalloc::map_mmio_permanent(PhysAddr::new(entry.address as u64), 0x20) }
+            .ok_or("failed to map IOAPIC")?
+            .as_u64();
+
+        let entries = ((IoApic::read(base, IOAPIC_VERSION) >> 16) & 0xFF) + 1;
+        let io_apic = IoApic { id: entry.id, gsi_base: entry.gsi_base, entries, regs: Mutex::new(base) };
+        for index in 0..entries {
+            io_apic.write_entry(index, ENTRY_MASKED);
+        }
+        log::info!(
+            "IOAPIC {}: id {:#x}, GSI {}-{}",
+            io_apics.len(),
+            IoApic::read(base, IOAPIC_ID) >> 24,
+            entry.gsi_base,
+            entry.gsi_base + entries - 1
+        );
+        io_apics.push(io_apic);
+    }
+    if io_apics.is_empty() {
+        return Err("no IOAPIC in MADT");
+    }
+    let count = io_apics.len();
+    IO_APICS.call_once(|| io_apics);
+    Ok(count)
+}
+
+fn find(gsi: u32) -> Option<&'static IoApic> {
+    IO_APICS.get()?.iter().find(|io| io.handles(gsi))
+}
+
+/// `gsi` のリダイレクションエントリを設定する
+pub fn set_redirection(gsi: u32, redirection: Redirection) -> Result<(), &'static str> {
+    let io_apic = find(gsi).ok_or("no IOAPIC handles this GSI")?;
+    io_apic.write_entry(gsi - io_apic.gsi_base, redirection.encode());
+    Ok(())
+}
+
+/// `gsi` をマスクする
+pub fn mask(gsi: u32) {
+    if let Some(io_apic) = find(gsi) {
+        io_apic.update_entry(gsi - io_apic.gsi_base, |entry| entry | ENTRY_MASKED);
+    }
+}
+
+/// `gsi` のマスクを解除する
+pub fn unmask(gsi: u32) {
+    if let Some(io_apic) = find(gsi) {
+        io_apic.update_entry(gsi - io_apic.gsi_base, |entry| entry & !ENTRY_MASKED);
+    }
+}
@@ -0,0 +1,283 @@
+#![allow(dead_code)]
+//! ローカル APIC
+//!
+//! - CPUID で x2APIC に対応していれば MSR 経由 (x2APIC モード)、そうでなければ
+//!   MMIO (xAPIC モード) でアクセスする。MMIO 領域はキャッシュ無効で vmalloc 領域にマップする。
+//! - ベースアドレスは MADT (アドレスオーバーライドを含む) を優先し、無ければ IA32_APIC_BASE から得る。
+//! - スプリアス割り込みベクタ・エラーベクタを設定し、LINT ピンは MADT の NMI 情報に従って設定する。
+//! - EOI 送信と IPI 送信の API を提供する。タイマは `timer`、IOAPIC は `ioapic` が扱う。
+
+pub mod ioapic;
+pub mod pic;
+pub mod timer;
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use spin::Once;
+use x86_64::registers::model_specific::Msr;
+use x86_64::PhysAddr;
+use crate::acpi::{Madt, Polarity, TriggerMode};
+use crate::cpu::CpuFeatures;
+use crate::memory::vmalloc;
+
+/// スプリアス割り込みのベクタ (下位 4bit が 1111 である必要がある)
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+/// APIC エラー割り込みのベクタ
+pub const ERROR_VECTOR: u8 = 0xFE;
+
+const IA32_APIC_BASE: u32 = 0x1B;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_X2APIC: u64 = 1 << 10;
+const APIC_BASE_ADDR_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+/// x2APIC の MSR 番号 = 0x800 + (xAPIC のオフセット >> 4)
+const X2APIC_MSR_BASE: u32 = 0x800;
+
+// レジスタオフセット (xAPIC の MMIO オフセット)
+pub const REG_ID: u32 = 0x020;
+pub const REG_VERSION: u32 = 0x030;
+pub const REG_TPR: u32 = 0x080;
+pub const REG_EOI: u32 = 0x0B0;
+pub const REG_SVR: u32 = 0x0F0;
+pub const REG_ESR: u32 = 0x280;
+pub const REG_ICR_LOW: u32 = 0x300;
+pub const REG_ICR_HIGH: u32 = 0x310;
+pub const REG_LVT_TIMER: u32 = 0x320;
+pub const REG_LVT_THERMAL: u32 = 0x330;
+pub const REG_LVT_PERF: u32 = 0x340;
+pub const REG_LVT_LINT0: u32 = 0x350;
+pub const REG_LVT_LINT1: u32 = 0x360;
+pub const REG_LVT_ERROR: u32 = 0x370;
+pub const REG_TIMER_INITIAL: u32 = 0x380;
+pub const REG_TIMER_CURRENT: u32 = 0x390;
+pub const REG_TIMER_DIVIDE: u32 = 0x3E0;
+
+/// LVT のマスクビット
+pub const LVT_MASKED: u32 = 1 << 16;
+const LVT_DELIVERY_NMI: u32 = 0b100 << 8;
+const LVT_ACTIVE_LOW: u32 = 1 << 13;
+const LVT_LEVEL: u32 = 1 << 15;
+const SVR_ENABLE: u32 = 1 << 8;
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+
+static LOCAL_APIC: Once<LocalApic> = Once::new();
+/// 最後に報告された APIC エラー (ESR)
+static LAST_ERROR: AtomicU32 = AtomicU32::new(0);
+
+/// アクセス方式
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApicMode {
+    /// MMIO (仮想アドレス)
+    XApic(u64),
+    X2Apic,
+}
+
+/// ローカル APIC へのアクセス
+#[derive(Debug)]
+pub struct LocalApic {
+    mode: ApicMode,
+}
+
+impl LocalApic {
+    pub fn mode(&self) -> ApicMode {
+        self.mode
+    }
+
+    pub fn read(&self, reg: u32) -> u32 {
+        match self.mode {
+            // Safety: init でマップした APIC レジスタ領域内
+            ApicMode::XApic(base) => unsafe { core::ptr::read_volatile((base + reg as u64) as *const u32) },
+            ApicMode::X2Apic => unsafe { Msr::new(X2APIC_MSR_BASE + (reg >> 4)).read() as u32 },
+        }
+    }
+
+    pub fn write(&self, reg: u32, value: u32) {
+        match self.mode {
+            ApicMode::XApic(base) => unsafe { core::ptr::write_volatile((base + reg as u64) as *mut u32, value) },
+            ApicMode::X2Apic => unsafe { Msr::new(X2APIC_MSR_BASE + (reg >> 4)).write(value as u64) },
+        }
+    }
+
+    /// この CPU の APIC ID
+    pub fn id(&self) -> u32 {
+        match self.mode {
+            ApicMode::XApic(_) => self.read(REG_ID) >> 24,
+            ApicMode::X2Apic => self.read(REG_ID),
+        }
+    }
+
+    pub fn eoi(&self) {
+        self.write(REG_EOI, 0);
+    }
+
+    /// ESR を読み取ってクリアする
+    fn take_error(&self) -> u32 {
+        // xAPIC では読み取り前に書き込みが必要
+        self.write(REG_ESR, 0);
+        self.read(REG_ESR)
+    }
+
+    fn send_icr(&self, dest: u32, low: u32) {
+        match self.mode {
+            ApicMode::XApic(_) => x86_64::instructions::interrupts::without_interrupts(|| {
+                // 上位 (宛先) を先に書き、下位への書き込みで送信される
+                self.write(REG_ICR_HIGH, dest << 24);
+                self.write(REG_ICR_LOW, low);
+                while self.read(REG_ICR_LOW) & ICR_DELIVERY_PENDING != 0 {
+                    core::hint::spin_loop();
+                }
+            }),
+            // x2APIC の ICR は 1 つの 64bit MSR
+            ApicMode::X2Apic => unsafe {
+                Msr::new(X2APIC_MSR_BASE + (REG_ICR_LOW >> 4)).write((dest as u64) << 32 | low as u64)
+            },
+        }
+    }
+}
+
+/// IPI の種類
+#[derive(Clone, Copy, Debug)]
+pub enum Ipi {
+    Fixed(u8),
+    Nmi,
+    Init,
+    /// ベクタは起動コードの物理ページ番号
+    Startup(u8),
+}
+
+/// IPI の宛先
+#[derive(Clone, Copy, Debug)]
+pub enum IpiDestination {
+    /// APIC ID 指定
+    Physical(u32),
+    SelfOnly,
+    AllIncludingSelf,
+    AllExcludingSelf,
+}
+
+/// ローカル APIC を有効化する
+///
+/// `madt` はベースアドレスと LINT ピンの NMI 設定に使う (無ければ MSR と既定値を使う)。
+pub fn init(madt: Option<&Madt>) -> Result<ApicMode, &'static str> {
+    let features = CpuFeatures::detect();
+    if !features.apic {
+        return Err("local APIC not supported");
+    }
+    // PIC の割り込みが APIC 経由の割り込みと混ざらないよう先に止める
+    if madt.is_none_or(|m| m.pcat_compat) {
+        pic::disable();
+    }
+
+    let mut base_msr = Msr::new(IA32_APIC_BASE);
+    // Safety: IA32_APIC_BASE は APIC 対応 CPU に必ず存在する
+    let base = unsafe { base_msr.read() };
+    let mode = if features.x2apic {
+        // xAPIC → x2APIC の順に有効化する必要がある
+        unsafe {
+            base_msr.write(base | APIC_BASE_ENABLE);
+            base_msr.write(base | APIC_BASE_ENABLE | APIC_BASE_X2APIC);
+        }
+        ApicMode::X2Apic
+    } else {
+        unsafe { base_msr.write(base | APIC_BASE_ENABLE) };
+        let phys = madt.map_or(base & APIC_BASE_ADDR_MASK, |m| m.local_apic_address);
+        // Safety: APIC の MMIO 領域 (4KiB)
+        let virt = unsafe { vmalloc::map_mmio_permanent(PhysAddr::new(phys), 0x1000) }
+            .ok_or("failed to map local APIC")?;
+        ApicMode::XApic(virt.as_u64())
+    };
+
+    let apic = LOCAL_APIC.call_once(|| LocalApic { mode });
+
+    // 割り込み源はまだ無いので、すべてマスクした状態から始める
+    for reg in [REG_LVT_TIMER, REG_LVT_THERMAL, REG_LVT_PERF, REG_LVT_LINT0, REG_LVT_LINT1] {
+        apic.write(reg, LVT_MASKED);
+    }
+    if let Some(madt) = madt {
+        configure_nmi_pins(apic, madt);
+    }
+    apic.write(REG_LVT_ERROR, ERROR_VECTOR as u32);
+    apic.take_error();
+    apic.write(REG_TPR, 0);
+    apic.write(REG_SVR, SVR_ENABLE | SPURIOUS_VECTOR as u32);
+    // 取り残された割り込みを確定させておく
+    apic.eoi();
+    Ok(mode)
+}
+
+/// MADT の Local APIC NMI エントリに従って LINT0/1 を NMI にする
+fn configure_nmi_pins(apic: &LocalApic, madt: &Madt) {
+    let id = apic.id();
+    let uid = madt.processors.iter().find(|p| p.apic_id == id).map(|p| p.processor_uid);
+    for nmi in &madt.local_apic_nmis {
+        if nmi.processor_uid != u32::MAX && Some(nmi.processor_uid) != uid {
+            continue;
+        }
+        let mut lvt = LVT_DELIVERY_NMI;
+        if nmi.flags.polarity(Polarity::ActiveHigh) == Polarity::ActiveLow {
+            lvt |= LVT_ACTIVE_LOW;
+        }
+        if nmi.flags.trigger_mode(TriggerMode::Edge) == TriggerMode::Level {
+            lvt |= LVT_LEVEL;
+        }
+        match nmi.lint {
+            0 => apic.write(REG_LVT_LINT0, lvt),
+            1 => apic.write(REG_LVT_LINT1, lvt),
+            _ => {}
+        }
+    }
+}
+
+/// 初期化済みのローカル APIC
+pub fn local_apic() -> Option<&'static LocalApic> {
+    LOCAL_APIC.get()
+}
+
+/// 割り込み処理の終了を通知する
+pub fn eoi() {
+    if let Some(apic) = LOCAL_APIC.get() {
+        apic.eoi();
+    }
+}
+
+/// この CPU の APIC ID (未初期化なら 0)
+pub fn id() -> u32 {
+    LOCAL_APIC.get().map_or(0, |apic| apic.id())
+}
+
+/// IPI を送信する
+pub fn send_ipi(dest: IpiDestination, ipi: Ipi) -> Result<(), &'static str> {
+    let apic = LOCAL_APIC.get().ok_or("local APIC not initialized")?;
+    let (vector, delivery) = match ipi {
+        Ipi::Fixed(vector) => (vector, 0b000),
+        Ipi::Nmi => (0, 0b100),
+        Ipi::Init => (0, 0b101),
+        Ipi::Startup(page) => (page, 0b110),
+    };
+    let (dest_id, shorthand) = match dest {
+        IpiDestination::Physical(id) => (id, 0b00),
+        IpiDestination::SelfOnly => (0, 0b01),
+        IpiDestination::AllIncludingSelf => (0, 0b10),
+        IpiDestination::AllExcludingSelf => (0, 0b11),
+    };
+    if matches!(apic.mode, ApicMode::XApic(_)) && dest_id > 0xFF {
+        return Err("APIC ID out of range for xAPIC");
+    }
+    let low = vector as u32 | delivery << 8 | ICR_LEVEL_ASSERT | shorthand << 18;
+    apic.send_icr(dest_id, low);
+    Ok(())
+}
+
+/// APIC エラー割り込みの処理
+pub fn handle_error() {
+    if let Some(apic) = LOCAL_APIC.get() {
+        let esr = apic.take_error();
+        LAST_ERROR.store(esr, Ordering::Relaxed);
+        log::error!("APIC error: ESR={:#x}", esr);
+        apic.eoi();
+    }
+}
+
+/// 最後に報告された APIC エラー
+pub fn last_error() -> u32 {
+    LAST_ERROR.load(Ordering::Relaxed)
+}
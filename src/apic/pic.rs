@@ -0,0 +1,73 @@
+//! レガシー 8259 PIC
+//!
+//! - ローカル APIC / IOAPIC を使うため PIC は使わないが、電源投入時の設定のままだと
+//!   IRQ0-7 が例外ベクタ 8-15 に重なるため、0x20-0x2F へ付け替えてから全マスクする。
+//! - マスク後も IRQ7 / IRQ15 のスプリアス割り込みは届きうるので、
+//!   0x20-0x2F は PIC 用に予約しておく。スプリアスには EOI を送らないが、IRQ15 のときは
+//!   マスタがカスケード (IRQ2) を受け付けているので、マスタにだけ EOI を送る。
+
+use x86_64::instructions::port::Port;
+
+/// マスタ PIC の割り込みベクタの先頭
+pub const PIC1_OFFSET: u8 = 0x20;
+/// スレーブ PIC の割り込みベクタの先頭
+pub const PIC2_OFFSET: u8 = 0x28;
+
+const PIC1_COMMAND: u16 = 0x20;
+const PIC1_DATA: u16 = 0x21;
+const PIC2_COMMAND: u16 = 0xA0;
+const PIC2_DATA: u16 = 0xA1;
+
+const ICW1_INIT: u8 = 0x10;
+const ICW1_ICW4: u8 = 0x01;
+const ICW4_8086: u8 = 0x01;
+const OCW2_EOI: u8 = 0x20;
+
+/// PIC のベクタを付け替え、全 IRQ をマスクする
+pub fn disable() {
+    let mut cmd1 = Port::<u8>::new(PIC1_COMMAND);
+    let mut data1 = Port::<u8>::new(PIC1_DATA);
+    let mut cmd2 = Port::<u8>::new(PIC2_COMMAND);
+    let mut data2 = Port::<u8>::new(PIC2_DATA);
+    // Safety: 8259 の標準的な初期化シーケンス (ICW1-4)
+    unsafe {
+        cmd1.write(ICW1_INIT | ICW1_ICW4);
+        io_wait();
+        cmd2.write(ICW1_INIT | ICW1_ICW4);
+        io_wait();
+        data1.write(PIC1_OFFSET);
+        io_wait();
+        data2.write(PIC2_OFFSET);
+        io_wait();
+        // マスタの IR2 にスレーブを接続
+        data1.write(1 << 2);
+        io_wait();
+        data2.write(2);
+        io_wait();
+        data1.write(ICW4_8086);
+        io_wait();
+        data2.write(ICW4_8086);
+        io_wait();
+
+        data1.write(0xFF);
+        data2.write(0xFF);
+    }
+}
+
+/// ベクタが PIC 用の予約範囲か
+pub fn is_pic_vector(vector: u8) -> bool {
+    (PIC1_OFFSET..PIC2_OFFSET + 8).contains(&vector)
+}
+
+/// PIC のベクタに届いた割り込み (マスク中なのでスプリアス) の後始末
+pub fn handle_spurious(vector: u8) {
+    if (PIC2_OFFSET..PIC2_OFFSET + 8).contains(&vector) {
+        // Safety: マスタへの非特定 EOI はカスケード IRQ2 の受付を終えるだけ
+        unsafe { Port::<u8>::new(PIC1_COMMAND).write(OCW2_EOI) };
+    }
+}
+
+/// 未使用ポート 0x80 への書き込みで PIC の処理を待つ
+fn io_wait() {
+    unsafe { Port::<u8>::new(0x80).write(0) };
+}
@@ -0,0 +1,169 @@
+//! ローカル APIC タイマ
+//!
+//! - 基準タイマ (HPET・ACPI PM タイマ、無ければ PIT) で一定時間を計り、
+//!   その間の APIC タイマのカウント数と TSC の増分から 1ms あたりのカウント数を求める。
+//! - 周期モード・ワンショットモード・TSC デッドラインモードで一定周期のティックを発生させる。
+//!   ワンショット系のモードでは、ティックごとに `rearm` で次の割り込みを設定し直す。
+
+use core::arch::x86_64::{_mm_mfence, _rdtsc};
+use core::sync::atomic::{AtomicU32, AtomicU64, AtomicU8, Ordering};
+use x86_64::registers::model_specific::Msr;
+use super::{local_apic, LVT_MASKED, REG_LVT_TIMER, REG_TIMER_CURRENT, REG_TIMER_DIVIDE, REG_TIMER_INITIAL};
+
+/// 分周比 16
+const DIVIDE_BY_16: u32 = 0b0011;
+const LVT_TIMER_PERIODIC: u32 = 0b01 << 17;
+const LVT_TIMER_TSC_DEADLINE: u32 = 0b10 << 17;
+const IA32_TSC_DEADLINE: u32 = 0x6E0;
+
+/// 較正に使う時間 (マイクロ秒)
+const CALIBRATION_US: u64 = 10_000;
+
+/// 1ms あたりの APIC タイマのカウント数 (分周後)
+static COUNTS_PER_MS: AtomicU32 = AtomicU32::new(0);
+/// 1ms あたりの TSC の増分
+static TSC_PER_MS: AtomicU64 = AtomicU64::new(0);
+
+/// 動作中のモード (`TimerMode as u8`、0 は停止中)
+static MODE: AtomicU8 = AtomicU8::new(0);
+/// 1 ティックあたりのカウント数 (ワンショット) または TSC 増分 (TSC デッドライン)
+static PERIOD: AtomicU64 = AtomicU64::new(0);
+/// 次の TSC デッドライン
+static NEXT_DEADLINE: AtomicU64 = AtomicU64::new(0);
+
+/// タイマの動作モード
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TimerMode {
+    Periodic = 1,
+    OneShot = 2,
+    /// IA32_TSC_DEADLINE への書き込みで発火時刻を指定する (CPU が対応している場合のみ)
+    TscDeadline = 3,
+}
+
+/// 較正結果
+#[derive(Clone, Copy, Debug)]
+pub struct Calibration {
+    pub counts_per_ms: u32,
+    pub tsc_per_ms: u64,
+}
+
+/// `wait_us` (指定マイクロ秒待つ関数) を基準に APIC タイマと TSC の速さを測る
+///
+/// 割り込みは発生させない。
+pub fn calibrate(wait_us: &dyn Fn(u64)) -> Result<Calibration, &'static str> {
+    let apic = local_apic().ok_or("local APIC not initialized")?;
+    apic.write(REG_LVT_TIMER, LVT_MASKED);
+    apic.write(REG_TIMER_DIVIDE, DIVIDE_BY_16);
+
+    let (elapsed, tsc) = x86_64::instructions::interrupts::without_interrupts(|| {
+        apic.write(REG_TIMER_INITIAL, u32::MAX);
+        let tsc_start = unsafe { _rdtsc() };
+        wait_us(CALIBRATION_US);
+        let remaining = apic.read(REG_TIMER_CURRENT);
+        let tsc_end = unsafe { _rdtsc() };
+        apic.write(REG_TIMER_INITIAL, 0);
+        (u32::MAX - remaining, tsc_end - tsc_start)
+    });
+
+    let ms = CALIBRATION_US / 1000;
+    let calibration = Calibration {
+        counts_per_ms: (elapsed as u64 / ms) as u32,
+        tsc_per_ms: tsc / ms,
+    };
+    if calibration.counts_per_ms == 0 {
+        return Err("APIC timer did not count");
+    }
+    COUNTS_PER_MS.store(calibration.counts_per_ms, Ordering::Relaxed);
+    TSC_PER_MS.store(calibration.tsc_per_ms, Ordering::Relaxed);
+    Ok(calibration)
+}
+
+/// 較正済みの 1ms あたりの TSC 増分 (未較正なら 0)
+pub fn tsc_per_ms() -> u64 {
+    TSC_PER_MS.load(Ordering::Relaxed)
+}
+
+/// `vector` に毎秒 `hz` 回のティックを発生させる
+pub fn start(vector: u8, hz: u32, mode: TimerMode) -> Result<(), &'static str> {
+    let apic = local_apic().ok_or("local APIC not initialized")?;
+    let counts_per_ms = COUNTS_PER_MS.load(Ordering::Relaxed) as u64;
+    if counts_per_ms == 0 {
+        return Err("APIC timer not calibrated");
+    }
+    let counts = (counts_per_ms * 1000 / hz as u64).clamp(1, u32::MAX as u64);
+
+    stop();
+    // 最初の割り込みで rearm が正しく動くよう、モードと周期を先に公開してから起動する
+    match mode {
+        TimerMode::Periodic | TimerMode::OneShot => {
+            PERIOD.store(counts, Ordering::Relaxed);
+            MODE.store(mode as u8, Ordering::Release);
+            let periodic = if mode == TimerMode::Periodic { LVT_TIMER_PERIODIC } else { 0 };
+            apic.write(REG_TIMER_DIVIDE, DIVIDE_BY_16);
+            apic.write(REG_LVT_TIMER, vector as u32 | periodic);
+            apic.write(REG_TIMER_INITIAL, counts as u32);
+        }
+        TimerMode::TscDeadline => {
+            if !crate::cpu::CpuFeatures::detect().tsc_deadline {
+                return Err("TSC-deadline mode not supported");
+            }
+            let period = TSC_PER_MS.load(Ordering::Relaxed) * 1000 / hz as u64;
+            if period == 0 {
+                return Err("TSC not calibrated");
+            }
+            apic.write(REG_LVT_TIMER, vector as u32 | LVT_TIMER_TSC_DEADLINE);
+            // LVT の書き込みを IA32_TSC_DEADLINE より先に確定させる
+            unsafe { _mm_mfence() };
+            let deadline = unsafe { _rdtsc() } + period;
+            PERIOD.store(period, Ordering::Relaxed);
+            NEXT_DEADLINE.store(deadline, Ordering::Relaxed);
+            MODE.store(mode as u8, Ordering::Release);
+            unsafe { Msr::new(IA32_TSC_DEADLINE).write(deadline) };
+        }
+    }
+    Ok(())
+}
+
+/// ワンショット系のモードで次のティックを設定する (ティックの割り込みハンドラから呼ぶ)
+pub fn rearm() {
+    let Some(apic) = local_apic() else { return };
+    let period = PERIOD.load(Ordering::Relaxed);
+    match mode() {
+        Some(TimerMode::OneShot) => apic.write(REG_TIMER_INITIAL, period as u32),
+        Some(TimerMode::TscDeadline) => {
+            // 前回のデッドライン基準で進めるので、ハンドラの遅れが累積しない
+            let now = unsafe { _rdtsc() };
+            let mut deadline = NEXT_DEADLINE.load(Ordering::Relaxed) + period;
+            if deadline <= now {
+                deadline = now + period;
+            }
+            NEXT_DEADLINE.store(deadline, Ordering::Relaxed);
+            unsafe { Msr::new(IA32_TSC_DEADLINE).write(deadline) };
+        }
+        _ => {}
+    }
+}
+
+/// タイマを止める
+pub fn stop() {
+    let previous = mode();
+    MODE.store(0, Ordering::Release);
+    if let Some(apic) = local_apic() {
+        if previous == Some(TimerMode::TscDeadline) {
+            unsafe { Msr::new(IA32_TSC_DEADLINE).write(0) };
+        }
+        apic.write(REG_LVT_TIMER, LVT_MASKED);
+        apic.write(REG_TIMER_INITIAL, 0);
+    }
+}
+
+/// 動作中のモード
+pub fn mode() -> Option<TimerMode> {
+    match MODE.load(Ordering::Acquire) {
+        1 => Some(TimerMode::Periodic),
+        2 => Some(TimerMode::OneShot),
+        3 => Some(TimerMode::TscDeadline),
+        _ => None,
+    }
+}
@@ -0,0 +1,165 @@
+#![allow(dead_code)]
+//! スタックバックトレース
+//!
+//! - `-Cforce-frame-pointers` によるフレームポインタ連鎖 ([rbp] = 呼び出し元の rbp,
+//!   [rbp+8] = 戻りアドレス) を辿る。
+//! - 辿る範囲は開始時の rbp を含む登録済みスタックの内側に限定し、壊れた連鎖で
+//!   未マップ領域を読んで二重フォルトしないようにする。登録済みスタックに無い場合
+//!   (UEFI から引き継いだブートスタックなど) はアイデンティティマップ内 (4GiB 未満) に限る。
+//! - シンボル表は ESP 上の `\EFI\BOOT\KERNEL.SYM` (`scripts/gen_symbols.sh` で生成) から
+//!   ExitBootServices 前に読み込む。panic 時にも使えるよう、ヒープを使わず生のテキストを
+//!   その場で走査して解決する。
+
+use core::fmt;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use x86_64::VirtAddr;
+use crate::efi::{self, EfiHandle, EfiSystemTable};
+use crate::kaslr;
+use crate::memory::stack;
+
+/// 辿る最大フレーム数
+pub const MAX_FRAMES: usize = 32;
+
+/// ESP 上のシンボル表のパス
+const SYMBOL_FILE: &str = "\\EFI\\BOOT\\KERNEL.SYM";
+
+/// アイデンティティマップの上限 (登録外スタックを辿る場合の範囲)
+const IDENTITY_MAP_END: u64 = 1 << 32;
+
+static SYMBOLS_PTR: AtomicPtr<u8> = AtomicPtr::new(core::ptr::null_mut());
+static SYMBOLS_LEN: AtomicUsize = AtomicUsize::new(0);
+
+/// ESP からシンボル表を読み込む (ExitBootServices 前に呼ぶこと)
+///
+/// 見つからなければバックトレースはアドレスのみで表示される。
+pub fn load_symbols(image_handle: EfiHandle, system_table: &EfiSystemTable) -> efi::Result<usize> {
+    let data = efi::read_file(image_handle, system_table, SYMBOL_FILE)?;
+    SYMBOLS_LEN.store(data.len(), Ordering::Relaxed);
+    // ptr を最後に公開し、読み手はこれが null でなければ len も有効とみなす
+    SYMBOLS_PTR.store(data.as_ptr() as *mut u8, Ordering::Release);
+    Ok(data.iter().filter(|&&b| b == b'\n').count())
+}
+
+fn symbol_table() -> Option<&'static [u8]> {
+    let ptr = SYMBOLS_PTR.load(Ordering::Acquire);
+    if ptr.is_null() {
+        return None;
+    }
+    // Safety: load_symbols で EfiLoaderData として確保した領域で、解放されることはない
+    Some(unsafe { core::slice::from_raw_parts(ptr, SYMBOLS_LEN.load(Ordering::Relaxed)) })
+}
+
+/// 解決済みシンボル
+#[derive(Clone, Copy, Debug)]
+pub struct Symbol {
+    pub name: &'static str,
+    pub offset: u64,
+}
+
+/// 実行時アドレスを `name+offset` に解決する
+///
+/// シンボル表は優先ベース基準のアドレスなので、KASLR のずれを差し引いてから引く。
+pub fn resolve(addr: u64) -> Option<Symbol> {
+    let table = symbol_table()?;
+    let addr = addr.wrapping_sub(kaslr::kernel_slide() as u64);
+
+    let mut best: Option<(u64, &'static [u8])> = None;
+    for line in table.split(|&b| b == b'\n') {
+        let Some((start, name)) = parse_line(line) else { continue };
+        if start <= addr && best.is_none_or(|(b, _)| start > b) {
+            best = Some((start, name));
+        }
+    }
+    let (start, name) = best?;
+    let name = core::str::from_utf8(name).unwrap_or("?");
+    Some(Symbol { name, offset: addr - start })
+}
+
+/// `<16 桁の 16 進アドレス> <シンボル名>` の 1 行を解釈する
+fn parse_line(line: &'static [u8]) -> Option<(u64, &'static [u8])> {
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let space = line.iter().position(|&b| b == b' ')?;
+    let (hex, name) = (&line[..space], &line[space + 1..]);
+    if hex.is_empty() || hex.len() > 16 || name.is_empty() {
+        return None;
+    }
+    let mut value = 0u64;
+    for &b in hex {
+        let digit = (b as char).to_digit(16)?;
+        value = value << 4 | digit as u64;
+    }
+    Some((value, name))
+}
+
+/// フレームポインタ連鎖をたどるイテレータ (戻りアドレスを返す)
+pub struct FrameWalker {
+    rbp: u64,
+    /// 辿ってよい範囲 [low, high)
+    bounds: (u64, u64),
+    remaining: usize,
+}
+
+impl FrameWalker {
+    /// `rbp` から呼び出し元へ遡る
+    pub fn new(rbp: u64) -> Self {
+        let bounds = match stack::find(VirtAddr::new_truncate(rbp)) {
+            Some(info) => (info.bottom.as_u64(), info.top.as_u64()),
+            None => (0x1000, IDENTITY_MAP_END),
+        };
+        Self { rbp, bounds, remaining: MAX_FRAMES }
+    }
+
+    fn frame_valid(&self, rbp: u64) -> bool {
+        rbp % 8 == 0 && rbp >= self.bounds.0 && rbp.saturating_add(16) <= self.bounds.1
+    }
+}
+
+impl Iterator for FrameWalker {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        if self.remaining == 0 || !self.frame_valid(self.rbp) {
+            return None;
+        }
+        self.remaining -= 1;
+        // Safety: frame_valid により rbp..rbp+16 はマップ済みのスタック内
+        let (next, ret) = unsafe { (*(self.rbp as *const u64), *((self.rbp + 8) as *const u64)) };
+        if ret == 0 {
+            return None;
+        }
+        // スタックは上位アドレスへ向かって遡るので、逆行したら次で打ち切る
+        self.rbp = if next > self.rbp { next } else { 0 };
+        Some(ret)
+    }
+}
+
+/// バックトレースの表示
+///
+/// `rip` を指定すると先頭フレームとして表示する (例外発生箇所など)。
+pub struct Backtrace {
+    pub rip: Option<u64>,
+    pub rbp: u64,
+}
+
+impl Backtrace {
+    /// 呼び出し元から遡るバックトレース
+    #[inline(always)]
+    pub fn capture() -> Self {
+        let rbp: u64;
+        unsafe { core::arch::asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack)) };
+        Self { rip: None, rbp }
+    }
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, addr) in self.rip.into_iter().chain(FrameWalker::new(self.rbp)).enumerate() {
+            write!(f, "#{:<2} {:#018x}", i, addr)?;
+            match resolve(addr) {
+                Some(sym) => writeln!(f, " {}+{:#x}", sym.name, sym.offset)?,
+                None => writeln!(f)?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+#![allow(dead_code)]
+//! カーネルコマンドライン
+//!
+//! - LoadedImage の起動オプション (UCS-2) を ExitBootServices 前に読み込む。
+//!   起動オプションが空なら ESP 上の `\EFI\BOOT\CMDLINE.TXT` を使う。
+//! - ヒープ初期化前に読むため、固定長のバッファに ASCII として保持する。
+//! - `key=value` と値なしのフラグを空白区切りで並べる (例: `clocksource=hpet quiet`)。
+
+use spin::Once;
+use crate::efi::{self, EfiHandle, EfiSystemTable};
+
+/// 保持できる最大長 (超えた分は切り捨てる)
+const MAX_LEN: usize = 512;
+const CMDLINE_FILE: &str = "\\EFI\\BOOT\\CMDLINE.TXT";
+
+struct Buffer {
+    bytes: [u8; MAX_LEN],
+    len: usize,
+}
+
+static CMDLINE: Once<Buffer> = Once::new();
+
+/// コマンドラインを読み込む (ExitBootServices 前に呼ぶこと)
+pub fn init(image_handle: EfiHandle, system_table: &EfiSystemTable) {
+    let mut buf = Buffer { bytes: [0; MAX_LEN], len: 0 };
+    if let Ok(options) = efi::load_options(image_handle, system_table) {
+        let chars = options.iter().take_while(|&&c| c != 0).map(|&c| c as u32);
+        buf.extend(chars);
+    }
+    // UEFI シェルから起動した場合、先頭はイメージ自身のパスになっている
+    buf.skip_image_path();
+    if buf.as_str().trim().is_empty() {
+        buf.len = 0;
+        if let Ok(file) = efi::read_file(image_handle, system_table, CMDLINE_FILE) {
+            buf.extend(file.iter().map(|&b| b as u32));
+        }
+    }
+    CMDLINE.call_once(|| buf);
+}
+
+impl Buffer {
+    /// ASCII 以外と制御文字は空白に置き換えて追加する
+    fn extend(&mut self, chars: impl Iterator<Item = u32>) {
+        for c in chars {
+            if self.len == MAX_LEN {
+                break;
+            }
+            self.bytes[self.len] = match c {
+                0x21..=0x7E => c as u8,
+                _ => b' ',
+            };
+            self.len += 1;
+        }
+    }
+
+    fn skip_image_path(&mut self) {
+        let s = self.as_str().trim_start();
+        let first = s.split(' ').next().unwrap_or("");
+        if first.len() >= 4 && first[first.len() - 4..].eq_ignore_ascii_case(".efi") {
+            let rest = s.len() - first.len();
+            self.bytes.copy_within(self.len - rest..self.len, 0);
+            self.len = rest;
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        // extend で ASCII のみ格納している
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+    }
+}
+
+/// コマンドライン全体 (未初期化なら空文字列)
+pub fn as_str() -> &'static str {
+    CMDLINE.get().map_or("", |buf| buf.as_str().trim())
+}
+
+/// 空白区切りの各項目
+pub fn args() -> impl Iterator<Item = &'static str> {
+    as_str().split(' ').filter(|arg| !arg.is_empty())
+}
+
+/// `key=value` の値 (同じキーが複数あれば最後のもの)
+pub fn get(key: &str) -> Option<&'static str> {
+    args()
+        .filter_map(|arg| arg.split_once('='))
+        .filter(|(k, _)| *k == key)
+        .map(|(_, v)| v)
+        .last()
+}
+
+/// 値なしのフラグ `flag` が指定されているか
+pub fn has(flag: &str) -> bool {
+    args().any(|arg| arg == flag)
+}
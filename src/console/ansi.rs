@@ -0,0 +1,172 @@
+//! ANSI / VT100 エスケープシーケンスの解析
+//!
+//! - 1 文字ずつ受け取り、表示する文字・制御文字・CSI シーケンス (`ESC [ ... 終端文字`)・
+//!   その他の ESC シーケンスに分ける。解釈はコンソール側で行う。
+//! - CSI のパラメータは `MAX_PARAMS` 個まで (超えた分は捨てる)。省略されたパラメータは 0。
+//!   `:` 区切り (`38:2:r:g:b` など) も `;` と同じに扱う。
+//! - 途中で ESC が来たら新しいシーケンスを始め、CAN / SUB が来たら捨てる。
+
+/// CSI のパラメータの最大数
+pub const MAX_PARAMS: usize = 16;
+
+const ESC: char = '\x1b';
+const CAN: char = '\x18';
+const SUB: char = '\x1a';
+
+/// 解析結果
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// 表示する文字
+    Print(char),
+    /// 制御文字 (`\n` `\r` `\t` `\x08` など)
+    Execute(char),
+    Csi(Csi),
+    /// `ESC` + 終端文字 (`ESC 7` など)
+    Esc(char),
+}
+
+/// CSI シーケンス
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Csi {
+    params: [u16; MAX_PARAMS],
+    len: usize,
+    /// `MAX_PARAMS` を超えた (以降のパラメータは捨てる)
+    overflowed: bool,
+    /// `?` などの非公開パラメータの印 (`ESC [ ? 25 h` の `?`)
+    pub private: Option<char>,
+    pub final_byte: char,
+}
+
+impl Csi {
+    const fn new() -> Self {
+        Self { params: [0; MAX_PARAMS], len: 0, overflowed: false, private: None, final_byte: '\0' }
+    }
+
+    pub fn params(&self) -> &[u16] {
+        &self.params[..self.len]
+    }
+
+    /// `index` 番目のパラメータ (省略・0 なら `default`)
+    pub fn param(&self, index: usize, default: u16) -> u16 {
+        match self.params().get(index) {
+            Some(&value) if value != 0 => value,
+            _ => default,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    /// `ESC` の後の中間文字 (`ESC ( B` など、終端文字まで読み飛ばす)
+    EscapeIntermediate,
+    Csi,
+}
+
+pub struct Parser {
+    state: State,
+    csi: Csi,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Self { state: State::Ground, csi: Csi::new() }
+    }
+
+    /// 1 文字進め、確定した動作があれば返す
+    pub fn advance(&mut self, ch: char) -> Option<Action> {
+        match ch {
+            ESC => {
+                self.state = State::Escape;
+                return None;
+            }
+            CAN | SUB => {
+                self.state = State::Ground;
+                return None;
+            }
+            _ => {}
+        }
+        match self.state {
+            State::Ground if is_control(ch) => Some(Action::Execute(ch)),
+            State::Ground => Some(Action::Print(ch)),
+            State::Escape => match ch {
+                '[' => {
+                    self.csi = Csi::new();
+                    self.state = State::Csi;
+                    None
+                }
+                '\x20'..='\x2f' => {
+                    self.state = State::EscapeIntermediate;
+                    None
+                }
+                c if is_control(c) => Some(Action::Execute(c)),
+                c => {
+                    self.state = State::Ground;
+                    Some(Action::Esc(c))
+                }
+            },
+            State::EscapeIntermediate => match ch {
+                '\x30'..='\x7e' => {
+                    self.state = State::Ground;
+                    None
+                }
+                c if is_control(c) => Some(Action::Execute(c)),
+                _ => None,
+            },
+            State::Csi => self.advance_csi(ch),
+        }
+    }
+
+    fn advance_csi(&mut self, ch: char) -> Option<Action> {
+        let csi = &mut self.csi;
+        match ch {
+            '0'..='9' => {
+                if csi.len == 0 {
+                    csi.len = 1;
+                }
+                if !csi.overflowed {
+                    let param = &mut csi.params[csi.len - 1];
+                    *param = param.saturating_mul(10).saturating_add(ch as u16 - '0' as u16);
+                }
+                None
+            }
+            ';' | ':' => {
+                // 先頭の区切りは省略された最初のパラメータ
+                if csi.len == 0 {
+                    csi.len = 1;
+                }
+                if csi.len < MAX_PARAMS {
+                    csi.len += 1;
+                } else {
+                    csi.overflowed = true;
+                }
+                None
+            }
+            '<'..='?' if csi.len == 0 && csi.private.is_none() => {
+                csi.private = Some(ch);
+                None
+            }
+            // 中間文字は使わない
+            '\x20'..='\x2f' | '<'..='?' => None,
+            '\x40'..='\x7e' => {
+                self.state = State::Ground;
+                let mut csi = core::mem::replace(&mut self.csi, Csi::new());
+                csi.final_byte = ch;
+                Some(Action::Csi(csi))
+            }
+            c if is_control(c) => Some(Action::Execute(c)),
+            _ => None,
+        }
+    }
+}
+
+fn is_control(ch: char) -> bool {
+    (ch as u32) < 0x20 || ch == '\x7f'
+}
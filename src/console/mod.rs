@@ -0,0 +1,655 @@
+#![allow(dead_code)]
+//! フレームバッファのテキストコンソール
+//!
+//! - 登録された `FrameBuffer` を文字セルの格子として使い、カーソル位置から順に書く。
+//!   右端で折り返し、下端ではフレームバッファの行をブロックコピーして 1 行スクロールする。
+//! - タブは `tab_width` 桁ごとの位置へ進める。`\r` は行頭へ、`\x08` は 1 桁戻る。
+//! - ANSI / VT100 のエスケープシーケンス (`ansi`) を解釈する。シリアル端末と同じ出力で同じ見た目になる。
+//!   - SGR: 16 色・256 色・24bit カラー、太字 (16 色は明るい色になる)、反転
+//!   - カーソル移動 (`A` `B` `C` `D` `E` `F` `G` `H` `d` `f`)、行・画面の消去 (`K` `J`)
+//!   - カーソル位置の保存・復元 (`ESC 7` / `ESC 8`、`CSI s` / `CSI u`)
+//!   - スクロール領域 (`CSI top;bottom r`) と領域内のスクロール (`S` `T`、`ESC D` / `ESC M`)
+//!   - カーソルの表示・非表示 (`CSI ?25 h` / `l`)。解釈しないシーケンスは読み捨てる。
+//! - カーソルはタイマで点滅させる (書き込み直後は必ず表示する)。
+//! - 画面の先頭から押し出された行はスクロールバックに残し、Shift+PageUp / PageDown で遡って表示する。
+//!   表示中に新しい出力があれば最下部に戻る。
+//! - セルの内容を保持しているので、カーソルの消去やスクロールバックの表示は描き直しで行う。
+
+pub mod ansi;
+
+use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use crate::drivers::keyboard::{KeyCode, KeyEvent};
+use crate::graphics::text::{CHAR_ADVANCE, GLYPH_WIDTH, LINE_HEIGHT};
+use crate::graphics::{FrameBuffer, COLOR_WHITE};
+use ansi::{Action, Csi, Parser};
+
+/// 画面端の余白
+const MARGIN: usize = 4;
+/// 既定の背景色
+pub const DEFAULT_BACKGROUND: u32 = 0x202020;
+pub const DEFAULT_FOREGROUND: u32 = COLOR_WHITE;
+const DEFAULT_TAB_WIDTH: usize = 8;
+/// スクロールバックに残す行数
+const SCROLLBACK_LINES: usize = 1000;
+const BLINK_INTERVAL: Duration = Duration::from_millis(500);
+/// カーソル (下線) の太さ
+const CURSOR_HEIGHT: usize = 2;
+
+/// xterm の既定の 16 色
+const PALETTE: [u32; 16] = [
+    0x000000, 0xCD0000, 0x00CD00, 0xCDCD00, 0x0000EE, 0xCD00CD, 0x00CDCD, 0xE5E5E5,
+    0x7F7F7F, 0xFF0000, 0x00FF00, 0xFFFF00, 0x5C5CFF, 0xFF00FF, 0x00FFFF, 0xFFFFFF,
+];
+
+/// SGR で指定された色
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Color {
+    Default,
+    /// 256 色のパレット番号
+    Indexed(u8),
+    Rgb(u32),
+}
+
+/// 256 色のパレット番号を 0xRRGGBB にする (16 色 + 6x6x6 の色立方体 + 24 段階の灰色)
+fn indexed_color(index: u8) -> u32 {
+    match index {
+        0..=15 => PALETTE[index as usize],
+        16..=231 => {
+            let level = |v: u8| if v == 0 { 0 } else { 55 + 40 * v as u32 };
+            let i = index - 16;
+            (level(i / 36) << 16) | (level(i / 6 % 6) << 8) | level(i % 6)
+        }
+        _ => {
+            let gray = 8 + 10 * (index - 232) as u32;
+            (gray << 16) | (gray << 8) | gray
+        }
+    }
+}
+
+/// 文字の属性 (SGR)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Attributes {
+    fg: Color,
+    bg: Color,
+    bold: bool,
+    reverse: bool,
+}
+
+impl Attributes {
+    const DEFAULT: Attributes = Attributes { fg: Color::Default, bg: Color::Default, bold: false, reverse: false };
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Cell {
+    ch: char,
+    fg: u32,
+    bg: u32,
+}
+
+/// `ESC 7` / `CSI s` で保存する状態
+#[derive(Clone, Copy)]
+struct SavedCursor {
+    col: usize,
+    row: usize,
+    attributes: Attributes,
+}
+
+pub struct Console {
+    fb: FrameBuffer<'static>,
+    cols: usize,
+    rows: usize,
+    /// 画面上のセル (`rows * cols`、行優先)
+    cells: Vec<Cell>,
+    /// 画面から押し出された行 (末尾が最新)
+    scrollback: VecDeque<Vec<Cell>>,
+    col: usize,
+    row: usize,
+    /// 既定の文字色・背景色
+    fg: u32,
+    bg: u32,
+    attributes: Attributes,
+    parser: Parser,
+    saved: Option<SavedCursor>,
+    /// スクロール領域の先頭・末尾の行 (末尾を含む)
+    top: usize,
+    bottom: usize,
+    tab_width: usize,
+    /// スクロールバックを遡っている行数 (0 なら最新の画面)
+    view: usize,
+    cursor_enabled: bool,
+    /// 点滅でカーソルを描いている状態か
+    cursor_drawn: bool,
+}
+
+impl Console {
+    pub fn new(mut fb: FrameBuffer<'static>, fg: u32, bg: u32) -> Self {
+        let cols = ((fb.width.saturating_sub(2 * MARGIN) + CHAR_ADVANCE - GLYPH_WIDTH) / CHAR_ADVANCE).max(1);
+        let rows = (fb.height.saturating_sub(2 * MARGIN) / LINE_HEIGHT).max(1);
+        fb.clear(bg);
+        Self {
+            fb,
+            cols,
+            rows,
+            cells: vec![Cell { ch: ' ', fg, bg }; cols * rows],
+            scrollback: VecDeque::new(),
+            col: 0,
+            row: 0,
+            fg,
+            bg,
+            attributes: Attributes::DEFAULT,
+            parser: Parser::new(),
+            saved: None,
+            top: 0,
+            bottom: rows - 1,
+            tab_width: DEFAULT_TAB_WIDTH,
+            view: 0,
+            cursor_enabled: true,
+            cursor_drawn: false,
+        }
+    }
+
+    /// (桁数, 行数)
+    pub fn size(&self) -> (usize, usize) {
+        (self.cols, self.rows)
+    }
+
+    /// カーソル位置 (桁, 行)
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.col, self.row)
+    }
+
+    /// 既定の文字色・背景色 (SGR で色を指定していない文字に使う)
+    pub fn set_colors(&mut self, fg: u32, bg: u32) {
+        self.fg = fg;
+        self.bg = bg;
+    }
+
+    pub fn colors(&self) -> (u32, u32) {
+        (self.fg, self.bg)
+    }
+
+    pub fn set_tab_width(&mut self, width: usize) {
+        self.tab_width = width.max(1);
+    }
+
+    pub fn set_cursor_enabled(&mut self, enabled: bool) {
+        self.hide_cursor();
+        self.cursor_enabled = enabled;
+        self.show_cursor();
+    }
+
+    /// 画面を背景色で消してカーソルを左上へ戻す (スクロールバックは残す)
+    pub fn clear(&mut self) {
+        self.view = 0;
+        let blank = self.blank();
+        self.cells.fill(blank);
+        self.col = 0;
+        self.row = 0;
+        self.cursor_drawn = false;
+        self.fb.clear(blank.bg);
+        self.show_cursor();
+    }
+
+    /// 既定の属性から書き始め、書き終えたら SGR の属性と解析途中のシーケンスを元に戻す
+    ///
+    /// シェルやプログラムが設定した色の途中に割り込むログ行などに使う。
+    pub fn write_isolated(&mut self, args: fmt::Arguments) -> fmt::Result {
+        let attributes = core::mem::replace(&mut self.attributes, Attributes::DEFAULT);
+        let parser = core::mem::take(&mut self.parser);
+        let result = self.write_fmt(args);
+        self.attributes = attributes;
+        self.parser = parser;
+        result
+    }
+
+    /// スクロールバックを `lines` 行遡る (負なら新しい方へ戻る)
+    pub fn scroll_view(&mut self, lines: isize) {
+        let view = self.view.saturating_add_signed(lines).min(self.scrollback.len());
+        if view != self.view {
+            self.view = view;
+            self.redraw();
+        }
+    }
+
+    /// 点滅の 1 段階 (タイマから呼ぶ)
+    fn blink(&mut self) {
+        if self.cursor_drawn {
+            self.hide_cursor();
+        } else {
+            self.show_cursor();
+        }
+    }
+
+    /// 現在の属性で描く (文字色, 背景色)
+    fn current_colors(&self) -> (u32, u32) {
+        let attributes = &self.attributes;
+        let fg = match attributes.fg {
+            Color::Default => self.fg,
+            // 太字は 16 色の暗い方を明るい方にする
+            Color::Indexed(index) if attributes.bold && index < 8 => indexed_color(index + 8),
+            Color::Indexed(index) => indexed_color(index),
+            Color::Rgb(rgb) => rgb,
+        };
+        let bg = match attributes.bg {
+            Color::Default => self.bg,
+            Color::Indexed(index) => indexed_color(index),
+            Color::Rgb(rgb) => rgb,
+        };
+        if attributes.reverse { (bg, fg) } else { (fg, bg) }
+    }
+
+    /// 消去に使うセル (背景は現在の属性の色)
+    fn blank(&self) -> Cell {
+        let (fg, bg) = self.current_colors();
+        Cell { ch: ' ', fg, bg }
+    }
+
+    fn process(&mut self, ch: char) {
+        match self.parser.advance(ch) {
+            Some(Action::Print(c)) => self.put_printable(c),
+            Some(Action::Execute(c)) => self.execute(c),
+            Some(Action::Csi(csi)) => self.csi(&csi),
+            Some(Action::Esc(c)) => self.esc(c),
+            None => {}
+        }
+    }
+
+    fn execute(&mut self, ch: char) {
+        match ch {
+            '\n' => self.newline(),
+            '\r' => self.col = 0,
+            '\t' => {
+                let next = (self.col / self.tab_width + 1) * self.tab_width;
+                while self.col < next.min(self.cols) {
+                    self.put_printable(' ');
+                }
+            }
+            '\x08' => self.col = self.col.saturating_sub(1).min(self.cols - 1),
+            _ => {}
+        }
+    }
+
+    fn put_printable(&mut self, ch: char) {
+        // 右端に達した後の文字で折り返す (右端ちょうどで改行が続いても空行にならない)
+        if self.col >= self.cols {
+            self.newline();
+        }
+        let (fg, bg) = self.current_colors();
+        let cell = Cell { ch, fg, bg };
+        self.cells[self.row * self.cols + self.col] = cell;
+        self.draw_cell(self.col, self.row, cell);
+        self.col += 1;
+    }
+
+    fn newline(&mut self) {
+        self.col = 0;
+        self.index();
+    }
+
+    /// 1 行下へ (スクロール領域の末尾なら領域をスクロール)
+    fn index(&mut self) {
+        if self.row == self.bottom {
+            self.scroll_up(1);
+        } else if self.row + 1 < self.rows {
+            self.row += 1;
+        }
+    }
+
+    /// 1 行上へ (スクロール領域の先頭なら領域を逆にスクロール)
+    fn reverse_index(&mut self) {
+        if self.row == self.top {
+            self.scroll_down(1);
+        } else {
+            self.row = self.row.saturating_sub(1);
+        }
+    }
+
+    /// スクロール領域を `lines` 行上へスクロールする
+    ///
+    /// 領域が画面の先頭から始まるときは、押し出された行をスクロールバックへ移す。
+    fn scroll_up(&mut self, lines: usize) {
+        let (top, bottom, cols) = (self.top, self.bottom, self.cols);
+        let lines = lines.min(bottom - top + 1);
+        if top == 0 {
+            for row in 0..lines {
+                if self.scrollback.len() == SCROLLBACK_LINES {
+                    self.scrollback.pop_front();
+                }
+                self.scrollback.push_back(self.cells[row * cols..(row + 1) * cols].to_vec());
+            }
+        }
+        self.cells.copy_within((top + lines) * cols..(bottom + 1) * cols, top * cols);
+        let blank = self.blank();
+        self.cells[(bottom + 1 - lines) * cols..(bottom + 1) * cols].fill(blank);
+        let height = (bottom - top + 1) * LINE_HEIGHT;
+        self.fb.scroll_up(MARGIN + top * LINE_HEIGHT, height, lines * LINE_HEIGHT, blank.bg);
+    }
+
+    /// スクロール領域を `lines` 行下へスクロールする
+    fn scroll_down(&mut self, lines: usize) {
+        let (top, bottom, cols) = (self.top, self.bottom, self.cols);
+        let lines = lines.min(bottom - top + 1);
+        self.cells.copy_within(top * cols..(bottom + 1 - lines) * cols, (top + lines) * cols);
+        let blank = self.blank();
+        self.cells[top * cols..(top + lines) * cols].fill(blank);
+        let height = (bottom - top + 1) * LINE_HEIGHT;
+        self.fb.scroll_down(MARGIN + top * LINE_HEIGHT, height, lines * LINE_HEIGHT, blank.bg);
+    }
+
+    /// セル `start..end` (行優先の通し番号) を消す
+    fn erase(&mut self, start: usize, end: usize) {
+        let blank = self.blank();
+        for index in start..end.min(self.cells.len()) {
+            self.cells[index] = blank;
+            self.draw_cell(index % self.cols, index / self.cols, blank);
+        }
+    }
+
+    fn move_to(&mut self, col: usize, row: usize) {
+        self.col = col.min(self.cols - 1);
+        self.row = row.min(self.rows - 1);
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved = Some(SavedCursor { col: self.col, row: self.row, attributes: self.attributes });
+    }
+
+    fn restore_cursor(&mut self) {
+        match self.saved {
+            Some(saved) => {
+                self.attributes = saved.attributes;
+                self.move_to(saved.col, saved.row);
+            }
+            None => {
+                self.attributes = Attributes::DEFAULT;
+                self.move_to(0, 0);
+            }
+        }
+    }
+
+    /// 端末を初期状態に戻す (`ESC c`)
+    fn reset(&mut self) {
+        self.attributes = Attributes::DEFAULT;
+        self.saved = None;
+        self.top = 0;
+        self.bottom = self.rows - 1;
+        self.cursor_enabled = true;
+        self.clear();
+    }
+
+    fn esc(&mut self, ch: char) {
+        match ch {
+            '7' => self.save_cursor(),
+            '8' => self.restore_cursor(),
+            'D' => self.index(),
+            'E' => self.newline(),
+            'M' => self.reverse_index(),
+            'c' => self.reset(),
+            _ => {}
+        }
+    }
+
+    fn csi(&mut self, csi: &Csi) {
+        if csi.private == Some('?') {
+            if csi.param(0, 0) == 25 {
+                match csi.final_byte {
+                    'h' => self.cursor_enabled = true,
+                    'l' => self.cursor_enabled = false,
+                    _ => {}
+                }
+            }
+            return;
+        }
+        if csi.private.is_some() {
+            return;
+        }
+        let n = csi.param(0, 1) as usize;
+        let (col, row, cols) = (self.col.min(self.cols - 1), self.row, self.cols);
+        match csi.final_byte {
+            // カーソル移動 (スクロール領域の中にいれば領域の端で止まる)
+            'A' => {
+                let limit = if row >= self.top { self.top } else { 0 };
+                self.move_to(col, row.saturating_sub(n).max(limit));
+            }
+            'B' => {
+                let limit = if row <= self.bottom { self.bottom } else { self.rows - 1 };
+                self.move_to(col, (row + n).min(limit));
+            }
+            'C' => self.move_to(col + n, row),
+            'D' => self.move_to(col.saturating_sub(n), row),
+            'E' => self.move_to(0, row + n),
+            'F' => self.move_to(0, row.saturating_sub(n)),
+            'G' => self.move_to(n - 1, row),
+            'd' => self.move_to(col, n - 1),
+            'H' | 'f' => self.move_to(csi.param(1, 1) as usize - 1, n - 1),
+            // 画面の消去 (0: カーソル以降, 1: カーソルまで, 2: 全体, 3: 全体とスクロールバック)
+            'J' => match csi.param(0, 0) {
+                0 => self.erase(row * cols + col, self.cells.len()),
+                1 => self.erase(0, row * cols + col + 1),
+                2 => self.erase(0, self.cells.len()),
+                3 => {
+                    self.scrollback.clear();
+                    self.erase(0, self.cells.len());
+                }
+                _ => {}
+            },
+            // 行の消去 (0: カーソル以降, 1: カーソルまで, 2: 行全体)
+            'K' => match csi.param(0, 0) {
+                0 => self.erase(row * cols + col, (row + 1) * cols),
+                1 => self.erase(row * cols, row * cols + col + 1),
+                2 => self.erase(row * cols, (row + 1) * cols),
+                _ => {}
+            },
+            'S' => self.scroll_up(n),
+            'T' => self.scroll_down(n),
+            'r' => {
+                let top = csi.param(0, 1) as usize - 1;
+                let bottom = (csi.param(1, self.rows as u16) as usize).min(self.rows) - 1;
+                if top < bottom {
+                    self.top = top;
+                    self.bottom = bottom;
+                    self.move_to(0, 0);
+                }
+            }
+            's' => self.save_cursor(),
+            'u' => self.restore_cursor(),
+            'm' => self.sgr(csi.params()),
+            _ => {}
+        }
+    }
+
+    /// 文字の属性を設定する (パラメータなしは `0` と同じ)
+    fn sgr(&mut self, params: &[u16]) {
+        if params.is_empty() {
+            self.attributes = Attributes::DEFAULT;
+            return;
+        }
+        let mut params = params.iter().copied();
+        while let Some(param) = params.next() {
+            let attributes = &mut self.attributes;
+            match param {
+                0 => *attributes = Attributes::DEFAULT,
+                1 => attributes.bold = true,
+                22 => attributes.bold = false,
+                7 => attributes.reverse = true,
+                27 => attributes.reverse = false,
+                30..=37 => attributes.fg = Color::Indexed((param - 30) as u8),
+                39 => attributes.fg = Color::Default,
+                40..=47 => attributes.bg = Color::Indexed((param - 40) as u8),
+                49 => attributes.bg = Color::Default,
+                90..=97 => attributes.fg = Color::Indexed((param - 90 + 8) as u8),
+                100..=107 => attributes.bg = Color::Indexed((param - 100 + 8) as u8),
+                38 | 48 => {
+                    let color = match params.next() {
+                        Some(5) => params.next().map(|index| Color::Indexed(index.min(255) as u8)),
+                        Some(2) => {
+                            let mut component = || params.next().unwrap_or(0).min(255) as u32;
+                            let (r, g, b) = (component(), component(), component());
+                            Some(Color::Rgb((r << 16) | (g << 8) | b))
+                        }
+                        _ => None,
+                    };
+                    if let Some(color) = color {
+                        if param == 38 {
+                            attributes.fg = color;
+                        } else {
+                            attributes.bg = color;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn cell_origin(col: usize, row: usize) -> (usize, usize) {
+        (MARGIN + col * CHAR_ADVANCE, MARGIN + row * LINE_HEIGHT)
+    }
+
+    fn draw_cell(&mut self, col: usize, row: usize, cell: Cell) {
+        let (x, y) = Self::cell_origin(col, row);
+        self.fb.fill_rect(x, y, CHAR_ADVANCE, LINE_HEIGHT, cell.bg);
+        self.fb.draw_char(x, y, cell.ch, cell.fg);
+    }
+
+    /// 画面全体を (スクロールバックの表示位置を反映して) 描き直す
+    fn redraw(&mut self) {
+        self.cursor_drawn = false;
+        self.fb.clear(self.bg);
+        let from_scrollback = self.view.min(self.rows);
+        for row in 0..self.rows {
+            for col in 0..self.cols {
+                let cell = if row < from_scrollback {
+                    let line = &self.scrollback[self.scrollback.len() - self.view + row];
+                    line.get(col).copied().unwrap_or(Cell { ch: ' ', fg: self.fg, bg: self.bg })
+                } else {
+                    self.cells[(row - from_scrollback) * self.cols + col]
+                };
+                self.draw_cell(col, row, cell);
+            }
+        }
+        self.show_cursor();
+    }
+
+    fn show_cursor(&mut self) {
+        if !self.cursor_enabled || self.view != 0 || self.cursor_drawn {
+            return;
+        }
+        let (x, y) = Self::cell_origin(self.col.min(self.cols - 1), self.row);
+        let fg = self.fg;
+        self.fb.fill_rect(x, y + LINE_HEIGHT - CURSOR_HEIGHT, GLYPH_WIDTH, CURSOR_HEIGHT, fg);
+        self.cursor_drawn = true;
+    }
+
+    fn hide_cursor(&mut self) {
+        if !self.cursor_drawn {
+            return;
+        }
+        let col = self.col.min(self.cols - 1);
+        let cell = self.cells[self.row * self.cols + col];
+        self.draw_cell(col, self.row, cell);
+        self.cursor_drawn = false;
+    }
+}
+
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.view != 0 {
+            self.view = 0;
+            self.redraw();
+        }
+        self.hide_cursor();
+        for ch in s.chars() {
+            self.process(ch);
+        }
+        self.show_cursor();
+        Ok(())
+    }
+}
+
+static CONSOLE: Mutex<Option<Console>> = Mutex::new(None);
+
+/// `fb` をコンソールにする (ヒープ初期化後に呼ぶ)
+pub fn init(fb: FrameBuffer<'static>) {
+    let console = Console::new(fb, DEFAULT_FOREGROUND, DEFAULT_BACKGROUND);
+    without_interrupts(|| *CONSOLE.lock() = Some(console));
+    crate::time::add_periodic(BLINK_INTERVAL, || {
+        // タイマ割り込みから呼ばれるので、書き込み中なら今回は見送る
+        if let Some(mut console) = CONSOLE.try_lock() {
+            if let Some(console) = console.as_mut() {
+                console.blink();
+            }
+        }
+    });
+}
+
+pub fn is_initialized() -> bool {
+    without_interrupts(|| CONSOLE.lock().is_some())
+}
+
+/// コンソールを操作する (未初期化なら `None`)
+pub fn with<R>(f: impl FnOnce(&mut Console) -> R) -> Option<R> {
+    without_interrupts(|| CONSOLE.lock().as_mut().map(f))
+}
+
+/// 書き込む (割り込み・例外ハンドラからも呼べるよう、使用中なら諦める)
+///
+/// `Console::write_isolated` で書くので、他の出力が設定した属性は変えない。
+pub fn try_print_isolated(args: fmt::Arguments) {
+    without_interrupts(|| {
+        let Some(mut guard) = CONSOLE.try_lock() else { return };
+        let Some(console) = guard.as_mut() else { return };
+        let _ = console.write_isolated(args);
+    });
+}
+
+/// Shift+PageUp / PageDown ならスクロールバックを動かして `true` を返す
+pub fn handle_key(event: &KeyEvent) -> bool {
+    if !event.pressed || !event.modifiers.shift {
+        return false;
+    }
+    let direction = match event.code {
+        KeyCode::PageUp => 1,
+        KeyCode::PageDown => -1,
+        _ => return false,
+    };
+    with(|console| {
+        let page = (console.rows / 2).max(1) as isize;
+        console.scroll_view(direction * page);
+    })
+    .is_some()
+}
+
+/// コンソールへ書く `fmt::Write` (`console_print!` と同じ経路)
+pub struct Writer;
+
+impl fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        _print(format_args!("{}", s));
+        Ok(())
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    with(|console| console.write_fmt(args));
+}
+
+/// コンソールへ出力
+#[macro_export]
+macro_rules! console_print {
+    ($($arg:tt)*) => ($crate::console::_print(format_args!($($arg)*)));
+}
+
+/// コンソールへ出力 (改行付き)
+#[macro_export]
+macro_rules! console_println {
+    () => ($crate::console_print!("\n"));
+    ($($arg:tt)*) => ($crate::console_print!("{}\n", format_args!($($arg)*)));
+}
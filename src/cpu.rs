@@ -0,0 +1,98 @@
+#![allow(dead_code)]
+//! CPU 機能の検出と制御レジスタの設定
+//!
+//! - CPUID から必要な機能ビットを読み取る。
+//! - NX / WP / SMEP / SMAP / UMIP によるカーネル保護を有効化する。
+
+use core::arch::x86_64::{__cpuid, __cpuid_count};
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+use x86_64::registers::model_specific::{Efer, EferFlags};
+
+/// CPUID で検出した機能
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CpuFeatures {
+    pub nx: bool,
+    pub smep: bool,
+    pub smap: bool,
+    pub umip: bool,
+    pub rdrand: bool,
+    pub apic: bool,
+    pub x2apic: bool,
+    pub tsc_deadline: bool,
+}
+
+impl CpuFeatures {
+    pub fn detect() -> Self {
+        // x86_64 では CPUID は常に使用可能
+        let max_leaf = __cpuid(0).eax;
+        let max_ext_leaf = __cpuid(0x8000_0000).eax;
+        let mut f = Self::default();
+        if max_leaf >= 1 {
+            let leaf1 = __cpuid(1);
+            f.rdrand = leaf1.ecx & (1 << 30) != 0;
+            f.x2apic = leaf1.ecx & (1 << 21) != 0;
+            f.tsc_deadline = leaf1.ecx & (1 << 24) != 0;
+            f.apic = leaf1.edx & (1 << 9) != 0;
+        }
+        if max_leaf >= 7 {
+            let leaf7 = __cpuid_count(7, 0);
+            f.smep = leaf7.ebx & (1 << 7) != 0;
+            f.smap = leaf7.ebx & (1 << 20) != 0;
+            f.umip = leaf7.ecx & (1 << 2) != 0;
+        }
+        if max_ext_leaf >= 0x8000_0001 {
+            let ext = __cpuid(0x8000_0001);
+            f.nx = ext.edx & (1 << 20) != 0;
+        }
+        f
+    }
+}
+
+static NX_ENABLED: AtomicBool = AtomicBool::new(false);
+static SMAP_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// EFER.NXE を有効化する (対応 CPU のみ)。有効になったかを返す
+///
+/// ページテーブルに NX ビットを立てる前に呼ぶこと (未対応時は予約ビット違反になる)。
+pub fn enable_nx() -> bool {
+    if !CpuFeatures::detect().nx {
+        return false;
+    }
+    // Safety: NXE は既存のマッピングの意味を変えない
+    unsafe { Efer::update(|f| f.insert(EferFlags::NO_EXECUTE_ENABLE)) };
+    NX_ENABLED.store(true, Ordering::Relaxed);
+    true
+}
+
+pub fn nx_enabled() -> bool {
+    NX_ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn smap_enabled() -> bool {
+    SMAP_ENABLED.load(Ordering::Relaxed)
+}
+
+/// CR0.WP と、対応していれば SMEP / SMAP / UMIP を有効化する
+///
+/// カーネルのページ保護を設定した後に呼ぶこと。
+pub fn enable_hardening() -> CpuFeatures {
+    let features = CpuFeatures::detect();
+    // Safety: カーネルは読み取り専用ページへ書き込まず、ユーザページを直接参照しない
+    unsafe {
+        Cr0::update(|f| f.insert(Cr0Flags::WRITE_PROTECT));
+        Cr4::update(|f| {
+            if features.smep {
+                f.insert(Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION);
+            }
+            if features.smap {
+                f.insert(Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION);
+            }
+            if features.umip {
+                f.insert(Cr4Flags::USER_MODE_INSTRUCTION_PREVENTION);
+            }
+        });
+    }
+    SMAP_ENABLED.store(features.smap, Ordering::Relaxed);
+    features
+}
@@ -0,0 +1,158 @@
+//! キー配列
+//!
+//! - 物理キー (`KeyCode`、US 配列の刻印で命名) と Shift の状態から文字を決める。
+//! - JIS 配列 (106/109) では記号キーの刻印が異なり、`Grave` の位置は 半角/全角 キーになる。
+//!   かな入力は IME の役割なので、ここでは英数の文字だけを返す。
+
+use super::KeyCode;
+
+/// キー配列
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    /// US 101/104
+    Us,
+    /// 日本語 JIS 106/109
+    Jis,
+}
+
+impl Layout {
+    /// コマンドラインの `keymap=` の値から選ぶ
+    pub fn from_name(name: &str) -> Option<Layout> {
+        match name {
+            "us" => Some(Layout::Us),
+            "jis" | "jp" | "jp106" => Some(Layout::Jis),
+            _ => None,
+        }
+    }
+
+    /// `code` を押したときの文字 (Caps Lock は呼び出し側で英字の Shift に反映する)
+    pub fn translate(self, code: KeyCode, shift: bool) -> Option<char> {
+        if let Some(c) = letter(code) {
+            return Some(if shift { c.to_ascii_uppercase() } else { c });
+        }
+        if let Some(c) = common(code) {
+            return Some(c);
+        }
+        let (normal, shifted) = match self {
+            Layout::Us => us(code)?,
+            Layout::Jis => jis(code)?,
+        };
+        if shift { shifted } else { Some(normal) }
+    }
+}
+
+fn letter(code: KeyCode) -> Option<char> {
+    use KeyCode::*;
+    Some(match code {
+        A => 'a',
+        B => 'b',
+        C => 'c',
+        D => 'd',
+        E => 'e',
+        F => 'f',
+        G => 'g',
+        H => 'h',
+        I => 'i',
+        J => 'j',
+        K => 'k',
+        L => 'l',
+        M => 'm',
+        N => 'n',
+        O => 'o',
+        P => 'p',
+        Q => 'q',
+        R => 'r',
+        S => 's',
+        T => 't',
+        U => 'u',
+        V => 'v',
+        W => 'w',
+        X => 'x',
+        Y => 'y',
+        Z => 'z',
+        _ => return None,
+    })
+}
+
+/// 配列と Shift によらないキー
+fn common(code: KeyCode) -> Option<char> {
+    use KeyCode::*;
+    Some(match code {
+        Enter | KeypadEnter => '\n',
+        Tab => '\t',
+        Backspace => '\x08',
+        Escape => '\x1B',
+        Space => ' ',
+        KeypadSlash => '/',
+        KeypadStar => '*',
+        KeypadMinus => '-',
+        KeypadPlus => '+',
+        _ => return None,
+    })
+}
+
+/// US 配列の (通常, Shift)
+fn us(code: KeyCode) -> Option<(char, Option<char>)> {
+    use KeyCode::*;
+    let (normal, shifted) = match code {
+        Key1 => ('1', '!'),
+        Key2 => ('2', '@'),
+        Key3 => ('3', '#'),
+        Key4 => ('4', '$'),
+        Key5 => ('5', '%'),
+        Key6 => ('6', '^'),
+        Key7 => ('7', '&'),
+        Key8 => ('8', '*'),
+        Key9 => ('9', '('),
+        Key0 => ('0', ')'),
+        Minus => ('-', '_'),
+        Equal => ('=', '+'),
+        LeftBracket => ('[', '{'),
+        RightBracket => (']', '}'),
+        Backslash => ('\\', '|'),
+        Semicolon => (';', ':'),
+        Quote => ('\'', '"'),
+        Grave => ('`', '~'),
+        Comma => (',', '<'),
+        Period => ('.', '>'),
+        Slash => ('/', '?'),
+        _ => return None,
+    };
+    Some((normal, Some(shifted)))
+}
+
+/// JIS 配列の (通常, Shift)
+fn jis(code: KeyCode) -> Option<(char, Option<char>)> {
+    use KeyCode::*;
+    Some(match code {
+        Key1 => ('1', Some('!')),
+        Key2 => ('2', Some('"')),
+        Key3 => ('3', Some('#')),
+        Key4 => ('4', Some('$')),
+        Key5 => ('5', Some('%')),
+        Key6 => ('6', Some('&')),
+        Key7 => ('7', Some('\'')),
+        Key8 => ('8', Some('(')),
+        Key9 => ('9', Some(')')),
+        // Shift+0 には文字が割り当てられていない
+        Key0 => ('0', None),
+        Minus => ('-', Some('=')),
+        // ^ キー
+        Equal => ('^', Some('~')),
+        Yen => ('\\', Some('|')),
+        // @ キー
+        LeftBracket => ('@', Some('`')),
+        // [ キー
+        RightBracket => ('[', Some('{')),
+        // ] キー (US の \ の位置)
+        Backslash => (']', Some('}')),
+        Semicolon => (';', Some('+')),
+        // : キー
+        Quote => (':', Some('*')),
+        Comma => (',', Some('<')),
+        Period => ('.', Some('>')),
+        Slash => ('/', Some('?')),
+        Ro => ('\\', Some('_')),
+        _ => return None,
+    })
+}
@@ -0,0 +1,372 @@
+#![allow(dead_code)]
+//! PS/2 キーボード
+//!
+//! - i8042 の第 1 ポートのキーボードをリセットし、スキャンコード set 2 を選ぶ。
+//!   選べなければ (またはコマンドラインで `scancode=1` なら) コントローラの変換を有効にして set 1 で受け取る。
+//! - IRQ1 でスキャンコードをデコードし、修飾キーとロックキー (LED) の状態を反映した
+//!   `KeyEvent` を入力サブシステム (`input`) へ送る。
+//! - 文字への変換はキー配列 (`keymap=us` / `keymap=jis`、既定は US) に従う。
+//!   NumLock が無効のときのテンキーは文字を返さず、キーコードだけを通知する。
+//! - LED の更新はコマンドの ACK を割り込みハンドラで受けながら進める。
+
+pub mod layout;
+pub mod scancode;
+
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use crate::drivers::ps2::{self, PortId, ACK, RESEND};
+use crate::input::{self, InputEvent};
+use crate::interrupts::InterruptContext;
+use crate::irq::{self, Irq};
+use layout::Layout;
+use scancode::{Decoder, ScancodeSet};
+
+pub type Result<T> = core::result::Result<T, &'static str>;
+
+const CMD_SET_LEDS: u8 = 0xED;
+const CMD_SCANCODE_SET: u8 = 0xF0;
+const CMD_ENABLE_SCANNING: u8 = 0xF4;
+const CMD_RESET: u8 = 0xFF;
+const SELF_TEST_PASSED: u8 = 0xAA;
+
+const LED_SCROLL_LOCK: u8 = 1 << 0;
+const LED_NUM_LOCK: u8 = 1 << 1;
+const LED_CAPS_LOCK: u8 = 1 << 2;
+
+/// 物理キー (US 配列の刻印で命名)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum KeyCode {
+    Escape,
+    F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+    PrintScreen, ScrollLock, Pause,
+    /// JIS 配列では 半角/全角
+    Grave,
+    Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0,
+    Minus, Equal, Backspace, Tab,
+    Q, W, E, R, T, Y, U, I, O, P,
+    LeftBracket, RightBracket, Backslash,
+    CapsLock,
+    A, S, D, F, G, H, J, K, L,
+    Semicolon, Quote, Enter,
+    LeftShift,
+    Z, X, C, V, B, N, M,
+    Comma, Period, Slash,
+    RightShift,
+    LeftCtrl, LeftGui, LeftAlt, Space, RightAlt, RightGui, Menu, RightCtrl,
+    Insert, Delete, Home, End, PageUp, PageDown,
+    Up, Down, Left, Right,
+    NumLock, KeypadSlash, KeypadStar, KeypadMinus, KeypadPlus, KeypadEnter, KeypadPeriod,
+    Keypad0, Keypad1, Keypad2, Keypad3, Keypad4, Keypad5, Keypad6, Keypad7, Keypad8, Keypad9,
+    /// JIS: ¥ キー
+    Yen,
+    /// JIS: ろ キー
+    Ro,
+    /// JIS: 変換
+    Henkan,
+    /// JIS: 無変換
+    Muhenkan,
+    /// JIS: カタカナ/ひらがな
+    KatakanaHiragana,
+}
+
+/// 押されている修飾キー
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub gui: bool,
+}
+
+/// ロックキーの状態
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Locks {
+    pub caps: bool,
+    pub num: bool,
+    pub scroll: bool,
+}
+
+impl Locks {
+    fn leds(self) -> u8 {
+        (if self.scroll { LED_SCROLL_LOCK } else { 0 })
+            | (if self.num { LED_NUM_LOCK } else { 0 })
+            | (if self.caps { LED_CAPS_LOCK } else { 0 })
+    }
+}
+
+/// キーの押下・解放
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    pub pressed: bool,
+    /// 押しっぱなしによる自動リピート
+    pub repeat: bool,
+    /// イベント発生時の修飾キー (このキー自身の変化を反映済み)
+    pub modifiers: Modifiers,
+    pub locks: Locks,
+    /// 入力された文字 (押下時のみ。Ctrl+英字は制御文字)
+    pub ch: Option<char>,
+}
+
+/// LED 更新の進行状況
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum LedUpdate {
+    Idle,
+    /// 0xED の ACK 待ち
+    Command(u8),
+    /// LED の値の ACK 待ち
+    Value(u8),
+}
+
+struct Keyboard {
+    decoder: Decoder,
+    layout: Layout,
+    /// 押されているキー (`KeyCode as u8` のビット)
+    held: [u64; 2],
+    left_shift: bool,
+    right_shift: bool,
+    left_ctrl: bool,
+    right_ctrl: bool,
+    left_alt: bool,
+    right_alt: bool,
+    left_gui: bool,
+    right_gui: bool,
+    locks: Locks,
+    led: LedUpdate,
+    /// LED の更新中に状態が変わったので、終わったらもう一度送る
+    led_pending: bool,
+}
+
+static KEYBOARD: Mutex<Option<Keyboard>> = Mutex::new(None);
+
+/// キーボードを初期化して IRQ1 を登録する (割り込みコントローラと PS/2 コントローラの初期化後に呼ぶ)
+pub fn init() -> Result<Irq> {
+    let ports = match ps2::ports() {
+        Some(ports) => ports,
+        None => ps2::init()?,
+    };
+    if !ports.first {
+        return Err("no PS/2 keyboard port");
+    }
+
+    ps2::device_command(PortId::First, CMD_RESET)?;
+    // リセット後の自己診断には時間がかかることがある
+    let passed = (0..5).any(|_| ps2::read_from(PortId::First) == Ok(SELF_TEST_PASSED));
+    if !passed {
+        return Err("keyboard self test failed");
+    }
+
+    let set = select_scancode_set();
+    ps2::device_command(PortId::First, CMD_SET_LEDS)?;
+    ps2::device_command(PortId::First, 0)?;
+    ps2::device_command(PortId::First, CMD_ENABLE_SCANNING)?;
+
+    let layout = crate::cmdline::get("keymap").and_then(Layout::from_name).unwrap_or(Layout::Us);
+    *KEYBOARD.lock() = Some(Keyboard::new(set, layout));
+
+    let irq = irq::register_isa(1, handle_irq)?;
+    ps2::set_irq(PortId::First, true)?;
+    Ok(irq)
+}
+
+/// スキャンコード set 2 を選ぶ。だめならコントローラの変換で set 1 にする
+fn select_scancode_set() -> ScancodeSet {
+    let set1 = crate::cmdline::get("scancode") == Some("1");
+    let set2 = !set1
+        && ps2::device_command(PortId::First, CMD_SCANCODE_SET).is_ok()
+        && ps2::device_command(PortId::First, 2).is_ok();
+    if set2 {
+        return ScancodeSet::Set2;
+    }
+    match ps2::set_translation(true) {
+        Ok(()) => ScancodeSet::Set1,
+        // 変換できなければキーボードの既定 (set 2) のまま
+        Err(_) => ScancodeSet::Set2,
+    }
+}
+
+/// IRQ1 のハンドラ
+fn handle_irq(_ctx: &InterruptContext) {
+    let Some(byte) = ps2::poll_port(PortId::First) else { return };
+    let Some(mut keyboard) = KEYBOARD.try_lock() else { return };
+    let Some(keyboard) = keyboard.as_mut() else { return };
+    if let Some(event) = keyboard.process(byte) {
+        input::report(InputEvent::Key(event));
+    }
+}
+
+impl Keyboard {
+    fn new(set: ScancodeSet, layout: Layout) -> Self {
+        Self {
+            decoder: Decoder::new(set),
+            layout,
+            held: [0; 2],
+            left_shift: false,
+            right_shift: false,
+            left_ctrl: false,
+            right_ctrl: false,
+            left_alt: false,
+            right_alt: false,
+            left_gui: false,
+            right_gui: false,
+            locks: Locks::default(),
+            led: LedUpdate::Idle,
+            led_pending: false,
+        }
+    }
+
+    /// 受け取った 1 byte を処理し、キーが確定すればイベントを返す
+    fn process(&mut self, byte: u8) -> Option<KeyEvent> {
+        if self.led != LedUpdate::Idle && (byte == ACK || byte == RESEND) {
+            self.advance_led(byte);
+            return None;
+        }
+        let scan = self.decoder.feed(byte)?;
+        let repeat = scan.pressed && self.is_held(scan.code);
+        self.set_held(scan.code, scan.pressed);
+        self.update_modifiers(scan.code, scan.pressed);
+        if scan.pressed && !repeat {
+            self.toggle_lock(scan.code);
+        }
+        Some(KeyEvent {
+            code: scan.code,
+            pressed: scan.pressed,
+            repeat,
+            modifiers: self.modifiers(),
+            locks: self.locks,
+            ch: if scan.pressed { self.character(scan.code) } else { None },
+        })
+    }
+
+    fn is_held(&self, code: KeyCode) -> bool {
+        let bit = code as usize;
+        self.held[bit / 64] & (1 << (bit % 64)) != 0
+    }
+
+    fn set_held(&mut self, code: KeyCode, pressed: bool) {
+        let bit = code as usize;
+        if pressed {
+            self.held[bit / 64] |= 1 << (bit % 64);
+        } else {
+            self.held[bit / 64] &= !(1 << (bit % 64));
+        }
+    }
+
+    fn update_modifiers(&mut self, code: KeyCode, pressed: bool) {
+        match code {
+            KeyCode::LeftShift => self.left_shift = pressed,
+            KeyCode::RightShift => self.right_shift = pressed,
+            KeyCode::LeftCtrl => self.left_ctrl = pressed,
+            KeyCode::RightCtrl => self.right_ctrl = pressed,
+            KeyCode::LeftAlt => self.left_alt = pressed,
+            KeyCode::RightAlt => self.right_alt = pressed,
+            KeyCode::LeftGui => self.left_gui = pressed,
+            KeyCode::RightGui => self.right_gui = pressed,
+            _ => {}
+        }
+    }
+
+    fn modifiers(&self) -> Modifiers {
+        Modifiers {
+            shift: self.left_shift || self.right_shift,
+            ctrl: self.left_ctrl || self.right_ctrl,
+            alt: self.left_alt || self.right_alt,
+            gui: self.left_gui || self.right_gui,
+        }
+    }
+
+    fn toggle_lock(&mut self, code: KeyCode) {
+        match code {
+            KeyCode::CapsLock => self.locks.caps = !self.locks.caps,
+            KeyCode::NumLock => self.locks.num = !self.locks.num,
+            KeyCode::ScrollLock => self.locks.scroll = !self.locks.scroll,
+            _ => return,
+        }
+        self.request_led_update();
+    }
+
+    fn character(&self, code: KeyCode) -> Option<char> {
+        let modifiers = self.modifiers();
+        if let Some(c) = keypad_digit(code) {
+            return self.locks.num.then_some(c);
+        }
+        let is_letter = matches!(self.layout.translate(code, false), Some('a'..='z'));
+        // Caps Lock は英字にだけ効く
+        let shift = if is_letter { modifiers.shift != self.locks.caps } else { modifiers.shift };
+        let c = self.layout.translate(code, shift)?;
+        if modifiers.ctrl && is_letter {
+            return Some((c.to_ascii_lowercase() as u8 - b'a' + 1) as char);
+        }
+        Some(c)
+    }
+
+    fn request_led_update(&mut self) {
+        if self.led != LedUpdate::Idle {
+            self.led_pending = true;
+            return;
+        }
+        let leds = self.locks.leds();
+        if ps2::write_port(PortId::First, CMD_SET_LEDS).is_ok() {
+            self.led = LedUpdate::Command(leds);
+        }
+    }
+
+    fn advance_led(&mut self, byte: u8) {
+        self.led = match (self.led, byte) {
+            (LedUpdate::Command(leds), ACK) => match ps2::write_port(PortId::First, leds) {
+                Ok(()) => LedUpdate::Value(leds),
+                Err(_) => LedUpdate::Idle,
+            },
+            (LedUpdate::Command(leds), _) => match ps2::write_port(PortId::First, CMD_SET_LEDS) {
+                Ok(()) => LedUpdate::Command(leds),
+                Err(_) => LedUpdate::Idle,
+            },
+            (LedUpdate::Value(leds), RESEND) => match ps2::write_port(PortId::First, leds) {
+                Ok(()) => LedUpdate::Value(leds),
+                Err(_) => LedUpdate::Idle,
+            },
+            _ => LedUpdate::Idle,
+        };
+        if self.led == LedUpdate::Idle && core::mem::take(&mut self.led_pending) {
+            self.request_led_update();
+        }
+    }
+}
+
+fn keypad_digit(code: KeyCode) -> Option<char> {
+    use KeyCode::*;
+    Some(match code {
+        Keypad0 => '0',
+        Keypad1 => '1',
+        Keypad2 => '2',
+        Keypad3 => '3',
+        Keypad4 => '4',
+        Keypad5 => '5',
+        Keypad6 => '6',
+        Keypad7 => '7',
+        Keypad8 => '8',
+        Keypad9 => '9',
+        KeypadPeriod => '.',
+        _ => return None,
+    })
+}
+
+/// キー配列を切り替える
+pub fn set_layout(layout: Layout) {
+    without_interrupts(|| {
+        if let Some(keyboard) = KEYBOARD.lock().as_mut() {
+            keyboard.layout = layout;
+        }
+    });
+}
+
+/// 現在のキー配列 (未初期化なら `None`)
+pub fn layout() -> Option<Layout> {
+    without_interrupts(|| KEYBOARD.lock().as_ref().map(|keyboard| keyboard.layout))
+}
+
+/// スキャンコードセット (未初期化なら `None`)
+pub fn scancode_set() -> Option<ScancodeSet> {
+    without_interrupts(|| KEYBOARD.lock().as_ref().map(|keyboard| keyboard.decoder.set()))
+}
@@ -0,0 +1,328 @@
+//! スキャンコードのデコード
+//!
+//! - set 1 (XT) と set 2 (AT) の make / break コードを物理キー (`KeyCode`) に変換する。
+//! - E0 で始まる拡張キー、E1 で始まる Pause、PrintScreen に付く疑似 Shift を扱う。
+
+use super::KeyCode;
+
+/// デコード結果
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Scan {
+    pub code: KeyCode,
+    pub pressed: bool,
+}
+
+/// キーボードが使うスキャンコードセット
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScancodeSet {
+    Set1,
+    Set2,
+}
+
+/// Pause キーの長さ (E1 の後に続くバイト数)
+const PAUSE_SET1_LEN: u8 = 5;
+const PAUSE_SET2_LEN: u8 = 7;
+
+/// 複数バイトのシーケンスを組み立てるデコーダ
+pub struct Decoder {
+    set: ScancodeSet,
+    extended: bool,
+    release: bool,
+    /// Pause のシーケンスの残りバイト数
+    pause: u8,
+}
+
+impl Decoder {
+    pub const fn new(set: ScancodeSet) -> Self {
+        Self { set, extended: false, release: false, pause: 0 }
+    }
+
+    pub fn set(&self) -> ScancodeSet {
+        self.set
+    }
+
+    /// 1 byte 受け取り、キーが確定すれば返す
+    pub fn feed(&mut self, byte: u8) -> Option<Scan> {
+        if self.pause > 0 {
+            self.pause -= 1;
+            // Pause は離したときのコードを含めて一度に送られ、押下だけを通知する
+            return (self.pause == 0).then_some(Scan { code: KeyCode::Pause, pressed: true });
+        }
+        match (self.set, byte) {
+            (_, 0xE0) => {
+                self.extended = true;
+                None
+            }
+            (ScancodeSet::Set1, 0xE1) => {
+                self.pause = PAUSE_SET1_LEN;
+                None
+            }
+            (ScancodeSet::Set2, 0xE1) => {
+                self.pause = PAUSE_SET2_LEN;
+                None
+            }
+            (ScancodeSet::Set2, 0xF0) => {
+                self.release = true;
+                None
+            }
+            (ScancodeSet::Set1, _) => {
+                let extended = core::mem::take(&mut self.extended);
+                let pressed = byte & 0x80 == 0;
+                let code = if extended { set1_extended(byte & 0x7F) } else { set1(byte & 0x7F) };
+                code.map(|code| Scan { code, pressed })
+            }
+            (ScancodeSet::Set2, _) => {
+                let extended = core::mem::take(&mut self.extended);
+                let pressed = !core::mem::take(&mut self.release);
+                let code = if extended { set2_extended(byte) } else { set2(byte) };
+                code.map(|code| Scan { code, pressed })
+            }
+        }
+    }
+}
+
+fn set1(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+    Some(match code {
+        0x01 => Escape,
+        0x02 => Key1,
+        0x03 => Key2,
+        0x04 => Key3,
+        0x05 => Key4,
+        0x06 => Key5,
+        0x07 => Key6,
+        0x08 => Key7,
+        0x09 => Key8,
+        0x0A => Key9,
+        0x0B => Key0,
+        0x0C => Minus,
+        0x0D => Equal,
+        0x0E => Backspace,
+        0x0F => Tab,
+        0x10 => Q,
+        0x11 => W,
+        0x12 => E,
+        0x13 => R,
+        0x14 => T,
+        0x15 => Y,
+        0x16 => U,
+        0x17 => I,
+        0x18 => O,
+        0x19 => P,
+        0x1A => LeftBracket,
+        0x1B => RightBracket,
+        0x1C => Enter,
+        0x1D => LeftCtrl,
+        0x1E => A,
+        0x1F => S,
+        0x20 => D,
+        0x21 => F,
+        0x22 => G,
+        0x23 => H,
+        0x24 => J,
+        0x25 => K,
+        0x26 => L,
+        0x27 => Semicolon,
+        0x28 => Quote,
+        0x29 => Grave,
+        0x2A => LeftShift,
+        0x2B => Backslash,
+        0x2C => Z,
+        0x2D => X,
+        0x2E => C,
+        0x2F => V,
+        0x30 => B,
+        0x31 => N,
+        0x32 => M,
+        0x33 => Comma,
+        0x34 => Period,
+        0x35 => Slash,
+        0x36 => RightShift,
+        0x37 => KeypadStar,
+        0x38 => LeftAlt,
+        0x39 => Space,
+        0x3A => CapsLock,
+        0x3B => F1,
+        0x3C => F2,
+        0x3D => F3,
+        0x3E => F4,
+        0x3F => F5,
+        0x40 => F6,
+        0x41 => F7,
+        0x42 => F8,
+        0x43 => F9,
+        0x44 => F10,
+        0x45 => NumLock,
+        0x46 => ScrollLock,
+        0x47 => Keypad7,
+        0x48 => Keypad8,
+        0x49 => Keypad9,
+        0x4A => KeypadMinus,
+        0x4B => Keypad4,
+        0x4C => Keypad5,
+        0x4D => Keypad6,
+        0x4E => KeypadPlus,
+        0x4F => Keypad1,
+        0x50 => Keypad2,
+        0x51 => Keypad3,
+        0x52 => Keypad0,
+        0x53 => KeypadPeriod,
+        0x57 => F11,
+        0x58 => F12,
+        0x70 => KatakanaHiragana,
+        0x73 => Ro,
+        0x79 => Henkan,
+        0x7B => Muhenkan,
+        0x7D => Yen,
+        _ => return None,
+    })
+}
+
+fn set1_extended(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+    Some(match code {
+        0x1C => KeypadEnter,
+        0x1D => RightCtrl,
+        0x35 => KeypadSlash,
+        0x37 => PrintScreen,
+        0x38 => RightAlt,
+        0x47 => Home,
+        0x48 => Up,
+        0x49 => PageUp,
+        0x4B => Left,
+        0x4D => Right,
+        0x4F => End,
+        0x50 => Down,
+        0x51 => PageDown,
+        0x52 => Insert,
+        0x53 => Delete,
+        0x5B => LeftGui,
+        0x5C => RightGui,
+        0x5D => Menu,
+        // 0x2A / 0x36 は PrintScreen などに付く疑似 Shift なので無視する
+        _ => return None,
+    })
+}
+
+fn set2(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+    Some(match code {
+        0x01 => F9,
+        0x03 => F5,
+        0x04 => F3,
+        0x05 => F1,
+        0x06 => F2,
+        0x07 => F12,
+        0x09 => F10,
+        0x0A => F8,
+        0x0B => F6,
+        0x0C => F4,
+        0x0D => Tab,
+        0x0E => Grave,
+        0x11 => LeftAlt,
+        0x12 => LeftShift,
+        0x13 => KatakanaHiragana,
+        0x14 => LeftCtrl,
+        0x15 => Q,
+        0x16 => Key1,
+        0x1A => Z,
+        0x1B => S,
+        0x1C => A,
+        0x1D => W,
+        0x1E => Key2,
+        0x21 => C,
+        0x22 => X,
+        0x23 => D,
+        0x24 => E,
+        0x25 => Key4,
+        0x26 => Key3,
+        0x29 => Space,
+        0x2A => V,
+        0x2B => F,
+        0x2C => T,
+        0x2D => R,
+        0x2E => Key5,
+        0x31 => N,
+        0x32 => B,
+        0x33 => H,
+        0x34 => G,
+        0x35 => Y,
+        0x36 => Key6,
+        0x3A => M,
+        0x3B => J,
+        0x3C => U,
+        0x3D => Key7,
+        0x3E => Key8,
+        0x41 => Comma,
+        0x42 => K,
+        0x43 => I,
+        0x44 => O,
+        0x45 => Key0,
+        0x46 => Key9,
+        0x49 => Period,
+        0x4A => Slash,
+        0x4B => L,
+        0x4C => Semicolon,
+        0x4D => P,
+        0x4E => Minus,
+        0x51 => Ro,
+        0x52 => Quote,
+        0x54 => LeftBracket,
+        0x55 => Equal,
+        0x58 => CapsLock,
+        0x59 => RightShift,
+        0x5A => Enter,
+        0x5B => RightBracket,
+        0x5D => Backslash,
+        0x64 => Henkan,
+        0x66 => Backspace,
+        0x67 => Muhenkan,
+        0x69 => Keypad1,
+        0x6A => Yen,
+        0x6B => Keypad4,
+        0x6C => Keypad7,
+        0x70 => Keypad0,
+        0x71 => KeypadPeriod,
+        0x72 => Keypad2,
+        0x73 => Keypad5,
+        0x74 => Keypad6,
+        0x75 => Keypad8,
+        0x76 => Escape,
+        0x77 => NumLock,
+        0x78 => F11,
+        0x79 => KeypadPlus,
+        0x7A => Keypad3,
+        0x7B => KeypadMinus,
+        0x7C => KeypadStar,
+        0x7D => Keypad9,
+        0x7E => ScrollLock,
+        0x83 => F7,
+        _ => return None,
+    })
+}
+
+fn set2_extended(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+    Some(match code {
+        0x11 => RightAlt,
+        0x14 => RightCtrl,
+        0x1F => LeftGui,
+        0x27 => RightGui,
+        0x2F => Menu,
+        0x4A => KeypadSlash,
+        0x5A => KeypadEnter,
+        0x69 => End,
+        0x6B => Left,
+        0x6C => Home,
+        0x70 => Insert,
+        0x71 => Delete,
+        0x72 => Down,
+        0x74 => Right,
+        0x75 => Up,
+        0x7A => PageDown,
+        0x7C => PrintScreen,
+        0x7D => PageUp,
+        // 0x12 / 0x59 は疑似 Shift なので無視する
+        _ => return None,
+    })
+}
@@ -0,0 +1,5 @@
+//! デバイスドライバ
+pub mod keyboard;
+pub mod mouse;
+pub mod ps2;
+pub mod serial;
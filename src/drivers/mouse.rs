@@ -0,0 +1,283 @@
+#![allow(dead_code)]
+//! PS/2 マウス
+//!
+//! - i8042 の第 2 ポートのマウスをリセットし、サンプルレートと解像度を設定する。
+//! - サンプルレートを決まった順 (200, 100, 80 / 200, 200, 80) に設定して ID を読み、
+//!   IntelliMouse (ホイール、ID 3) と 5 ボタン IntelliMouse (ID 4) を検出する。
+//! - IRQ12 で 3 byte (拡張時は 4 byte) のパケットを組み立てる。先頭バイトの bit 3 が立っていない場合や
+//!   バイトの間隔が空いた場合は途中のバイトを捨てて同期を取り直す。
+//! - 移動量は画面座標 (右・下が正) に直し、移動・ボタン・ホイールの入力イベントとして `input` へ送る。
+
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use crate::drivers::ps2::{self, PortId};
+use crate::input::{self, InputEvent, MouseButton};
+use crate::interrupts::InterruptContext;
+use crate::irq::{self, Irq};
+use crate::time::Instant;
+
+pub type Result<T> = core::result::Result<T, &'static str>;
+
+const CMD_SET_RESOLUTION: u8 = 0xE8;
+const CMD_GET_ID: u8 = 0xF2;
+const CMD_SET_SAMPLE_RATE: u8 = 0xF3;
+const CMD_ENABLE_REPORTING: u8 = 0xF4;
+const CMD_SET_DEFAULTS: u8 = 0xF6;
+const CMD_RESET: u8 = 0xFF;
+const SELF_TEST_PASSED: u8 = 0xAA;
+
+const ID_STANDARD: u8 = 0x00;
+const ID_WHEEL: u8 = 0x03;
+const ID_FIVE_BUTTONS: u8 = 0x04;
+
+/// 1 秒あたりのサンプル数
+const SAMPLE_RATE: u8 = 100;
+/// 8 カウント/mm
+const RESOLUTION: u8 = 3;
+
+/// 先頭バイト: 左・右・中ボタン
+const PACKET_LEFT: u8 = 1 << 0;
+const PACKET_RIGHT: u8 = 1 << 1;
+const PACKET_MIDDLE: u8 = 1 << 2;
+/// 先頭バイト: 常に 1 (同期用)
+const PACKET_ALWAYS_ONE: u8 = 1 << 3;
+const PACKET_X_SIGN: u8 = 1 << 4;
+const PACKET_Y_SIGN: u8 = 1 << 5;
+const PACKET_X_OVERFLOW: u8 = 1 << 6;
+const PACKET_Y_OVERFLOW: u8 = 1 << 7;
+/// 5 ボタン時の 4 byte 目: 第 4・第 5 ボタン
+const PACKET_BUTTON4: u8 = 1 << 4;
+const PACKET_BUTTON5: u8 = 1 << 5;
+
+/// これ以上間が空いたら、組み立て途中のパケットを捨てる
+const PACKET_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// マウスの種類
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MouseKind {
+    /// 3 ボタン (ホイールなし)
+    Standard,
+    /// IntelliMouse (ホイール付き)
+    Wheel,
+    /// 5 ボタン IntelliMouse
+    FiveButtons,
+}
+
+impl MouseKind {
+    fn packet_len(self) -> usize {
+        match self {
+            MouseKind::Standard => 3,
+            MouseKind::Wheel | MouseKind::FiveButtons => 4,
+        }
+    }
+}
+
+/// 押されているボタン
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MouseButtons {
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+    /// 第 4 ボタン (戻る)
+    pub back: bool,
+    /// 第 5 ボタン (進む)
+    pub forward: bool,
+}
+
+/// 1 パケット分の入力
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MouseEvent {
+    /// 右が正
+    pub dx: i32,
+    /// 下が正
+    pub dy: i32,
+    /// ホイールの回転 (手前が正)
+    pub wheel: i8,
+    pub buttons: MouseButtons,
+    /// 直前のパケットから変化したボタン
+    pub changed: MouseButtons,
+}
+
+struct Mouse {
+    kind: MouseKind,
+    packet: [u8; 4],
+    len: usize,
+    last_byte: Instant,
+    buttons: MouseButtons,
+}
+
+static MOUSE: Mutex<Option<Mouse>> = Mutex::new(None);
+
+/// マウスを初期化して IRQ12 を登録する (割り込みコントローラの初期化後に呼ぶ)
+pub fn init() -> Result<(MouseKind, Irq)> {
+    let ports = match ps2::ports() {
+        Some(ports) => ports,
+        None => ps2::init()?,
+    };
+    if !ports.second {
+        return Err("no PS/2 auxiliary port");
+    }
+
+    ps2::device_command(PortId::Second, CMD_RESET)?;
+    // リセット後は自己診断の結果と ID が続く
+    let passed = (0..5).any(|_| ps2::read_from(PortId::Second) == Ok(SELF_TEST_PASSED));
+    if !passed {
+        return Err("mouse self test failed");
+    }
+    let _ = ps2::read_from(PortId::Second);
+    ps2::device_command(PortId::Second, CMD_SET_DEFAULTS)?;
+
+    let kind = detect()?;
+    set_sample_rate(SAMPLE_RATE)?;
+    ps2::device_command(PortId::Second, CMD_SET_RESOLUTION)?;
+    ps2::device_command(PortId::Second, RESOLUTION)?;
+
+    *MOUSE.lock() = Some(Mouse {
+        kind,
+        packet: [0; 4],
+        len: 0,
+        last_byte: Instant::now(),
+        buttons: MouseButtons::default(),
+    });
+
+    let irq = irq::register_isa(12, handle_irq)?;
+    // ACK をハンドラに読まれないよう、割り込みを止めてから送信を有効にする
+    without_interrupts(|| {
+        ps2::device_command(PortId::Second, CMD_ENABLE_REPORTING)?;
+        ps2::set_irq(PortId::Second, true)
+    })?;
+    Ok((kind, irq))
+}
+
+fn set_sample_rate(rate: u8) -> Result<()> {
+    ps2::device_command(PortId::Second, CMD_SET_SAMPLE_RATE)?;
+    ps2::device_command(PortId::Second, rate)
+}
+
+fn device_id() -> Result<u8> {
+    ps2::device_command(PortId::Second, CMD_GET_ID)?;
+    ps2::read_from(PortId::Second)
+}
+
+/// 決まった順にサンプルレートを設定し、拡張モードに入ったかを ID で確かめる
+fn detect() -> Result<MouseKind> {
+    for rate in [200, 100, 80] {
+        set_sample_rate(rate)?;
+    }
+    if device_id()? != ID_WHEEL {
+        return Ok(MouseKind::Standard);
+    }
+    for rate in [200, 200, 80] {
+        set_sample_rate(rate)?;
+    }
+    Ok(match device_id()? {
+        ID_FIVE_BUTTONS => MouseKind::FiveButtons,
+        _ => MouseKind::Wheel,
+    })
+}
+
+/// IRQ12 のハンドラ
+fn handle_irq(_ctx: &InterruptContext) {
+    let Some(byte) = ps2::poll_port(PortId::Second) else { return };
+    let Some(mut mouse) = MOUSE.try_lock() else { return };
+    let Some(mouse) = mouse.as_mut() else { return };
+    if let Some(event) = mouse.feed(byte) {
+        report(event);
+    }
+}
+
+/// 1 パケット分の入力を入力イベントに分けて送る
+fn report(event: MouseEvent) {
+    if event.dx != 0 || event.dy != 0 {
+        input::report(InputEvent::Motion { dx: event.dx, dy: event.dy });
+    }
+    let buttons = [
+        (MouseButton::Left, event.changed.left, event.buttons.left),
+        (MouseButton::Right, event.changed.right, event.buttons.right),
+        (MouseButton::Middle, event.changed.middle, event.buttons.middle),
+        (MouseButton::Back, event.changed.back, event.buttons.back),
+        (MouseButton::Forward, event.changed.forward, event.buttons.forward),
+    ];
+    for (button, changed, pressed) in buttons {
+        if changed {
+            input::report(InputEvent::Button { button, pressed });
+        }
+    }
+    if event.wheel != 0 {
+        input::report(InputEvent::Wheel { delta: event.wheel as i32 });
+    }
+}
+
+impl Mouse {
+    /// 受け取った 1 byte をパケットに加え、揃えばイベントを返す
+    fn feed(&mut self, byte: u8) -> Option<MouseEvent> {
+        let now = Instant::now();
+        if self.len > 0 && now.duration_since(self.last_byte) > PACKET_TIMEOUT {
+            self.len = 0;
+        }
+        self.last_byte = now;
+        if self.len == 0 && byte & PACKET_ALWAYS_ONE == 0 {
+            // 先頭バイトではないので、同期が取れるまで読み捨てる
+            return None;
+        }
+        self.packet[self.len] = byte;
+        self.len += 1;
+        if self.len < self.kind.packet_len() {
+            return None;
+        }
+        self.len = 0;
+        Some(self.decode())
+    }
+
+    fn decode(&mut self) -> MouseEvent {
+        let [flags, x, y, extra] = self.packet;
+        // 9 bit の 2 の補数。オーバーフローした軸の値は信用できないので捨てる
+        let axis = |value: u8, sign: u8, overflow: u8| -> i32 {
+            if flags & overflow != 0 {
+                0
+            } else if flags & sign != 0 {
+                value as i32 - 0x100
+            } else {
+                value as i32
+            }
+        };
+        let (wheel, back, forward) = match self.kind {
+            MouseKind::Standard => (0, false, false),
+            MouseKind::Wheel => (extra as i8, false, false),
+            // 下位 4 bit が符号付きの回転量
+            MouseKind::FiveButtons => (
+                ((extra << 4) as i8) >> 4,
+                extra & PACKET_BUTTON4 != 0,
+                extra & PACKET_BUTTON5 != 0,
+            ),
+        };
+        let buttons = MouseButtons {
+            left: flags & PACKET_LEFT != 0,
+            right: flags & PACKET_RIGHT != 0,
+            middle: flags & PACKET_MIDDLE != 0,
+            back,
+            forward,
+        };
+        let previous = core::mem::replace(&mut self.buttons, buttons);
+        MouseEvent {
+            dx: axis(x, PACKET_X_SIGN, PACKET_X_OVERFLOW),
+            // マウスの Y は上が正
+            dy: -axis(y, PACKET_Y_SIGN, PACKET_Y_OVERFLOW),
+            wheel,
+            buttons,
+            changed: MouseButtons {
+                left: buttons.left != previous.left,
+                right: buttons.right != previous.right,
+                middle: buttons.middle != previous.middle,
+                back: buttons.back != previous.back,
+                forward: buttons.forward != previous.forward,
+            },
+        }
+    }
+}
+
+/// マウスの種類 (未初期化なら `None`)
+pub fn kind() -> Option<MouseKind> {
+    without_interrupts(|| MOUSE.lock().as_ref().map(|mouse| mouse.kind))
+}
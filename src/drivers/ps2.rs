@@ -0,0 +1,274 @@
+#![allow(dead_code)]
+//! i8042 PS/2 コントローラ
+//!
+//! - 初期化時はポート・割り込みを止めて自己診断とインターフェーステストを行い、
+//!   使えるポートだけを有効にする。
+//! - 初期化とデバイスへのコマンド送信は応答をポーリングで待つ (割り込みを有効にする前に行う)。
+//! - 割り込みが有効になった後のデータは各デバイスドライバ (キーボード・マウス) が読む。
+
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+
+pub type Result<T> = core::result::Result<T, &'static str>;
+
+const DATA_PORT: u16 = 0x60;
+/// 読み出しはステータス、書き込みはコマンド
+const STATUS_PORT: u16 = 0x64;
+
+/// ステータス: 出力バッファにデータがある
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+/// ステータス: 入力バッファが空いていない
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+/// ステータス: 出力バッファのデータは第 2 ポート (マウス) から
+pub const STATUS_AUX_DATA: u8 = 1 << 5;
+
+const CMD_READ_CONFIG: u8 = 0x20;
+const CMD_WRITE_CONFIG: u8 = 0x60;
+const CMD_DISABLE_PORT2: u8 = 0xA7;
+const CMD_ENABLE_PORT2: u8 = 0xA8;
+const CMD_TEST_PORT2: u8 = 0xA9;
+const CMD_SELF_TEST: u8 = 0xAA;
+const CMD_TEST_PORT1: u8 = 0xAB;
+const CMD_DISABLE_PORT1: u8 = 0xAD;
+const CMD_ENABLE_PORT1: u8 = 0xAE;
+/// 次のデータを第 2 ポートへ送る
+const CMD_WRITE_PORT2: u8 = 0xD4;
+
+const SELF_TEST_OK: u8 = 0x55;
+const PORT_TEST_OK: u8 = 0x00;
+
+/// 構成バイト: 第 1 ポートの割り込み (IRQ1)
+const CONFIG_PORT1_IRQ: u8 = 1 << 0;
+/// 構成バイト: 第 2 ポートの割り込み (IRQ12)
+const CONFIG_PORT2_IRQ: u8 = 1 << 1;
+/// 構成バイト: 第 2 ポートのクロック停止 (無効)
+const CONFIG_PORT2_CLOCK_DISABLED: u8 = 1 << 5;
+/// 構成バイト: 第 1 ポートのスキャンコードを set 1 に変換する
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+/// デバイスの応答: 受理
+pub const ACK: u8 = 0xFA;
+/// デバイスの応答: 再送要求
+pub const RESEND: u8 = 0xFE;
+
+/// ステータスを待つ最大回数
+const MAX_POLLS: usize = 100_000;
+/// 再送要求に応じて送り直す最大回数
+const MAX_RESENDS: usize = 3;
+
+/// コントローラのポート
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PortId {
+    /// キーボード
+    First,
+    /// マウス
+    Second,
+}
+
+/// 初期化結果 (使えるポート)
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Ports {
+    pub first: bool,
+    pub second: bool,
+}
+
+/// 初期化とコマンド送信を排他する
+static CONTROLLER: Mutex<Option<Ports>> = Mutex::new(None);
+
+fn status() -> u8 {
+    // Safety: ステータスの読み出しに副作用は無い
+    unsafe { Port::<u8>::new(STATUS_PORT).read() }
+}
+
+fn wait_input_empty() -> Result<()> {
+    for _ in 0..MAX_POLLS {
+        if status() & STATUS_INPUT_FULL == 0 {
+            return Ok(());
+        }
+        core::hint::spin_loop();
+    }
+    Err("PS/2 controller input buffer stuck")
+}
+
+fn wait_output_full() -> Result<()> {
+    for _ in 0..MAX_POLLS {
+        if status() & STATUS_OUTPUT_FULL != 0 {
+            return Ok(());
+        }
+        core::hint::spin_loop();
+    }
+    Err("PS/2 controller did not respond")
+}
+
+fn command(cmd: u8) -> Result<()> {
+    wait_input_empty()?;
+    // Safety: 入力バッファが空いているのを確認した
+    unsafe { Port::<u8>::new(STATUS_PORT).write(cmd) };
+    Ok(())
+}
+
+/// データポートへ 1 byte 書く (入力バッファが空くのを待つ)
+pub fn write_data(value: u8) -> Result<()> {
+    wait_input_empty()?;
+    // Safety: 入力バッファが空いているのを確認した
+    unsafe { Port::<u8>::new(DATA_PORT).write(value) };
+    Ok(())
+}
+
+/// データが届くのを待って読む
+pub fn read_data() -> Result<u8> {
+    wait_output_full()?;
+    Ok(read_data_unchecked())
+}
+
+/// `port` のデバイスからのデータが届くのを待って読む (もう一方のポートのデータは捨てる)
+pub fn read_from(port: PortId) -> Result<u8> {
+    for _ in 0..MAX_POLLS {
+        if let Some((status, data)) = poll() {
+            if (status & STATUS_AUX_DATA != 0) == (port == PortId::Second) {
+                return Ok(data);
+            }
+        }
+        core::hint::spin_loop();
+    }
+    Err("PS/2 device did not respond")
+}
+
+/// 待たずにデータポートを読む
+pub fn read_data_unchecked() -> u8 {
+    // Safety: データポートの読み出しは出力バッファを空けるだけ
+    unsafe { Port::<u8>::new(DATA_PORT).read() }
+}
+
+/// `port` のデバイスからのデータがあれば読む (割り込みハンドラ用)
+///
+/// もう一方のポートのデータは、そちらのハンドラが読めるよう残しておく。
+pub fn poll_port(port: PortId) -> Option<u8> {
+    let status = status();
+    let ready = status & STATUS_OUTPUT_FULL != 0 && (status & STATUS_AUX_DATA != 0) == (port == PortId::Second);
+    ready.then(read_data_unchecked)
+}
+
+/// 出力バッファにデータがあれば、そのステータスと値を返す
+pub fn poll() -> Option<(u8, u8)> {
+    let status = status();
+    (status & STATUS_OUTPUT_FULL != 0).then(|| (status, read_data_unchecked()))
+}
+
+/// 出力バッファに残っているデータを捨てる
+fn flush() {
+    for _ in 0..16 {
+        if poll().is_none() {
+            break;
+        }
+    }
+}
+
+fn read_config() -> Result<u8> {
+    command(CMD_READ_CONFIG)?;
+    read_data()
+}
+
+fn write_config(config: u8) -> Result<()> {
+    command(CMD_WRITE_CONFIG)?;
+    write_data(config)
+}
+
+/// コントローラを初期化する (割り込みは無効のまま)
+pub fn init() -> Result<Ports> {
+    let mut controller = CONTROLLER.lock();
+    // 初期化中にデバイスが送ってくるデータを取り込まないよう、両方のポートを止める
+    command(CMD_DISABLE_PORT1)?;
+    command(CMD_DISABLE_PORT2)?;
+    flush();
+
+    let mut config = read_config()?;
+    config &= !(CONFIG_PORT1_IRQ | CONFIG_PORT2_IRQ | CONFIG_TRANSLATION);
+    write_config(config)?;
+
+    command(CMD_SELF_TEST)?;
+    if read_data()? != SELF_TEST_OK {
+        return Err("PS/2 controller self test failed");
+    }
+    // 自己診断で構成バイトが初期化されるコントローラがある
+    write_config(config)?;
+
+    // 第 2 ポートを有効にしてクロックが動けば 2 チャネルのコントローラ
+    command(CMD_ENABLE_PORT2)?;
+    let dual = read_config()? & CONFIG_PORT2_CLOCK_DISABLED == 0;
+    if dual {
+        command(CMD_DISABLE_PORT2)?;
+    }
+
+    command(CMD_TEST_PORT1)?;
+    let first = read_data()? == PORT_TEST_OK;
+    let second = dual && {
+        command(CMD_TEST_PORT2)?;
+        read_data()? == PORT_TEST_OK
+    };
+    if first {
+        command(CMD_ENABLE_PORT1)?;
+    }
+    if second {
+        command(CMD_ENABLE_PORT2)?;
+    }
+    flush();
+
+    let ports = Ports { first, second };
+    *controller = Some(ports);
+    Ok(ports)
+}
+
+/// 初期化済みならその結果
+pub fn ports() -> Option<Ports> {
+    *CONTROLLER.lock()
+}
+
+/// `port` のデバイスへ 1 byte 送る (応答は待たない)
+pub fn write_port(port: PortId, value: u8) -> Result<()> {
+    if port == PortId::Second {
+        command(CMD_WRITE_PORT2)?;
+    }
+    write_data(value)
+}
+
+/// `port` のデバイスへコマンドを送り、ACK を待つ (再送要求には送り直す)
+///
+/// 割り込みを有効にする前か、`set_irq(port, false)` で止めている間に使う。
+pub fn device_command(port: PortId, value: u8) -> Result<()> {
+    for _ in 0..MAX_RESENDS {
+        write_port(port, value)?;
+        match read_from(port)? {
+            ACK => return Ok(()),
+            RESEND => continue,
+            _ => return Err("PS/2 device did not acknowledge"),
+        }
+    }
+    Err("PS/2 device kept requesting resend")
+}
+
+/// 構成バイトを書き換える
+///
+/// 応答の構成バイトを割り込みハンドラに読まれないよう、割り込みを止めて行う。
+fn update_config(f: impl FnOnce(u8) -> u8) -> Result<()> {
+    without_interrupts(|| {
+        let _controller = CONTROLLER.lock();
+        let config = read_config()?;
+        write_config(f(config))
+    })
+}
+
+/// `port` の割り込みを有効・無効にする
+pub fn set_irq(port: PortId, enabled: bool) -> Result<()> {
+    let bit = match port {
+        PortId::First => CONFIG_PORT1_IRQ,
+        PortId::Second => CONFIG_PORT2_IRQ,
+    };
+    update_config(|config| if enabled { config | bit } else { config & !bit })
+}
+
+/// 第 1 ポートのスキャンコード変換 (set 2 → set 1) を有効・無効にする
+pub fn set_translation(enabled: bool) -> Result<()> {
+    update_config(|config| if enabled { config | CONFIG_TRANSLATION } else { config & !CONFIG_TRANSLATION })
+}
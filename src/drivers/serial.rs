@@ -0,0 +1,455 @@
+#![allow(dead_code)]
+//! シリアルポート (COM1, 16550 UART)
+//!
+//! - 起動直後はポーリングで送受信する (既定 115200bps, 8N1。コマンドラインの `serial=9600n8` などで変更)。
+//! - 初期化時にループバックで動作を確かめ、FIFO の有無から UART の種類を判定する。
+//! - 割り込みコントローラの初期化後に `enable_interrupts` を呼ぶと、受信は IRQ4 でバッファに溜め、
+//!   送信はバッファに積んで送信保持レジスタが空いたときの割り込みで FIFO へ詰める。
+//!   送信バッファが一杯のときは、溜まっている分をその場でポーリング送信して空ける。
+//! - 例外・パニック時はロックを取れなくても書き込めるよう `_print_emergency` を用意する
+//!   (送信バッファの残りを先に送ってから書くので、出力の順序は保たれる)。
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use spin::{Mutex, Once};
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+use crate::sync::ring::RingBuffer;
+use crate::interrupts::InterruptContext;
+use crate::irq::{self, Irq};
+
+pub type Result<T> = core::result::Result<T, &'static str>;
+
+pub const COM1_BASE: u16 = 0x3F8;
+/// COM1 の ISA IRQ
+const COM1_IRQ: u8 = 4;
+
+/// 分周比 1 のときのボーレート
+const BASE_BAUD: u32 = 115_200;
+
+// レジスタ (base からのオフセット)
+const REG_DATA: u16 = 0;
+const REG_IER: u16 = 1;
+/// DLAB = 1 のときの除数
+const REG_DIVISOR_LOW: u16 = 0;
+const REG_DIVISOR_HIGH: u16 = 1;
+/// 読み出しは IIR、書き込みは FCR
+const REG_IIR_FCR: u16 = 2;
+const REG_LCR: u16 = 3;
+const REG_MCR: u16 = 4;
+const REG_LSR: u16 = 5;
+const REG_MSR: u16 = 6;
+const REG_SCRATCH: u16 = 7;
+
+const IER_RX_AVAILABLE: u8 = 1 << 0;
+const IER_TX_EMPTY: u8 = 1 << 1;
+const IER_LINE_STATUS: u8 = 1 << 2;
+
+/// FIFO 有効・送受信 FIFO クリア・受信トリガ 14 byte
+const FCR_ENABLE_FIFO_14: u8 = 0xC7;
+
+const IIR_NO_INTERRUPT: u8 = 1 << 0;
+const IIR_ID_MASK: u8 = 0x0E;
+const IIR_MODEM_STATUS: u8 = 0x00;
+const IIR_TX_EMPTY: u8 = 0x02;
+const IIR_RX_AVAILABLE: u8 = 0x04;
+const IIR_LINE_STATUS: u8 = 0x06;
+const IIR_RX_TIMEOUT: u8 = 0x0C;
+const IIR_FIFO_MASK: u8 = 0xC0;
+
+const LCR_DLAB: u8 = 1 << 7;
+
+const MCR_DTR: u8 = 1 << 0;
+const MCR_RTS: u8 = 1 << 1;
+/// 割り込み信号を PIC/IOAPIC へつなぐ
+const MCR_OUT2: u8 = 1 << 3;
+const MCR_LOOPBACK: u8 = 1 << 4;
+
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_OVERRUN: u8 = 1 << 1;
+const LSR_PARITY_ERROR: u8 = 1 << 2;
+const LSR_FRAMING_ERROR: u8 = 1 << 3;
+const LSR_TX_HOLDING_EMPTY: u8 = 1 << 5;
+
+/// 16550A の送信 FIFO の大きさ
+const TX_FIFO_LEN: usize = 16;
+const TX_BUFFER_LEN: usize = 1024;
+const RX_BUFFER_LEN: usize = 256;
+
+/// パリティ
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+    Mark,
+    Space,
+}
+
+/// 回線の設定
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LineConfig {
+    pub baud: u32,
+    /// 5〜8
+    pub data_bits: u8,
+    pub parity: Parity,
+    /// 1 または 2
+    pub stop_bits: u8,
+}
+
+impl Default for LineConfig {
+    fn default() -> Self {
+        Self { baud: BASE_BAUD, data_bits: 8, parity: Parity::None, stop_bits: 1 }
+    }
+}
+
+impl LineConfig {
+    /// `115200n8` 形式 (ボーレート・パリティ n/o/e/m/s・データビット) を解釈する
+    pub fn parse(s: &str) -> Option<LineConfig> {
+        let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let mut config = LineConfig { baud: s[..digits].parse().ok()?, ..LineConfig::default() };
+        let mut rest = s[digits..].chars();
+        if let Some(parity) = rest.next() {
+            config.parity = match parity {
+                'n' => Parity::None,
+                'o' => Parity::Odd,
+                'e' => Parity::Even,
+                'm' => Parity::Mark,
+                's' => Parity::Space,
+                _ => return None,
+            };
+        }
+        if let Some(bits) = rest.next() {
+            config.data_bits = bits.to_digit(10)? as u8;
+        }
+        Some(config)
+    }
+
+    fn divisor(&self) -> Result<u16> {
+        if self.baud == 0 || !BASE_BAUD.is_multiple_of(self.baud) {
+            return Err("unsupported baud rate");
+        }
+        u16::try_from(BASE_BAUD / self.baud).map_err(|_| "unsupported baud rate")
+    }
+
+    fn lcr(&self) -> Result<u8> {
+        if !(5..=8).contains(&self.data_bits) {
+            return Err("unsupported data bits");
+        }
+        let stop = match self.stop_bits {
+            1 => 0,
+            2 => 1 << 2,
+            _ => return Err("unsupported stop bits"),
+        };
+        let parity = match self.parity {
+            Parity::None => 0b000,
+            Parity::Odd => 0b001,
+            Parity::Even => 0b011,
+            Parity::Mark => 0b101,
+            Parity::Space => 0b111,
+        } << 3;
+        Ok((self.data_bits - 5) | stop | parity)
+    }
+}
+
+/// UART の種類 (FIFO の有無)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UartKind {
+    /// FIFO なし (8250 / 16450)
+    NoFifo,
+    /// FIFO が正しく動かない 16550
+    Uart16550,
+    /// FIFO 付き 16550A 互換
+    Uart16550A,
+}
+
+/// 16550 互換 UART
+pub struct SerialPort {
+    base: u16,
+}
+
+impl SerialPort {
+    pub const fn new(base: u16) -> Self {
+        Self { base }
+    }
+
+    fn read_reg(&self, reg: u16) -> u8 {
+        // Safety: base は UART の I/O ポート
+        unsafe { Port::<u8>::new(self.base + reg).read() }
+    }
+
+    fn write_reg(&self, reg: u16, value: u8) {
+        // Safety: base は UART の I/O ポート
+        unsafe { Port::<u8>::new(self.base + reg).write(value) }
+    }
+
+    /// 115200bps, 8bit, パリティなし, ストップビット 1 に設定
+    pub fn init(&mut self) {
+        // 既定の設定は必ず受け付けられる
+        let _ = self.configure(&LineConfig::default());
+    }
+
+    /// 回線を設定し、ループバックで動作を確かめる (割り込みは無効のまま)
+    pub fn configure(&mut self, config: &LineConfig) -> Result<UartKind> {
+        let divisor = config.divisor()?;
+        let lcr = config.lcr()?;
+        self.write_reg(REG_IER, 0x00);
+        self.write_reg(REG_LCR, LCR_DLAB);
+        self.write_reg(REG_DIVISOR_LOW, divisor as u8);
+        self.write_reg(REG_DIVISOR_HIGH, (divisor >> 8) as u8);
+        self.write_reg(REG_LCR, lcr);
+        self.write_reg(REG_IIR_FCR, FCR_ENABLE_FIFO_14);
+        let kind = match self.read_reg(REG_IIR_FCR) & IIR_FIFO_MASK {
+            0xC0 => UartKind::Uart16550A,
+            0x80 => UartKind::Uart16550,
+            _ => UartKind::NoFifo,
+        };
+
+        // ループバックで送った値が読めなければ、ポートが無いか壊れている
+        self.write_reg(REG_MCR, MCR_LOOPBACK | MCR_OUT2 | MCR_RTS);
+        self.write_reg(REG_DATA, 0xAE);
+        let echoed = (0..1000).find_map(|_| (self.read_reg(REG_LSR) & LSR_DATA_READY != 0).then(|| self.read_reg(REG_DATA)));
+        self.write_reg(REG_MCR, MCR_DTR | MCR_RTS | MCR_OUT2);
+        if echoed != Some(0xAE) {
+            return Err("serial port loopback test failed");
+        }
+        Ok(kind)
+    }
+
+    /// 送信保持レジスタが空くのを待って 1 byte 送信
+    pub fn write_byte(&mut self, byte: u8) {
+        while self.read_reg(REG_LSR) & LSR_TX_HOLDING_EMPTY == 0 {
+            core::hint::spin_loop();
+        }
+        self.write_reg(REG_DATA, byte);
+    }
+
+    /// 受信データがあれば 1 byte 読む
+    pub fn read_byte(&mut self) -> Option<u8> {
+        (self.read_reg(REG_LSR) & LSR_DATA_READY != 0).then(|| self.read_reg(REG_DATA))
+    }
+
+    /// 送信バッファの内容を FIFO の空き分だけ送る。残りがあれば送信割り込みを有効にする
+    fn fill_tx_fifo(&mut self, buffers: &Buffers) {
+        if self.read_reg(REG_LSR) & LSR_TX_HOLDING_EMPTY != 0 {
+            for _ in 0..TX_FIFO_LEN {
+                match buffers.tx.pop() {
+                    Some(byte) => self.write_reg(REG_DATA, byte),
+                    None => break,
+                }
+            }
+        }
+        let ier = if buffers.tx.is_empty() { IER_RX_AVAILABLE | IER_LINE_STATUS } else { IER_RX_AVAILABLE | IER_LINE_STATUS | IER_TX_EMPTY };
+        self.write_reg(REG_IER, ier);
+    }
+
+    /// 送信バッファに積む (割り込み駆動時)。一杯ならその場で送って空ける
+    fn queue_byte(&mut self, buffers: &Buffers, byte: u8) {
+        while buffers.tx.push(byte).is_err() {
+            if let Some(oldest) = buffers.tx.pop() {
+                self.write_byte(oldest);
+            }
+        }
+    }
+
+    fn write_str_with(&mut self, s: &str, mut put: impl FnMut(&mut Self, u8)) {
+        for byte in s.bytes() {
+            if byte == b'\n' {
+                put(self, b'\r');
+            }
+            put(self, byte);
+        }
+    }
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match buffers() {
+            Some(buffers) => {
+                self.write_str_with(s, |port, byte| port.queue_byte(buffers, byte));
+                self.fill_tx_fifo(buffers);
+            }
+            None => self.write_str_with(s, Self::write_byte),
+        }
+        Ok(())
+    }
+}
+
+/// 割り込み駆動時の送受信バッファ
+struct Buffers {
+    tx: RingBuffer<u8, TX_BUFFER_LEN>,
+    rx: RingBuffer<u8, RX_BUFFER_LEN>,
+}
+
+pub static COM1: Mutex<SerialPort> = Mutex::new(SerialPort::new(COM1_BASE));
+static BUFFERS: Once<Buffers> = Once::new();
+/// 割り込み駆動に切り替えたか
+static BUFFERED: AtomicBool = AtomicBool::new(false);
+/// 読める受信データがあるか
+pub fn has_input() -> bool {
+    match buffers() {
+        Some(buffers) => !buffers.rx.is_empty(),
+        None => without_interrupts(|| COM1.lock().read_reg(REG_LSR) & LSR_DATA_READY != 0),
+    }
+}
+
+/// 受信バッファが一杯で捨てた数
+static RX_DROPPED: AtomicU64 = AtomicU64::new(0);
+/// オーバーラン・パリティ・フレーミングエラーの数
+static LINE_ERRORS: AtomicU64 = AtomicU64::new(0);
+
+fn buffers() -> Option<&'static Buffers> {
+    if BUFFERED.load(Ordering::Acquire) {
+        BUFFERS.get()
+    } else {
+        None
+    }
+}
+
+/// COM1 を初期化 (コマンドラインの `serial=` で回線を設定する)
+pub fn init() {
+    let mut port = COM1.lock();
+    let config = crate::cmdline::get("serial").and_then(LineConfig::parse);
+    // 指定された設定が使えなければ既定の設定に戻す
+    if config.is_none_or(|config| port.configure(&config).is_err()) {
+        port.init();
+    }
+}
+
+/// 回線の設定を変える
+pub fn configure(config: &LineConfig) -> Result<UartKind> {
+    without_interrupts(|| {
+        let mut port = COM1.lock();
+        let kind = port.configure(config)?;
+        if buffers().is_some() {
+            port.write_reg(REG_IER, IER_RX_AVAILABLE | IER_LINE_STATUS);
+        }
+        Ok(kind)
+    })
+}
+
+/// 受信割り込みと送信バッファを有効にする (ヒープと割り込みコントローラの初期化後に呼ぶ)
+pub fn enable_interrupts() -> Result<Irq> {
+    BUFFERS.call_once(|| Buffers { tx: RingBuffer::new(), rx: RingBuffer::new() });
+    let irq = irq::register_isa(COM1_IRQ, handle_irq)?;
+    without_interrupts(|| {
+        let port = COM1.lock();
+        BUFFERED.store(true, Ordering::Release);
+        port.write_reg(REG_IER, IER_RX_AVAILABLE | IER_LINE_STATUS);
+    });
+    Ok(irq)
+}
+
+/// IRQ4 のハンドラ
+fn handle_irq(_ctx: &InterruptContext) {
+    // 送信側は割り込みを止めて COM1 のロックを取るので、ここでレジスタを触っても混ざらない
+    let mut port = SerialPort::new(COM1_BASE);
+    let Some(buffers) = BUFFERS.get() else { return };
+    loop {
+        let iir = port.read_reg(REG_IIR_FCR);
+        if iir & IIR_NO_INTERRUPT != 0 {
+            break;
+        }
+        match iir & IIR_ID_MASK {
+            IIR_RX_AVAILABLE | IIR_RX_TIMEOUT => {
+                while let Some(byte) = port.read_byte() {
+                    if buffers.rx.push(byte).is_err() {
+                        RX_DROPPED.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+            IIR_TX_EMPTY => port.fill_tx_fifo(buffers),
+            IIR_LINE_STATUS => {
+                let lsr = port.read_reg(REG_LSR);
+                if lsr & (LSR_OVERRUN | LSR_PARITY_ERROR | LSR_FRAMING_ERROR) != 0 {
+                    LINE_ERRORS.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            IIR_MODEM_STATUS => {
+                port.read_reg(REG_MSR);
+            }
+            _ => break,
+        }
+    }
+}
+
+/// 受信した 1 byte を取り出す (割り込み駆動前はポートを直接ポーリングする)
+pub fn read_byte() -> Option<u8> {
+    match buffers() {
+        Some(buffers) => buffers.rx.pop(),
+        None => without_interrupts(|| COM1.lock().read_byte()),
+    }
+}
+
+/// 受信バッファが一杯で捨てた数
+pub fn rx_dropped() -> u64 {
+    RX_DROPPED.load(Ordering::Relaxed)
+}
+
+/// 回線エラー (オーバーラン・パリティ・フレーミング) の数
+pub fn line_errors() -> u64 {
+    LINE_ERRORS.load(Ordering::Relaxed)
+}
+
+/// COM1 へ書く `fmt::Write` (`serial_print!` と同じ経路)
+pub struct Writer;
+
+impl fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        _print(format_args!("{}", s));
+        Ok(())
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    without_interrupts(|| {
+        let _ = COM1.lock().write_fmt(args);
+    });
+}
+
+/// 送信バッファの残りをポーリングで送る
+fn drain_polled(port: &mut SerialPort) {
+    if let Some(buffers) = BUFFERS.get() {
+        while let Some(byte) = buffers.tx.pop() {
+            port.write_byte(byte);
+        }
+    }
+}
+
+/// 例外・パニック用: ロック保持中でも (出力が混ざるのを許容して) ポーリングで書き込む
+#[doc(hidden)]
+pub fn _print_emergency(args: fmt::Arguments) {
+    let mut fallback = SerialPort::new(COM1_BASE);
+    let mut guard = COM1.try_lock();
+    let port = match guard.as_deref_mut() {
+        Some(port) => port,
+        None => &mut fallback,
+    };
+    drain_polled(port);
+    // 割り込みで送れなくても確実に出力するため、バッファを通さない
+    let _ = Polled(port).write_fmt(args);
+}
+
+/// 送信バッファを通さずにポーリングで書く
+struct Polled<'a>(&'a mut SerialPort);
+
+impl fmt::Write for Polled<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write_str_with(s, SerialPort::write_byte);
+        Ok(())
+    }
+}
+
+/// シリアルへ出力
+#[macro_export]
+macro_rules! serial_print {
+    ($($arg:tt)*) => ($crate::drivers::serial::_print(format_args!($($arg)*)));
+}
+
+/// シリアルへ出力 (改行付き)
+#[macro_export]
+macro_rules! serial_println {
+    () => ($crate::serial_print!("\n"));
+    ($($arg:tt)*) => ($crate::serial_print!("{}\n", format_args!($($arg)*)));
+}
@@ -32,16 +32,100 @@ pub const EFI_GRAPHICS_OUTPUT_PROTOCOL_GUID: EfiGuid = EfiGuid {
     data3: [0x96, 0xfb, 0x7a, 0xde, 0xd0, 0x80, 0x51, 0x6a],
 };
 
+/// EFI_RNG_PROTOCOL GUID
+pub const EFI_RNG_PROTOCOL_GUID: EfiGuid = EfiGuid {
+    data0: 0x3152bca5,
+    data1: 0xeade,
+    data2: 0x433d,
+    data3: [0x86, 0x2e, 0xc0, 0x1c, 0xdc, 0x29, 0x1f, 0x44],
+};
+
+/// ACPI 2.0 以降の RSDP を指す構成テーブルの GUID
+pub const EFI_ACPI_20_TABLE_GUID: EfiGuid = EfiGuid {
+    data0: 0x8868e871,
+    data1: 0xe4f1,
+    data2: 0x11d3,
+    data3: [0xbc, 0x22, 0x00, 0x80, 0xc7, 0x3c, 0x88, 0x81],
+};
+
+/// EFI_LOADED_IMAGE_PROTOCOL GUID
+pub const EFI_LOADED_IMAGE_PROTOCOL_GUID: EfiGuid = EfiGuid {
+    data0: 0x5b1b31a1,
+    data1: 0x9562,
+    data2: 0x11d2,
+    data3: [0x8e, 0x3f, 0x00, 0xa0, 0xc9, 0x69, 0x72, 0x3b],
+};
+
+/// EFI_SIMPLE_FILE_SYSTEM_PROTOCOL GUID
+pub const EFI_SIMPLE_FILE_SYSTEM_PROTOCOL_GUID: EfiGuid = EfiGuid {
+    data0: 0x964e5b22,
+    data1: 0x6459,
+    data2: 0x11d2,
+    data3: [0x8e, 0x39, 0x00, 0xa0, 0xc9, 0x69, 0x72, 0x3b],
+};
+
+const EFI_ERROR_BIT: u64 = 1 << 63;
+
+/// UEFI のステータスコード
+///
+/// ファームウェアが返しうる値はすべて列挙しておく (未定義の値を受け取ると未定義動作になるため)。
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 #[must_use]
 #[repr(u64)]
 pub enum EfiStatus {
     Success = 0,
+    WarnUnknownGlyph = 1,
+    WarnDeleteFailure = 2,
+    WarnWriteFailure = 3,
+    WarnBufferTooSmall = 4,
+    WarnStaleData = 5,
+    WarnFileSystem = 6,
+    WarnResetRequired = 7,
+    LoadError = EFI_ERROR_BIT | 1,
+    InvalidParameter = EFI_ERROR_BIT | 2,
+    Unsupported = EFI_ERROR_BIT | 3,
+    BadBufferSize = EFI_ERROR_BIT | 4,
+    BufferTooSmall = EFI_ERROR_BIT | 5,
+    NotReady = EFI_ERROR_BIT | 6,
+    DeviceError = EFI_ERROR_BIT | 7,
+    WriteProtected = EFI_ERROR_BIT | 8,
+    OutOfResources = EFI_ERROR_BIT | 9,
+    VolumeCorrupted = EFI_ERROR_BIT | 10,
+    VolumeFull = EFI_ERROR_BIT | 11,
+    NoMedia = EFI_ERROR_BIT | 12,
+    MediaChanged = EFI_ERROR_BIT | 13,
+    NotFound = EFI_ERROR_BIT | 14,
+    AccessDenied = EFI_ERROR_BIT | 15,
+    NoResponse = EFI_ERROR_BIT | 16,
+    NoMapping = EFI_ERROR_BIT | 17,
+    Timeout = EFI_ERROR_BIT | 18,
+    NotStarted = EFI_ERROR_BIT | 19,
+    AlreadyStarted = EFI_ERROR_BIT | 20,
+    Aborted = EFI_ERROR_BIT | 21,
+    IcmpError = EFI_ERROR_BIT | 22,
+    TftpError = EFI_ERROR_BIT | 23,
+    ProtocolError = EFI_ERROR_BIT | 24,
+    IncompatibleVersion = EFI_ERROR_BIT | 25,
+    SecurityViolation = EFI_ERROR_BIT | 26,
+    CrcError = EFI_ERROR_BIT | 27,
+    EndOfMedia = EFI_ERROR_BIT | 28,
+    EndOfFile = EFI_ERROR_BIT | 31,
+    InvalidLanguage = EFI_ERROR_BIT | 32,
+    CompromisedData = EFI_ERROR_BIT | 33,
+    IpAddressConflict = EFI_ERROR_BIT | 34,
+    HttpError = EFI_ERROR_BIT | 35,
 }
 
 #[repr(C)]
 pub struct EfiBootServicesTable {
-    _reserved0: [u64; 7],
+    _reserved0: [u64; 5],
+    pub allocate_pages: extern "win64" fn(
+        allocate_type: u32,
+        memory_type: u32,
+        pages: usize,
+        memory: *mut u64,
+    ) -> EfiStatus,
+    pub free_pages: extern "win64" fn(memory: u64, pages: usize) -> EfiStatus,
     pub get_memory_map: extern "win64" fn(
         memory_map_size: *mut usize,
         memory_map: *mut EfiMemoryDescriptor,
@@ -49,7 +133,19 @@ pub struct EfiBootServicesTable {
         descriptor_size: *mut usize,
         descriptor_version: *mut u32,
     ) -> EfiStatus,
-    _reserved1: [u64; 21],
+    pub allocate_pool: extern "win64" fn(
+        pool_type: u32,
+        size: usize,
+        buffer: *mut *mut EfiVoid,
+    ) -> EfiStatus,
+    pub free_pool: extern "win64" fn(buffer: *mut EfiVoid) -> EfiStatus,
+    _reserved1a: [u64; 9],
+    pub handle_protocol: extern "win64" fn(
+        handle: EfiHandle,
+        protocol: *const EfiGuid,
+        interface: *mut *mut EfiVoid,
+    ) -> EfiStatus,
+    _reserved1b: [u64; 9],
     pub exit_boot_services: extern "win64" fn(image_handle: EfiHandle, map_key: usize) -> EfiStatus,
     pub get_next_monotonic_count: extern "win64" fn(count: *mut u64) -> EfiStatus,
     pub stall: extern "win64" fn(microseconds: usize) -> EfiStatus,
@@ -60,7 +156,11 @@ pub struct EfiBootServicesTable {
         interface: *mut *mut EfiVoid,
     ) -> EfiStatus,
 }
+const _: () = assert!(offset_of!(EfiBootServicesTable, allocate_pages) == 40);
+const _: () = assert!(offset_of!(EfiBootServicesTable, free_pages) == 48);
 const _: () = assert!(offset_of!(EfiBootServicesTable, get_memory_map) == 56);
+const _: () = assert!(offset_of!(EfiBootServicesTable, allocate_pool) == 64);
+const _: () = assert!(offset_of!(EfiBootServicesTable, handle_protocol) == 152);
 const _: () = assert!(offset_of!(EfiBootServicesTable, exit_boot_services) == 232);
 const _: () = assert!(offset_of!(EfiBootServicesTable, locate_protocol) == 320);
 
@@ -68,8 +168,19 @@ const _: () = assert!(offset_of!(EfiBootServicesTable, locate_protocol) == 320);
 pub struct EfiSystemTable {
     _reserved0: [u64; 12],
     pub boot_services: &'static EfiBootServicesTable,
+    pub number_of_table_entries: usize,
+    pub configuration_table: *const EfiConfigurationTable,
 }
 const _: () = assert!(offset_of!(EfiSystemTable, boot_services) == 96);
+const _: () = assert!(offset_of!(EfiSystemTable, configuration_table) == 112);
+
+/// EFI_CONFIGURATION_TABLE
+#[repr(C)]
+pub struct EfiConfigurationTable {
+    pub vendor_guid: EfiGuid,
+    pub vendor_table: *const EfiVoid,
+}
+const _: () = assert!(size_of::<EfiConfigurationTable>() == 24);
 
 #[repr(C)]
 #[derive(Debug)]
@@ -100,6 +211,81 @@ pub struct EfiGraphicsOutputProtocol<'a> {
     pub mode: &'a EfiGraphicsOutputProtocolMode<'a>,
 }
 
+/// 乱数生成プロトコル (EFI_RNG_PROTOCOL)
+#[repr(C)]
+pub struct EfiRngProtocol {
+    pub get_info: extern "win64" fn(
+        this: *mut EfiRngProtocol,
+        algorithm_list_size: *mut usize,
+        algorithm_list: *mut EfiGuid,
+    ) -> EfiStatus,
+    pub get_rng: extern "win64" fn(
+        this: *mut EfiRngProtocol,
+        algorithm: *const EfiGuid,
+        value_length: usize,
+        value: *mut u8,
+    ) -> EfiStatus,
+}
+
+/// EFI_LOADED_IMAGE_PROTOCOL
+#[repr(C)]
+pub struct EfiLoadedImageProtocol {
+    pub revision: u32,
+    pub parent_handle: EfiHandle,
+    pub system_table: *const EfiSystemTable,
+    pub device_handle: EfiHandle,
+    pub file_path: *const EfiVoid,
+    _reserved: *const EfiVoid,
+    pub load_options_size: u32,
+    pub load_options: *const EfiVoid,
+    pub image_base: *const EfiVoid,
+    pub image_size: u64,
+}
+const _: () = assert!(offset_of!(EfiLoadedImageProtocol, device_handle) == 24);
+const _: () = assert!(offset_of!(EfiLoadedImageProtocol, load_options) == 56);
+
+/// EFI_SIMPLE_FILE_SYSTEM_PROTOCOL
+#[repr(C)]
+pub struct EfiSimpleFileSystemProtocol {
+    pub revision: u64,
+    pub open_volume: extern "win64" fn(
+        this: *mut EfiSimpleFileSystemProtocol,
+        root: *mut *mut EfiFileProtocol,
+    ) -> EfiStatus,
+}
+
+/// EFI_FILE_PROTOCOL (使用する関数のみ)
+#[repr(C)]
+pub struct EfiFileProtocol {
+    pub revision: u64,
+    pub open: extern "win64" fn(
+        this: *mut EfiFileProtocol,
+        new_handle: *mut *mut EfiFileProtocol,
+        file_name: *const u16,
+        open_mode: u64,
+        attributes: u64,
+    ) -> EfiStatus,
+    pub close: extern "win64" fn(this: *mut EfiFileProtocol) -> EfiStatus,
+    _delete: u64,
+    pub read: extern "win64" fn(
+        this: *mut EfiFileProtocol,
+        buffer_size: *mut usize,
+        buffer: *mut EfiVoid,
+    ) -> EfiStatus,
+    _write: u64,
+    pub get_position: extern "win64" fn(this: *mut EfiFileProtocol, position: *mut u64) -> EfiStatus,
+    pub set_position: extern "win64" fn(this: *mut EfiFileProtocol, position: u64) -> EfiStatus,
+}
+const _: () = assert!(offset_of!(EfiFileProtocol, read) == 32);
+const _: () = assert!(offset_of!(EfiFileProtocol, set_position) == 56);
+
+const EFI_FILE_MODE_READ: u64 = 0x1;
+const EFI_LOADER_CODE: u32 = 1;
+const EFI_LOADER_DATA: u32 = 2;
+pub const EFI_CONVENTIONAL_MEMORY: u32 = 7;
+/// AllocatePages の AllocateAddress (指定した物理アドレスに確保する)
+const ALLOCATE_ADDRESS: u32 = 2;
+
 /// UEFI Memory Descriptor (UEFI 2.x)
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
@@ -135,6 +321,16 @@ impl MemoryMapHolder {
             descriptor_version: 0,
         }
     }
+
+    /// 取得済みのメモリマップの各エントリ
+    pub fn descriptors(&self) -> impl Iterator<Item = EfiMemoryDescriptor> + '_ {
+        let count = self.memory_map_size.checked_div(self.descriptor_size).unwrap_or(0);
+        (0..count).map(move |i| {
+            let ptr = self.memory_map_buffer[i * self.descriptor_size..].as_ptr() as *const EfiMemoryDescriptor;
+            // Safety: GetMemoryMap が descriptor_size ごとにエントリを書き込んでいる (バッファは 8 バイト境界とは限らない)
+            unsafe { ptr.read_unaligned() }
+        })
+    }
 }
 
 impl EfiBootServicesTable {
@@ -182,4 +378,178 @@ pub fn framebuffer<'a>(system_table: &'a EfiSystemTable) -> Result<FrameBuffer<'
     };
 
     Ok(FrameBuffer::new(vram_slice, width, height))
-} 
\ No newline at end of file
+}
+
+/// EFI_RNG_PROTOCOL から 64bit の乱数を得る (ExitBootServices 前のみ、未実装なら `None`)
+pub fn random_u64(system_table: &EfiSystemTable) -> Option<u64> {
+    let mut rng_ptr = null_mut::<EfiRngProtocol>();
+    let status = (system_table.boot_services.locate_protocol)(
+        &EFI_RNG_PROTOCOL_GUID,
+        null_mut::<EfiVoid>(),
+        &mut rng_ptr as *mut *mut EfiRngProtocol as *mut *mut EfiVoid,
+    );
+    if status != EfiStatus::Success || rng_ptr.is_null() {
+        return None;
+    }
+
+    let mut value = 0u64;
+    // Safety: UEFI が有効なプロトコルを返したと仮定。アルゴリズムは既定 (null) を使う
+    let status = unsafe {
+        ((*rng_ptr).get_rng)(rng_ptr, core::ptr::null(), size_of::<u64>(), &mut value as *mut u64 as *mut u8)
+    };
+    (status == EfiStatus::Success).then_some(value)
+}
+
+/// 構成テーブルから `guid` に対応するテーブルの物理アドレスを探す
+pub fn find_configuration_table(system_table: &EfiSystemTable, guid: &EfiGuid) -> Option<u64> {
+    // Safety: ファームウェアが number_of_table_entries 個の有効なエントリを用意している
+    let tables = unsafe {
+        core::slice::from_raw_parts(system_table.configuration_table, system_table.number_of_table_entries)
+    };
+    tables
+        .iter()
+        .find(|t| t.vendor_guid == *guid)
+        .map(|t| t.vendor_table as u64)
+}
+
+/// 自分自身の EFI_LOADED_IMAGE_PROTOCOL
+fn loaded_image(image_handle: EfiHandle, system_table: &EfiSystemTable) -> Result<&'static EfiLoadedImageProtocol> {
+    let mut loaded_image = null_mut::<EfiLoadedImageProtocol>();
+    let status = (system_table.boot_services.handle_protocol)(
+        image_handle,
+        &EFI_LOADED_IMAGE_PROTOCOL_GUID,
+        &mut loaded_image as *mut *mut EfiLoadedImageProtocol as *mut *mut EfiVoid,
+    );
+    if status != EfiStatus::Success || loaded_image.is_null() {
+        return Err("Failed to get loaded image protocol");
+    }
+    // Safety: UEFI が有効なプロトコルを返したと仮定
+    Ok(unsafe { &*loaded_image })
+}
+
+/// イメージの起動オプション (UEFI シェルやブートエントリから渡された UCS-2 文字列)
+///
+/// ExitBootServices 前にのみ呼び出せる。
+pub fn load_options(image_handle: EfiHandle, system_table: &EfiSystemTable) -> Result<&'static [u16]> {
+    let image = loaded_image(image_handle, system_table)?;
+    if image.load_options.is_null() {
+        return Ok(&[]);
+    }
+    let len = image.load_options_size as usize / size_of::<u16>();
+    // Safety: ファームウェアが load_options_size バイトの領域を用意している
+    Ok(unsafe { core::slice::from_raw_parts(image.load_options as *const u16, len) })
+}
+
+/// ブートボリューム (自分自身を読み込んだデバイス) 上のファイルを丸ごと読み込む
+///
+/// - `path` は `\EFI\BOOT\KERNEL.SYM` のような UEFI 形式のパス。
+/// - バッファは EfiLoaderData として確保するため、ExitBootServices 後も解放されない。
+/// - ExitBootServices 前にのみ呼び出せる。
+pub fn read_file(
+    image_handle: EfiHandle,
+    system_table: &EfiSystemTable,
+    path: &str,
+) -> Result<&'static [u8]> {
+    let bs = system_table.boot_services;
+
+    // UCS-2 に変換 (終端 NUL 付き)
+    let mut name = [0u16; 128];
+    if path.len() >= name.len() {
+        return Err("path too long");
+    }
+    for (dst, ch) in name.iter_mut().zip(path.chars()) {
+        *dst = ch as u16;
+    }
+
+    let device = loaded_image(image_handle, system_table)?.device_handle;
+
+    let mut fs = null_mut::<EfiSimpleFileSystemProtocol>();
+    let status = (bs.handle_protocol)(
+        device,
+        &EFI_SIMPLE_FILE_SYSTEM_PROTOCOL_GUID,
+        &mut fs as *mut *mut EfiSimpleFileSystemProtocol as *mut *mut EfiVoid,
+    );
+    if status != EfiStatus::Success {
+        return Err("Failed to get simple file system protocol");
+    }
+
+    unsafe {
+        let mut root = null_mut::<EfiFileProtocol>();
+        if ((*fs).open_volume)(fs, &mut root) != EfiStatus::Success {
+            return Err("Failed to open boot volume");
+        }
+        let mut file = null_mut::<EfiFileProtocol>();
+        let status = ((*root).open)(root, &mut file, name.as_ptr(), EFI_FILE_MODE_READ, 0);
+        let _ = ((*root).close)(root);
+        if status != EfiStatus::Success {
+            return Err("File not found");
+        }
+
+        let result = read_whole(bs, file);
+        let _ = ((*file).close)(file);
+        result
+    }
+}
+
+/// `len` 要素の配列を EfiLoaderData として確保する (ゼロ初期化済み)
+///
+/// - ExitBootServices 後も解放されない。ヒープ初期化前に大きな表を作るのに使う。
+/// - ExitBootServices 前にのみ呼び出せる。
+///
+/// # Safety
+///
+/// `T` はすべてのビットが 0 の値が有効で、アラインメントが 8 バイト以下の型であること。
+pub unsafe fn allocate_zeroed<T>(system_table: &EfiSystemTable, len: usize) -> Result<&'static mut [T]> {
+    if len == 0 {
+        return Ok(&mut []);
+    }
+    let size = len.checked_mul(size_of::<T>()).ok_or("allocation too large")?;
+    let mut buffer = null_mut::<EfiVoid>();
+    if (system_table.boot_services.allocate_pool)(EFI_LOADER_DATA, size, &mut buffer) != EfiStatus::Success {
+        return Err("Failed to allocate pool");
+    }
+    // プールは 8 バイト境界に確保され、誰とも共有していない
+    core::ptr::write_bytes(buffer, 0, size);
+    Ok(core::slice::from_raw_parts_mut(buffer as *mut T, len))
+}
+
+/// 物理アドレス `addr` から `pages` ページを EfiLoaderCode として確保する (ExitBootServices 前のみ)
+pub fn allocate_code_pages_at(system_table: &EfiSystemTable, addr: u64, pages: usize) -> Result<()> {
+    let mut memory = addr;
+    let status = (system_table.boot_services.allocate_pages)(ALLOCATE_ADDRESS, EFI_LOADER_CODE, pages, &mut memory);
+    if status != EfiStatus::Success {
+        return Err("Failed to allocate pages");
+    }
+    Ok(())
+}
+
+/// `allocate_code_pages_at` で確保したページを返す
+pub fn free_pages(system_table: &EfiSystemTable, addr: u64, pages: usize) {
+    let _ = (system_table.boot_services.free_pages)(addr, pages);
+}
+
+/// 開いたファイルを末尾まで読み込む
+unsafe fn read_whole(bs: &EfiBootServicesTable, file: *mut EfiFileProtocol) -> Result<&'static [u8]> {
+    // 末尾へシークしてサイズを得る
+    let mut size = 0u64;
+    if ((*file).set_position)(file, u64::MAX) != EfiStatus::Success
+        || ((*file).get_position)(file, &mut size) != EfiStatus::Success
+        || ((*file).set_position)(file, 0) != EfiStatus::Success
+    {
+        return Err("Failed to get file size");
+    }
+    if size == 0 {
+        return Ok(&[]);
+    }
+
+    let mut buffer = null_mut::<EfiVoid>();
+    if (bs.allocate_pool)(EFI_LOADER_DATA, size as usize, &mut buffer) != EfiStatus::Success {
+        return Err("Failed to allocate file buffer");
+    }
+    let mut read_size = size as usize;
+    if ((*file).read)(file, &mut read_size, buffer) != EfiStatus::Success {
+        let _ = (bs.free_pool)(buffer);
+        return Err("Failed to read file");
+    }
+    Ok(core::slice::from_raw_parts(buffer, read_size))
+}
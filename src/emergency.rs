@@ -0,0 +1,93 @@
+#![allow(dead_code)]
+//! 緊急コンソール
+//!
+//! - `efi::framebuffer` 直後に一度だけフレームバッファを登録しておき、
+//!   例外ハンドラや panic ハンドラからロックを一切取らずに描画できるようにする。
+//! - 状態はすべてアトミック変数で持つ。通常の描画処理と同時に書き込むと画面は乱れるが、
+//!   停止直前の表示を優先する。
+
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::graphics::FrameBuffer;
+use crate::graphics::text::{CHAR_ADVANCE, GLYPH_WIDTH, LINE_HEIGHT};
+
+/// 画面端の余白
+const MARGIN: usize = 8;
+
+static VRAM_BASE: AtomicUsize = AtomicUsize::new(0);
+static VRAM_LEN: AtomicUsize = AtomicUsize::new(0);
+static WIDTH: AtomicUsize = AtomicUsize::new(0);
+static HEIGHT: AtomicUsize = AtomicUsize::new(0);
+static CURSOR_X: AtomicUsize = AtomicUsize::new(MARGIN);
+static CURSOR_Y: AtomicUsize = AtomicUsize::new(MARGIN);
+
+/// フレームバッファを緊急コンソールとして登録する
+pub fn register(fb: &mut FrameBuffer) {
+    let (base, len) = fb.raw_parts();
+    VRAM_LEN.store(len, Ordering::Relaxed);
+    WIDTH.store(fb.width, Ordering::Relaxed);
+    HEIGHT.store(fb.height, Ordering::Relaxed);
+    // base を最後に公開し、読み手はこれが 0 以外なら他の値も有効とみなす
+    VRAM_BASE.store(base as usize, Ordering::Release);
+}
+
+/// 登録済みフレームバッファへの描画ハンドル
+pub struct EmergencyConsole {
+    fb: FrameBuffer<'static>,
+    color: u32,
+}
+
+impl EmergencyConsole {
+    /// 登録済みなら描画ハンドルを返す
+    ///
+    /// 既存の `FrameBuffer` と同じ VRAM を指す別名を作るため、緊急時以外は使わないこと。
+    pub fn get() -> Option<Self> {
+        let base = VRAM_BASE.load(Ordering::Acquire);
+        if base == 0 {
+            return None;
+        }
+        let len = VRAM_LEN.load(Ordering::Relaxed);
+        // Safety: register で受け取った VRAM 範囲で、UEFI 終了後も常にマップされている
+        let vram = unsafe { core::slice::from_raw_parts_mut(base as *mut u32, len) };
+        let fb = FrameBuffer::new(vram, WIDTH.load(Ordering::Relaxed), HEIGHT.load(Ordering::Relaxed));
+        Some(Self { fb, color: 0 })
+    }
+
+    /// 画面を塗りつぶしてカーソルを左上に戻す
+    pub fn clear(&mut self, background: u32) {
+        self.fb.clear(background);
+        CURSOR_X.store(MARGIN, Ordering::Relaxed);
+        CURSOR_Y.store(MARGIN, Ordering::Relaxed);
+    }
+
+    pub fn set_color(&mut self, color: u32) {
+        self.color = color;
+    }
+
+    fn newline(&mut self) {
+        CURSOR_X.store(MARGIN, Ordering::Relaxed);
+        let y = CURSOR_Y.load(Ordering::Relaxed) + LINE_HEIGHT;
+        // 画面下端に達したら上から書き直す (スクロールはしない)
+        let y = if y + LINE_HEIGHT > self.fb.height { MARGIN } else { y };
+        CURSOR_Y.store(y, Ordering::Relaxed);
+    }
+}
+
+impl fmt::Write for EmergencyConsole {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for ch in s.chars() {
+            if ch == '\n' {
+                self.newline();
+                continue;
+            }
+            if CURSOR_X.load(Ordering::Relaxed) + GLYPH_WIDTH > self.fb.width - MARGIN {
+                self.newline();
+            }
+            let x = CURSOR_X.load(Ordering::Relaxed);
+            let y = CURSOR_Y.load(Ordering::Relaxed);
+            self.fb.draw_char(x, y, ch, self.color);
+            CURSOR_X.store(x + CHAR_ADVANCE, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+//! CPU 例外ハンドラ
+//!
+//! - 全アーキテクチャ例外について、例外名・エラーコードの解釈・汎用レジスタ・
+//!   フォルトアドレス・バックトレースをシリアルへ出力する。
+//! - #DB / NMI / #BP は同じ内容をフレームバッファのコンソールにも出してから復帰する。
+//!   それ以外は例外時のレジスタを `panic_screen` に渡してから panic で停止する
+//!   (画面への表示は panic 画面が担当する)。
+
+use core::arch::asm;
+use core::fmt;
+use x86_64::registers::control::Cr2;
+use x86_64::VirtAddr;
+use crate::backtrace::Backtrace;
+use crate::interrupts::InterruptContext;
+use crate::memory::stack;
+use crate::panic_screen;
+
+/// ベクタ番号 0-31 の例外名
+pub const EXCEPTION_NAMES: [&str; 32] = [
+    "Divide Error (#DE)",
+    "Debug (#DB)",
+    "Non-Maskable Interrupt",
+    "Breakpoint (#BP)",
+    "Overflow (#OF)",
+    "Bound Range Exceeded (#BR)",
+    "Invalid Opcode (#UD)",
+    "Device Not Available (#NM)",
+    "Double Fault (#DF)",
+    "Coprocessor Segment Overrun",
+    "Invalid TSS (#TS)",
+    "Segment Not Present (#NP)",
+    "Stack-Segment Fault (#SS)",
+    "General Protection (#GP)",
+    "Page Fault (#PF)",
+    "Reserved",
+    "x87 Floating-Point (#MF)",
+    "Alignment Check (#AC)",
+    "Machine Check (#MC)",
+    "SIMD Floating-Point (#XM)",
+    "Virtualization (#VE)",
+    "Control Protection (#CP)",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Hypervisor Injection (#HV)",
+    "VMM Communication (#VC)",
+    "Security (#SX)",
+    "Reserved",
+];
+
+/// 例外を処理する。復帰可能な例外以外は戻らない
+pub fn handle(ctx: &mut InterruptContext) {
+    let vector = ctx.vector as usize;
+    let name = EXCEPTION_NAMES[vector];
+    crate::drivers::serial::_print_emergency(format_args!("{}", Report(ctx)));
+
+    match vector {
+        // デバッグ系は報告のみで続行 (ロック中のコンソールには書かない)
+        1..=3 => crate::console::try_print_isolated(format_args!("{}", Report(ctx))),
+        8 | 14 => {
+            check_stack_overflow(ctx);
+            fatal(name, ctx);
+        }
+        _ => fatal(name, ctx),
+    }
+}
+
+fn fatal(name: &str, ctx: &InterruptContext) -> ! {
+    panic_screen::set_exception_context(ctx);
+    match ctx.vector {
+        14 => panic!("{} at {:#x}: {:#x}", name, ctx.rip, Cr2::read().as_u64()),
+        _ => panic!("{} at {:#x}", name, ctx.rip),
+    }
+}
+
+/// フォルトアドレスまたは割り込み時の RSP がスタックのガードページに当たっていれば
+/// スタックオーバーフローとして報告する
+///
+/// ガードページへの push で #PF のフレームを積めなかった場合は #DF になる。
+fn check_stack_overflow(ctx: &InterruptContext) {
+    let hit = stack::guard_hit(Cr2::read()).or_else(|| stack::guard_hit(VirtAddr::new(ctx.rsp)));
+    if let Some(info) = hit {
+        panic_screen::set_exception_context(ctx);
+        panic!("kernel stack overflow in {}", info.name);
+    }
+}
+
+/// 例外の報告 (例外名・追加情報・レジスタ・バックトレース)
+struct Report<'a>(&'a InterruptContext);
+
+impl fmt::Display for Report<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ctx = self.0;
+        write!(
+            f,
+            "\n*** EXCEPTION: {} (vector {})\n{}{}backtrace:\n{}",
+            EXCEPTION_NAMES[ctx.vector as usize],
+            ctx.vector,
+            Detail(ctx),
+            ctx,
+            Backtrace { rip: Some(ctx.rip), rbp: ctx.rbp }
+        )
+    }
+}
+
+/// 例外ごとの追加情報 (エラーコードの解釈など)
+struct Detail<'a>(&'a InterruptContext);
+
+impl fmt::Display for Detail<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let err = self.0.error_code;
+        match self.0.vector {
+            14 => {
+                write!(f, "fault address: {:#018x}\naccess:", Cr2::read().as_u64())?;
+                f.write_str(if err & 1 != 0 { " protection-violation" } else { " not-present" })?;
+                f.write_str(if err & (1 << 1) != 0 { " write" } else { " read" })?;
+                f.write_str(if err & (1 << 2) != 0 { " user" } else { " kernel" })?;
+                for (bit, label) in [(3, " reserved-bit"), (4, " instruction-fetch"), (5, " protection-key"), (6, " shadow-stack"), (15, " sgx")] {
+                    if err & (1 << bit) != 0 {
+                        f.write_str(label)?;
+                    }
+                }
+                writeln!(f)
+            }
+            10..=13 if err != 0 => {
+                let table = match (err >> 1) & 0b11 {
+                    0 => "GDT",
+                    2 => "LDT",
+                    _ => "IDT",
+                };
+                writeln!(
+                    f,
+                    "selector: {} index {}{}",
+                    table,
+                    (err >> 3) & 0x1FFF,
+                    if err & 1 != 0 { " (external)" } else { "" }
+                )
+            }
+            16 => writeln!(f, "x87 FSW: {:#06x}", x87_status()),
+            19 => writeln!(f, "MXCSR: {:#010x}", mxcsr()),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn mxcsr() -> u32 {
+    let mut value = 0u32;
+    // Safety: SSE は UEFI により有効化済み
+    unsafe { asm!("stmxcsr [{}]", in(reg) &mut value, options(nostack)) };
+    value
+}
+
+fn x87_status() -> u16 {
+    let value: u16;
+    unsafe { asm!("fnstsw ax", out("ax") value, options(nomem, nostack)) };
+    value
+}
@@ -7,6 +7,8 @@
 
 #![allow(dead_code)]
 
+pub mod extra;
+
 /// フォントデータ: 0x20 (' ') 〜 0x7E ('~')
 /// 配列インデックス = code - 0x20
 pub static ASCII_FONT: [[u8; 8]; 95] = [
@@ -203,13 +205,13 @@ pub static ASCII_FONT: [[u8; 8]; 95] = [
     [0x76,0xDC,0x00,0x00,0x00,0x00,0x00,0x00],
 ];
 
-/// 文字からビットマップを取得
+/// 文字からビットマップを取得 (ASCII 以外は読み込んだ追加フォントから)
 #[inline]
 pub fn glyph(ch: char) -> Option<&'static [u8; 8]> {
     let code = ch as u32;
     if (0x20..=0x7E).contains(&code) {
         Some(&ASCII_FONT[(code - 0x20) as usize])
     } else {
-        None
+        extra::glyph(ch)
     }
 } 
\ No newline at end of file
@@ -0,0 +1,126 @@
+//! 追加フォント (かな・漢字など ASCII 以外の字形)
+//!
+//! - ESP 上の `\EFI\BOOT\FONT.BDF` を ExitBootServices 前に読み込む。
+//! - 形式は BDF。符号化は Unicode (`CHARSET_REGISTRY "ISO10646"`) で、字形は 8x8 以内であること
+//!   (美咲フォントの Unicode 版など)。8x8 に収まらない字形は読み飛ばす。
+//! - 読み込み時に 8x8 のビットマップへ変換して符号位置順に並べ、引くときは二分探索する。
+//! - 組み込みの ASCII フォントが優先され、ここには ASCII 以外の文字だけが入る。
+
+use spin::Once;
+use crate::efi::{self, EfiHandle, EfiSystemTable};
+
+/// ESP 上のフォントのパス
+const FONT_FILE: &str = "\\EFI\\BOOT\\FONT.BDF";
+
+/// 1 文字分の字形
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Glyph {
+    code: u32,
+    bitmap: [u8; 8],
+}
+
+static GLYPHS: Once<&'static [Glyph]> = Once::new();
+
+/// ESP からフォントを読み込み、字形の数を返す (ExitBootServices 前に呼ぶこと)
+///
+/// 見つからなければ ASCII 以外の文字は表示されない。
+pub fn load(image_handle: EfiHandle, system_table: &EfiSystemTable) -> efi::Result<usize> {
+    let data = efi::read_file(image_handle, system_table, FONT_FILE)?;
+    let text = core::str::from_utf8(data).map_err(|_| "font is not valid text")?;
+    let header = parse_header(text)?;
+
+    // ヒープはまだ無いので、字形表は EfiLoaderData に置く
+    // Safety: Glyph はすべて 0 の値が有効で、アラインメントは 4
+    let glyphs = unsafe { efi::allocate_zeroed::<Glyph>(system_table, chars(text, &header).count())? };
+    for (slot, glyph) in glyphs.iter_mut().zip(chars(text, &header)) {
+        *slot = glyph;
+    }
+    glyphs.sort_unstable_by_key(|glyph| glyph.code);
+
+    let glyphs = GLYPHS.call_once(|| glyphs);
+    Ok(glyphs.len())
+}
+
+/// `ch` の字形 (読み込んでいなければ `None`)
+pub fn glyph(ch: char) -> Option<&'static [u8; 8]> {
+    let glyphs = GLYPHS.get()?;
+    let index = glyphs.binary_search_by_key(&(ch as u32), |glyph| glyph.code).ok()?;
+    Some(&glyphs[index].bitmap)
+}
+
+/// フォント全体の情報
+struct Header {
+    /// ベースラインから上の高さ (8x8 の枠の何行目がベースラインか)
+    ascent: i32,
+}
+
+fn parse_header(text: &str) -> efi::Result<Header> {
+    if !text.starts_with("STARTFONT") {
+        return Err("font is not in BDF format");
+    }
+    let mut ascent = None;
+    let mut bounding_box = None;
+    for line in text.lines().take_while(|line| !line.starts_with("CHARS ")) {
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        match key {
+            "CHARSET_REGISTRY" => {
+                if !value.trim_matches('"').eq_ignore_ascii_case("ISO10646") {
+                    return Err("font is not Unicode (ISO10646) encoded");
+                }
+            }
+            "FONT_ASCENT" => ascent = value.trim().parse().ok(),
+            "FONTBOUNDINGBOX" => bounding_box = parse_bbx(value),
+            _ => {}
+        }
+    }
+    // FONT_ASCENT が無ければ外接矩形から求める
+    let ascent = ascent.or(bounding_box.map(|(_, h, _, y)| h + y)).ok_or("font has no ascent")?;
+    Ok(Header { ascent: ascent.clamp(0, 8) })
+}
+
+/// `BBX` / `FONTBOUNDINGBOX` の (幅, 高さ, x オフセット, y オフセット)
+fn parse_bbx(value: &str) -> Option<(i32, i32, i32, i32)> {
+    let mut fields = value.split_whitespace().map(|field| field.parse::<i32>().ok());
+    Some((fields.next()??, fields.next()??, fields.next()??, fields.next()??))
+}
+
+/// 使える字形 (ASCII 以外で 8x8 に収まるもの)
+fn chars<'t>(text: &'t str, header: &'t Header) -> impl Iterator<Item = Glyph> + 't {
+    text.split("STARTCHAR").skip(1).filter_map(|block| parse_char(block, header))
+}
+
+/// `STARTCHAR` から `ENDCHAR` までの 1 文字を 8x8 のビットマップにする
+fn parse_char(block: &str, header: &Header) -> Option<Glyph> {
+    let mut lines = block.lines();
+    let mut code = None;
+    let mut bbx = None;
+    for line in lines.by_ref() {
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        match key {
+            "ENCODING" => code = value.trim().parse::<u32>().ok(),
+            "BBX" => bbx = parse_bbx(value),
+            "BITMAP" => break,
+            _ => {}
+        }
+    }
+    let code = code.filter(|&code| code > 0x7E && char::from_u32(code).is_some())?;
+    let (width, height, x_offset, y_offset) = bbx?;
+    if !(0..=8).contains(&width) || height < 0 || x_offset < 0 || x_offset + width > 8 {
+        return None;
+    }
+
+    let mut bitmap = [0u8; 8];
+    // 字形の最上行が枠の何行目に来るか
+    let top = header.ascent - (y_offset + height);
+    for (row, line) in lines.take_while(|line| !line.starts_with("ENDCHAR")).enumerate().take(height as usize) {
+        let y = top + row as i32;
+        if !(0..8).contains(&y) {
+            continue;
+        }
+        // 各行の先頭 1 バイトだけ使う (幅 8 以内なので 2 バイト目以降は無い)
+        let bits = u8::from_str_radix(line.get(..2)?, 16).ok()?;
+        bitmap[y as usize] = bits >> x_offset;
+    }
+    Some(Glyph { code, bitmap })
+}
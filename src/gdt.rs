@@ -1,10 +1,22 @@
+use core::ptr::addr_of;
 use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable};
 use x86_64::structures::tss::TaskStateSegment;
 use x86_64::VirtAddr;
 use lazy_static::lazy_static;
+use crate::memory::stack::{KernelStack, IST_STACK_SIZE};
 
-// TSS 用の固定スタック
-static mut DOUBLE_FAULT_STACK: [u8; 4096] = [0; 4096];
+/// ダブルフォルトで使う IST 番号
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+
+/// 起動中だけ使うダブルフォルト用の固定スタック
+///
+/// `init` から `install_guarded_stacks` (ヒープ初期化の直後) までの間だけ IST が指す。
+/// ガードページが無いので、この間にこのスタックを溢れさせると隣の .bss を壊す。
+/// 大きさはガード付きの IST スタックと同じにしてある。
+static mut BOOT_DOUBLE_FAULT_STACK: [u8; IST_STACK_SIZE] = [0; IST_STACK_SIZE];
+
+// IST は後から差し替えるため可変 static で保持する
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
 
 // セグメントセレクタ保持
 pub struct Selectors {
@@ -13,19 +25,16 @@ pub struct Selectors {
 }
 
 lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        // ダブルフォルト用スタック
-        let stack_start = unsafe { &DOUBLE_FAULT_STACK as *const _ as u64 };
-        let stack_end = stack_start + 4096;
-        tss.interrupt_stack_table[0] = VirtAddr::new(stack_end);
-        tss
-    };
-
     static ref GDT: (GlobalDescriptorTable, Selectors) = {
+        // ダブルフォルト用スタック (起動中だけ。install_guarded_stacks で差し替える)
+        let stack_start = &raw const BOOT_DOUBLE_FAULT_STACK as u64;
+        let stack_end = (stack_start + IST_STACK_SIZE as u64) & !0xF;
+        set_ist_stack(DOUBLE_FAULT_IST_INDEX, VirtAddr::new(stack_end));
+
         let mut gdt = GlobalDescriptorTable::new();
         let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        // Safety: TSS は static で、以後 IST エントリ以外は変更しない
+        let tss_selector = gdt.add_entry(Descriptor::tss_segment(unsafe { &*addr_of!(TSS) }));
         (gdt, Selectors { code_selector, tss_selector })
     };
 }
@@ -40,4 +49,24 @@ pub fn init() {
         set_cs(GDT.1.code_selector);
         load_tss(GDT.1.tss_selector);
     }
-} 
\ No newline at end of file
+}
+
+/// IST エントリ `index` のスタックトップを設定する
+///
+/// CPU は例外発生のたびに TSS を読み直すので、ロード後の差し替えも有効。
+pub fn set_ist_stack(index: u16, top: VirtAddr) {
+    // Safety: 8 byte のアラインされた書き込みで、IST を参照する例外とは競合しない
+    unsafe {
+        let ist = &raw mut TSS.interrupt_stack_table[index as usize];
+        ist.write_volatile(top);
+    }
+}
+
+/// IST を起動用の固定スタックからガードページ付きスタックへ差し替える
+///
+/// フレームアロケータとヒープの初期化後、できるだけ早く呼び出すこと。
+pub fn install_guarded_stacks() -> Option<()> {
+    let stack = KernelStack::new("double_fault", IST_STACK_SIZE)?;
+    set_ist_stack(DOUBLE_FAULT_IST_INDEX, stack.leak());
+    Some(())
+}
@@ -5,6 +5,8 @@
 //! - ピクセル・矩形・テキスト描画などの基本 API を提供します。
 //! - UEFI のフレームバッファへのアクセスを想定しており 32-bit BGRX/RGBX 形式を扱います。
 
+pub mod text;
+
 use core::cmp::{max, min};
 use crate::font;
 
@@ -31,6 +33,21 @@ impl<'a> FrameBuffer<'a> {
         Self { vram, width, height }
     }
 
+    /// VRAM 先頭ポインタと要素数 (緊急コンソールの登録用)
+    pub fn raw_parts(&mut self) -> (*mut u32, usize) {
+        (self.vram.as_mut_ptr(), self.vram.len())
+    }
+
+    /// 下端の `rows` 行を別の `FrameBuffer` として切り離す (自身の高さはその分減る)
+    pub fn split_off_bottom(&mut self, rows: usize) -> FrameBuffer<'a> {
+        let rows = min(rows, self.height);
+        let vram = core::mem::take(&mut self.vram);
+        let (top, bottom) = vram.split_at_mut((self.height - rows) * self.width);
+        self.vram = top;
+        self.height -= rows;
+        FrameBuffer::new(bottom, self.width, rows)
+    }
+
     /// ピクセルを描画 (境界チェック付き)
     pub fn draw_pixel(&mut self, x: usize, y: usize, color: u32) {
         if x < self.width && y < self.height {
@@ -59,6 +76,30 @@ impl<'a> FrameBuffer<'a> {
         }
     }
 
+    /// `y` から高さ `height` の行範囲を `dy` px 上へずらし、空いた下端を `fill` で塗る
+    ///
+    /// 行単位のブロックコピーなので、描き直すより速い。
+    pub fn scroll_up(&mut self, y: usize, height: usize, dy: usize, fill: u32) {
+        let y_end = min(y.saturating_add(height), self.height);
+        if y >= y_end {
+            return;
+        }
+        let dy = min(dy, y_end - y);
+        self.vram.copy_within((y + dy) * self.width..y_end * self.width, y * self.width);
+        self.fill_rect(0, y_end - dy, self.width, dy, fill);
+    }
+
+    /// `y` から高さ `height` の行範囲を `dy` px 下へずらし、空いた上端を `fill` で塗る
+    pub fn scroll_down(&mut self, y: usize, height: usize, dy: usize, fill: u32) {
+        let y_end = min(y.saturating_add(height), self.height);
+        if y >= y_end {
+            return;
+        }
+        let dy = min(dy, y_end - y);
+        self.vram.copy_within(y * self.width..(y_end - dy) * self.width, (y + dy) * self.width);
+        self.fill_rect(0, y, self.width, dy, fill);
+    }
+
     /// 矩形の枠線のみ描画
     pub fn stroke_rect(&mut self, x: usize, y: usize, w: usize, h: usize, color: u32) {
         if w == 0 || h == 0 {
@@ -144,7 +185,7 @@ impl<'a> FrameBuffer<'a> {
     pub fn draw_text(&mut self, mut x: usize, y: usize, text: &str, color: u32) {
         for ch in text.chars() {
             self.draw_char(x, y, ch, color);
-            x += 8 + 2; // 文字幅 + スペース
+            x += text::CHAR_ADVANCE;
         }
     }
 
@@ -0,0 +1,330 @@
+//! テキストの配置と書式付き出力
+//!
+//! - 8x8 フォントを 1 文字 `CHAR_ADVANCE` px、1 行 `LINE_HEIGHT` px で並べたときの大きさを測る。
+//! - 矩形 (`Rect`) の中に、左・中央・右 / 上・中央・下揃えで描く。幅を超える行は空白で
+//!   (空白がなければ文字の途中で) 折り返し、矩形からはみ出た部分は描かない。
+//! - `fb_print!` で `format_args!` の結果をそのまま描ける。ヒープを使わず固定長のバッファで整形する。
+
+use core::fmt;
+use super::FrameBuffer;
+
+/// 字形の幅・高さ
+pub const GLYPH_WIDTH: usize = 8;
+pub const GLYPH_HEIGHT: usize = 8;
+/// 1 文字の送り幅 (字形 8px + 間隔 2px)
+pub const CHAR_ADVANCE: usize = 10;
+/// 1 行の送り幅
+pub const LINE_HEIGHT: usize = 10;
+
+/// 矩形領域
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub const fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Self { x, y, width, height }
+    }
+
+    pub const fn right(&self) -> usize {
+        self.x + self.width
+    }
+
+    pub const fn bottom(&self) -> usize {
+        self.y + self.height
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        (self.x..self.right()).contains(&x) && (self.y..self.bottom()).contains(&y)
+    }
+
+    /// 重なっている部分 (重ならなければ空)
+    pub fn intersect(&self, other: &Rect) -> Rect {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.right().min(other.right()).max(x);
+        let bottom = self.bottom().min(other.bottom()).max(y);
+        Rect::new(x, y, right - x, bottom - y)
+    }
+
+    /// 両方を含む最小の矩形
+    pub fn union(&self, other: &Rect) -> Rect {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect::new(x, y, self.right().max(other.right()) - x, self.bottom().max(other.bottom()) - y)
+    }
+}
+
+/// 横方向の揃え
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HAlign {
+    Left,
+    Center,
+    Right,
+}
+
+/// 縦方向の揃え
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VAlign {
+    Top,
+    Middle,
+    Bottom,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Align {
+    pub h: HAlign,
+    pub v: VAlign,
+}
+
+impl Align {
+    pub const TOP_LEFT: Align = Align { h: HAlign::Left, v: VAlign::Top };
+    pub const TOP_CENTER: Align = Align { h: HAlign::Center, v: VAlign::Top };
+    pub const TOP_RIGHT: Align = Align { h: HAlign::Right, v: VAlign::Top };
+    pub const CENTER: Align = Align { h: HAlign::Center, v: VAlign::Middle };
+    pub const BOTTOM_LEFT: Align = Align { h: HAlign::Left, v: VAlign::Bottom };
+    pub const BOTTOM_RIGHT: Align = Align { h: HAlign::Right, v: VAlign::Bottom };
+}
+
+/// `chars` 文字を 1 行に並べたときの幅 (末尾の間隔は含めない)
+pub const fn width_of(chars: usize) -> usize {
+    if chars == 0 {
+        0
+    } else {
+        chars * CHAR_ADVANCE - (CHAR_ADVANCE - GLYPH_WIDTH)
+    }
+}
+
+/// `lines` 行を並べたときの高さ (最終行の行間は含めない)
+pub const fn height_of(lines: usize) -> usize {
+    if lines == 0 {
+        0
+    } else {
+        lines * LINE_HEIGHT - (LINE_HEIGHT - GLYPH_HEIGHT)
+    }
+}
+
+/// 幅 `max_width` に収まる 1 行の文字数 (最低 1 文字)
+pub const fn chars_per_line(max_width: usize) -> usize {
+    let chars = (max_width + CHAR_ADVANCE - GLYPH_WIDTH) / CHAR_ADVANCE;
+    if chars == 0 { 1 } else { chars }
+}
+
+/// 描いたときの (幅, 高さ) (改行のみで行を分け、折り返さない)
+pub fn measure(text: &str) -> (usize, usize) {
+    measure_lines(text.split('\n'))
+}
+
+/// 幅 `max_width` で折り返して描いたときの (幅, 高さ)
+pub fn measure_wrapped(text: &str, max_width: usize) -> (usize, usize) {
+    measure_lines(wrap(text, max_width))
+}
+
+fn measure_lines<'t>(lines: impl Iterator<Item = &'t str>) -> (usize, usize) {
+    let (longest, count) = lines.fold((0, 0), |(longest, count), line| (longest.max(line.chars().count()), count + 1));
+    (width_of(longest), height_of(count))
+}
+
+/// 幅 `max_width` で折り返した各行
+pub fn wrap(text: &str, max_width: usize) -> Wrap<'_> {
+    Wrap { rest: Some(text), max_chars: chars_per_line(max_width) }
+}
+
+/// `wrap` の結果
+pub struct Wrap<'t> {
+    rest: Option<&'t str>,
+    max_chars: usize,
+}
+
+impl<'t> Iterator for Wrap<'t> {
+    type Item = &'t str;
+
+    fn next(&mut self) -> Option<&'t str> {
+        let rest = self.rest?;
+        let line_end = rest.find('\n').unwrap_or(rest.len());
+        let line = &rest[..line_end];
+        // max_chars 文字目の直後のバイト位置 (収まるなら行末)
+        let limit = line.char_indices().nth(self.max_chars).map_or(line.len(), |(i, _)| i);
+        if limit == line.len() {
+            self.rest = rest.get(line_end + 1..);
+            return Some(line);
+        }
+        // 収まる範囲の最後の空白で折り返し、なければ文字の途中で切る
+        let (end, next) = if line[limit..].starts_with(' ') {
+            (limit, limit + 1)
+        } else {
+            match line[..limit].rfind(' ') {
+                Some(space) if space > 0 => (space, space + 1),
+                _ => (limit, limit),
+            }
+        };
+        self.rest = Some(&rest[next..]);
+        Some(&line[..end])
+    }
+}
+
+impl<'a> FrameBuffer<'a> {
+    /// 画面全体の矩形
+    pub fn bounds(&self) -> Rect {
+        Rect::new(0, 0, self.width, self.height)
+    }
+
+    /// `area` の中に `text` を折り返して揃えて描き、実際に描いた範囲を返す
+    pub fn draw_text_in(&mut self, area: Rect, text: &str, align: Align, color: u32) -> Rect {
+        let clip = area.intersect(&self.bounds());
+        let (_, total_height) = measure_wrapped(text, area.width);
+        let mut y = match align.v {
+            VAlign::Top => area.y,
+            VAlign::Middle => area.y + area.height.saturating_sub(total_height) / 2,
+            VAlign::Bottom => area.y + area.height.saturating_sub(total_height),
+        };
+        let mut drawn = Rect::default();
+        for line in wrap(text, area.width) {
+            if y >= clip.bottom() {
+                break;
+            }
+            let width = width_of(line.chars().count());
+            let x = match align.h {
+                HAlign::Left => area.x,
+                HAlign::Center => area.x + area.width.saturating_sub(width) / 2,
+                HAlign::Right => area.x + area.width.saturating_sub(width),
+            };
+            for (i, ch) in line.chars().enumerate() {
+                self.draw_char_clipped(x + i * CHAR_ADVANCE, y, ch, color, &clip);
+            }
+            drawn = drawn.union(&Rect::new(x, y, width, GLYPH_HEIGHT).intersect(&clip));
+            y += LINE_HEIGHT;
+        }
+        drawn
+    }
+
+    /// `clip` の内側だけに 1 文字描く
+    pub fn draw_char_clipped(&mut self, x: usize, y: usize, ch: char, color: u32, clip: &Rect) {
+        if let Some(bitmap) = super::font_for(ch) {
+            for (dy, line) in bitmap.iter().enumerate() {
+                for dx in 0..GLYPH_WIDTH {
+                    if (line >> (7 - dx)) & 0x01 != 0 && clip.contains(x + dx, y + dy) {
+                        self.draw_pixel(x + dx, y + dy, color);
+                    }
+                }
+            }
+        }
+    }
+
+    /// `area` の左上から順に書く `fmt::Write` (右端で折り返し、下端を越えた分は捨てる)
+    pub fn text_writer<'f>(&'f mut self, area: Rect, color: u32) -> TextWriter<'f, 'a> {
+        TextWriter { x: area.x, y: area.y, fb: self, area, color }
+    }
+}
+
+/// `FrameBuffer::text_writer` の結果
+pub struct TextWriter<'f, 'a> {
+    fb: &'f mut FrameBuffer<'a>,
+    area: Rect,
+    x: usize,
+    y: usize,
+    color: u32,
+}
+
+impl TextWriter<'_, '_> {
+    /// 次の文字を描く位置
+    pub fn position(&self) -> (usize, usize) {
+        (self.x, self.y)
+    }
+
+    pub fn set_color(&mut self, color: u32) {
+        self.color = color;
+    }
+
+    fn newline(&mut self) {
+        self.x = self.area.x;
+        self.y += LINE_HEIGHT;
+    }
+}
+
+impl fmt::Write for TextWriter<'_, '_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let clip = self.area.intersect(&self.fb.bounds());
+        for ch in s.chars() {
+            if ch == '\n' {
+                self.newline();
+                continue;
+            }
+            if self.x + GLYPH_WIDTH > self.area.right() && self.x > self.area.x {
+                self.newline();
+            }
+            if self.y >= clip.bottom() {
+                break;
+            }
+            self.fb.draw_char_clipped(self.x, self.y, ch, self.color, &clip);
+            self.x += CHAR_ADVANCE;
+        }
+        Ok(())
+    }
+}
+
+/// 固定長の整形用バッファ (入り切らない分は文字単位で捨てる)
+pub struct FmtBuffer<const N: usize> {
+    bytes: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> FmtBuffer<N> {
+    pub const fn new() -> Self {
+        Self { bytes: [0; N], len: 0 }
+    }
+
+    pub fn as_str(&self) -> &str {
+        // write_str で文字の途中では切らない
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+    }
+}
+
+impl<const N: usize> Default for FmtBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> fmt::Write for FmtBuffer<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for ch in s.chars() {
+            let len = ch.len_utf8();
+            if self.len + len > N {
+                break;
+            }
+            ch.encode_utf8(&mut self.bytes[self.len..self.len + len]);
+            self.len += len;
+        }
+        Ok(())
+    }
+}
+
+/// `fb_print!` で整形できる最大バイト数
+pub const FB_PRINT_CAPACITY: usize = 256;
+
+/// `FrameBuffer` の矩形の中に書式付きで描く (描いた範囲を返す)
+///
+/// `fb_print!(fb, area, Align::CENTER, COLOR_WHITE, "{} UTC", now)`
+#[macro_export]
+macro_rules! fb_print {
+    ($fb:expr, $area:expr, $align:expr, $color:expr, $($arg:tt)*) => {{
+        let mut buf = $crate::graphics::text::FmtBuffer::<{ $crate::graphics::text::FB_PRINT_CAPACITY }>::new();
+        let _ = core::fmt::Write::write_fmt(&mut buf, format_args!($($arg)*));
+        $fb.draw_text_in($area, buf.as_str(), $align, $color)
+    }};
+}
@@ -0,0 +1,184 @@
+#![allow(dead_code)]
+//! 実行中のカーネルイメージ (PE/COFF) の情報
+//!
+//! - UEFI がロードした自分自身のイメージヘッダを `__ImageBase` から読み取り、
+//!   ロードアドレス・リンク時の優先ベース・セクション一覧を提供する。
+//! - セクション属性はページ保護 (NX / 読み取り専用) の設定に使う。
+//! - ベース再配置テーブル (.reloc) を使って、イメージを別のアドレスへ複製できる (KASLR 用)。
+
+const IMAGE_SCN_MEM_EXECUTE: u32 = 0x2000_0000;
+const IMAGE_SCN_MEM_WRITE: u32 = 0x8000_0000;
+/// データディレクトリ中のベース再配置テーブルの番号
+const IMAGE_DIRECTORY_ENTRY_BASERELOC: u32 = 5;
+/// 再配置エントリの種類 (上位 4bit)
+const IMAGE_REL_BASED_ABSOLUTE: u16 = 0;
+const IMAGE_REL_BASED_DIR64: u16 = 10;
+
+extern "C" {
+    /// リンカ (lld-link) が定義するイメージ先頭 (DOS ヘッダ) のシンボル
+    static __ImageBase: u8;
+}
+
+/// セクションのアクセス属性
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SectionKind {
+    /// 実行可能 (RX)
+    Text,
+    /// 読み取り専用 (R)
+    ReadOnly,
+    /// 読み書き可能 (RW)
+    Data,
+}
+
+/// イメージ内の 1 セクション
+#[derive(Clone, Copy, Debug)]
+pub struct Section {
+    pub name: [u8; 8],
+    /// ロード後の先頭アドレス
+    pub start: u64,
+    /// メモリ上のサイズ
+    pub size: u64,
+    pub kind: SectionKind,
+}
+
+impl Section {
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|&b| b == 0).unwrap_or(8);
+        core::str::from_utf8(&self.name[..len]).unwrap_or("?")
+    }
+}
+
+/// ロード済みカーネルイメージ
+#[derive(Clone, Copy, Debug)]
+pub struct KernelImage {
+    /// 実際のロードアドレス
+    pub base: u64,
+    /// リンク時に想定したロードアドレス (PE の ImageBase)
+    pub preferred_base: u64,
+    /// メモリ上のイメージサイズ (SizeOfImage)
+    pub size: u64,
+    section_table: u64,
+    section_count: usize,
+    /// ベース再配置テーブルの (RVA, サイズ)。無ければサイズ 0
+    reloc: (u64, u64),
+}
+
+impl KernelImage {
+    /// 自分自身のヘッダを読み取る
+    pub fn current() -> Self {
+        // Safety: __ImageBase はロード済みイメージの先頭で、ヘッダはイメージ内に常駐している
+        unsafe {
+            let base = &__ImageBase as *const u8 as u64;
+            let pe = base + read::<u32>(base + 0x3C) as u64;
+            let section_count = read::<u16>(pe + 6) as usize;
+            let optional_size = read::<u16>(pe + 20) as u64;
+            let optional = pe + 24;
+            // PE32+ のデータディレクトリはオプションヘッダの 112 バイト目から 8 バイトずつ並ぶ
+            let directories = read::<u32>(optional + 108);
+            let reloc = if directories > IMAGE_DIRECTORY_ENTRY_BASERELOC {
+                let entry = optional + 112 + IMAGE_DIRECTORY_ENTRY_BASERELOC as u64 * 8;
+                (read::<u32>(entry) as u64, read::<u32>(entry + 4) as u64)
+            } else {
+                (0, 0)
+            };
+            Self {
+                base,
+                preferred_base: read::<u64>(optional + 24),
+                size: read::<u32>(optional + 56) as u64,
+                section_table: optional + optional_size,
+                section_count,
+                reloc,
+            }
+        }
+    }
+
+    pub fn end(&self) -> u64 {
+        self.base + self.size
+    }
+
+    /// リンク時アドレスからのずれ (ロードアドレス - 優先ベース)
+    pub fn slide(&self) -> i64 {
+        self.base.wrapping_sub(self.preferred_base) as i64
+    }
+
+    pub fn sections(&self) -> impl Iterator<Item = Section> + '_ {
+        (0..self.section_count).map(move |i| {
+            let hdr = self.section_table + (i as u64) * 40;
+            // Safety: セクションテーブルは current() で求めたヘッダ内を指す
+            unsafe {
+                let name = read::<[u8; 8]>(hdr);
+                let size = read::<u32>(hdr + 8) as u64;
+                let rva = read::<u32>(hdr + 12) as u64;
+                let flags = read::<u32>(hdr + 36);
+                let kind = if flags & IMAGE_SCN_MEM_EXECUTE != 0 {
+                    SectionKind::Text
+                } else if flags & IMAGE_SCN_MEM_WRITE != 0 {
+                    SectionKind::Data
+                } else {
+                    SectionKind::ReadOnly
+                };
+                Section { name, start: self.base + rva, size, kind }
+            }
+        })
+    }
+
+    /// `addr` を含むセクションの属性 (ヘッダなどセクション外は `None`)
+    pub fn kind_of(&self, addr: u64) -> Option<SectionKind> {
+        self.sections()
+            .find(|s| (s.start..s.start + s.size).contains(&addr))
+            .map(|s| s.kind)
+    }
+
+    /// イメージ全体を `dest` へ複製し、ベース再配置を適用する
+    ///
+    /// 複製後のイメージはそのまま `dest` で実行できる。失敗したときは複製先の内容は不定。
+    ///
+    /// # Safety
+    ///
+    /// - `dest` から `size` バイトが書き込み可能で、このイメージと重ならないこと。
+    /// - 呼び出し時点のメモリ内容 (static の値を含む) をそのまま写すので、
+    ///   複製を実行するならそれまでに static を書き換えていないこと。
+    pub unsafe fn copy_to(&self, dest: u64) -> Result<(), &'static str> {
+        let (reloc_rva, reloc_size) = self.reloc;
+        if reloc_size == 0 {
+            return Err("image has no base relocations");
+        }
+        if reloc_rva + reloc_size > self.size {
+            return Err("base relocations are outside the image");
+        }
+        core::ptr::copy_nonoverlapping(self.base as *const u8, dest as *mut u8, self.size as usize);
+
+        // 各ブロックは (ページ RVA, ブロックサイズ) の後に 16bit のエントリが並ぶ
+        let delta = dest.wrapping_sub(self.base);
+        let mut block = dest + reloc_rva;
+        let end = block + reloc_size;
+        while block + 8 <= end {
+            let page = read::<u32>(block) as u64;
+            let block_size = read::<u32>(block + 4) as u64;
+            if block_size < 8 || block + block_size > end {
+                return Err("malformed base relocation block");
+            }
+            for i in 0..(block_size - 8) / 2 {
+                let entry = read::<u16>(block + 8 + i * 2);
+                match entry >> 12 {
+                    IMAGE_REL_BASED_ABSOLUTE => {}
+                    IMAGE_REL_BASED_DIR64 => {
+                        let rva = page + (entry & 0xFFF) as u64;
+                        if rva + 8 > self.size {
+                            return Err("base relocation is outside the image");
+                        }
+                        let target = (dest + rva) as *mut u64;
+                        target.write_unaligned(target.read_unaligned().wrapping_add(delta));
+                    }
+                    _ => return Err("unsupported base relocation type"),
+                }
+            }
+            block += block_size;
+        }
+        Ok(())
+    }
+}
+
+unsafe fn read<T: Copy>(addr: u64) -> T {
+    (addr as *const T).read_unaligned()
+}
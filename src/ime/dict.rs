@@ -0,0 +1,97 @@
+//! かな漢字変換辞書
+//!
+//! - ESP 上の `\EFI\BOOT\IME.DIC` を ExitBootServices 前に読み込む。
+//! - 形式は SKK 辞書 (UTF-8): `よみ /候補1/候補2;注釈/` を 1 行に 1 つ、`;` で始まる行はコメント。
+//!   送りがな付きの見出し (末尾がアルファベット) は使わない。
+//! - 配布されている SKK-JISYO.L などは EUC-JP なので、UTF-8 に変換してから置くこと
+//!   (例: `iconv -f euc-jp -t utf-8 SKK-JISYO.L > SKK-JISYO.utf8`)。UTF-8 でなければ読み込みに失敗する。
+//! - 読み込み時に UTF-8 の検証と、見出し行の先頭オフセットをよみ順に並べた索引の作成を 1 度だけ行い、
+//!   引くときは索引を二分探索する (数 MB の辞書でもキー入力ごとの走査はしない)。
+//!   辞書ファイル自体の並び順には依存しない。
+
+use alloc::vec::Vec;
+use spin::Once;
+use crate::efi::{self, EfiHandle, EfiSystemTable};
+
+/// ESP 上の辞書のパス
+const DICTIONARY_FILE: &str = "\\EFI\\BOOT\\IME.DIC";
+
+struct Dictionary {
+    text: &'static str,
+    /// 見出し行の先頭オフセット (よみ順、同じよみは辞書の順)
+    index: &'static [u32],
+}
+
+static DICTIONARY: Once<Dictionary> = Once::new();
+
+/// ESP から辞書を読み込み、見出しの数を返す (ExitBootServices 前に呼ぶこと)
+///
+/// 見つからなければ変換候補はひらがなとカタカナだけになる。
+pub fn load(image_handle: EfiHandle, system_table: &EfiSystemTable) -> efi::Result<usize> {
+    let data = efi::read_file(image_handle, system_table, DICTIONARY_FILE)?;
+    let text = core::str::from_utf8(data).map_err(|_| "dictionary is not UTF-8 (convert EUC-JP dictionaries first)")?;
+    if u32::try_from(text.len()).is_err() {
+        return Err("dictionary too large");
+    }
+
+    // ヒープはまだ無いので、索引は EfiLoaderData に置く
+    // Safety: u32 は 0 が有効な値
+    let index = unsafe { efi::allocate_zeroed::<u32>(system_table, entry_offsets(text).count())? };
+    for (slot, offset) in index.iter_mut().zip(entry_offsets(text)) {
+        *slot = offset as u32;
+    }
+    index.sort_unstable_by(|&a, &b| reading_at(text, a).cmp(reading_at(text, b)).then(a.cmp(&b)));
+
+    let dictionary = DICTIONARY.call_once(|| Dictionary { text, index });
+    Ok(dictionary.index.len())
+}
+
+/// 使う見出し行 (コメントと送りがな付きを除く) の先頭オフセット
+fn entry_offsets(text: &str) -> impl Iterator<Item = usize> + '_ {
+    let base = text.as_ptr() as usize;
+    text.lines()
+        .filter(|line| !line.starts_with(';'))
+        .filter(|line| {
+            line.split_once(" /")
+                .is_some_and(|(reading, _)| !reading.ends_with(|c: char| c.is_ascii_alphabetic()))
+        })
+        .map(move |line| line.as_ptr() as usize - base)
+}
+
+/// `offset` から始まる行の (よみ, 候補の並び)
+fn entry_at(text: &str, offset: u32) -> (&str, &str) {
+    let rest = &text[offset as usize..];
+    let line = rest.lines().next().unwrap_or("");
+    line.split_once(" /").unwrap_or((line, ""))
+}
+
+fn reading_at(text: &str, offset: u32) -> &str {
+    entry_at(text, offset).0
+}
+
+/// `reading` の変換候補 (辞書の順)
+pub fn lookup(reading: &str) -> Vec<&'static str> {
+    let mut candidates = Vec::new();
+    let Some(dictionary) = DICTIONARY.get() else { return candidates };
+    let text = dictionary.text;
+    let first = dictionary.index.partition_point(|&offset| reading_at(text, offset) < reading);
+    for &offset in &dictionary.index[first..] {
+        let (r, list) = entry_at(text, offset);
+        if r != reading {
+            break;
+        }
+        for candidate in list.split('/') {
+            // 注釈を除く
+            let candidate = candidate.split(';').next().unwrap_or("");
+            if !candidate.is_empty() && !candidates.contains(&candidate) {
+                candidates.push(candidate);
+            }
+        }
+    }
+    candidates
+}
+
+/// 辞書を読み込んでいるか
+pub fn is_loaded() -> bool {
+    DICTIONARY.get().is_some()
+}
@@ -0,0 +1,245 @@
+#![allow(dead_code)]
+//! 日本語入力 (IME)
+//!
+//! - キーイベントを受け取り、ローマ字をひらがな (カタカナ入力モードではカタカナ) に変換して
+//!   未確定文字列 (プリエディット) を組み立てる。
+//! - 半角/全角キー (JIS 配列の `Grave` の位置、US 配列では Alt+`) で直接入力と切り替え、
+//!   カタカナ/ひらがなキーでひらがな入力 (Shift 付きでカタカナ入力) にする。
+//! - スペース・変換キーで辞書 (`dict`) を引いて候補を選ぶ。候補の末尾には常にひらがなとカタカナを加える。
+//!   数字キーで表示中の候補を直接選べる。F6 / F7 でひらがな・カタカナのまま確定する。
+//! - 確定した文字列と、IME が処理しなかったキーイベントを呼び出し側へ返す。
+
+pub mod dict;
+pub mod romaji;
+pub mod window;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::drivers::keyboard::{self, layout::Layout, KeyCode, KeyEvent};
+use romaji::Romaji;
+
+/// 候補ウィンドウの 1 ページの候補数 (数字キーで選べる数)
+pub const PAGE_SIZE: usize = 9;
+
+/// 入力モード
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// 半角英数 (IME オフ)
+    Direct,
+    Hiragana,
+    Katakana,
+}
+
+/// `Ime::process` の結果
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Output {
+    /// 確定した文字列
+    Commit(String),
+    /// IME が処理しなかったキー (そのまま使う)
+    Key(KeyEvent),
+}
+
+/// 変換中の候補
+struct Conversion {
+    candidates: Vec<String>,
+    index: usize,
+}
+
+pub struct Ime {
+    mode: Mode,
+    romaji: Romaji,
+    /// 確定前のかな (常にひらがなで持ち、表示・確定時にモードに合わせる)
+    kana: String,
+    conversion: Option<Conversion>,
+}
+
+impl Default for Ime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ime {
+    pub const fn new() -> Self {
+        Self {
+            mode: Mode::Direct,
+            romaji: Romaji::new(),
+            kana: String::new(),
+            conversion: None,
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// 未確定の文字列があるか
+    pub fn is_composing(&self) -> bool {
+        !self.kana.is_empty() || !self.romaji.is_empty()
+    }
+
+    /// 表示用の未確定文字列 (変換中は選択中の候補)
+    pub fn preedit(&self) -> String {
+        if let Some(conversion) = &self.conversion {
+            return conversion.candidates[conversion.index].clone();
+        }
+        let mut text = self.kana_for_mode();
+        text.push_str(self.romaji.pending());
+        text
+    }
+
+    /// 変換中なら候補と選択中の位置
+    pub fn candidates(&self) -> Option<(&[String], usize)> {
+        self.conversion.as_ref().map(|c| (c.candidates.as_slice(), c.index))
+    }
+
+    /// キーイベントを 1 つ処理する
+    pub fn process(&mut self, event: &KeyEvent) -> Option<Output> {
+        if !event.pressed {
+            return if self.is_composing() { None } else { Some(Output::Key(*event)) };
+        }
+        if is_toggle(event) {
+            let committed = self.commit_all();
+            self.mode = if self.mode == Mode::Direct { Mode::Hiragana } else { Mode::Direct };
+            return committed;
+        }
+        if event.code == KeyCode::KatakanaHiragana {
+            let committed = self.commit_all();
+            self.mode = if event.modifiers.shift { Mode::Katakana } else { Mode::Hiragana };
+            return committed;
+        }
+        if self.mode == Mode::Direct {
+            return Some(Output::Key(*event));
+        }
+        if self.conversion.is_some() {
+            return self.process_converting(event);
+        }
+        if self.is_composing() {
+            return self.process_composing(event);
+        }
+        // 未入力: 文字なら入力を始め、それ以外はそのまま返す
+        match event.ch {
+            Some(c) if is_input_char(c) && !has_command_modifier(event) => {
+                self.romaji.push(c, &mut self.kana);
+                None
+            }
+            _ => Some(Output::Key(*event)),
+        }
+    }
+
+    fn process_composing(&mut self, event: &KeyEvent) -> Option<Output> {
+        match event.code {
+            KeyCode::Space | KeyCode::Henkan => self.start_conversion(),
+            KeyCode::Enter | KeyCode::KeypadEnter => return self.commit_all(),
+            KeyCode::Escape => self.reset(),
+            KeyCode::Backspace => {
+                if !self.romaji.backspace() {
+                    self.kana.pop();
+                }
+            }
+            KeyCode::F6 => return self.commit_kana(Mode::Hiragana),
+            KeyCode::F7 => return self.commit_kana(Mode::Katakana),
+            _ => match event.ch {
+                Some(c) if is_input_char(c) && !has_command_modifier(event) => self.romaji.push(c, &mut self.kana),
+                // 入力中はその他のキーを無視する
+                _ => {}
+            },
+        }
+        None
+    }
+
+    fn process_converting(&mut self, event: &KeyEvent) -> Option<Output> {
+        let conversion = self.conversion.as_mut()?;
+        let count = conversion.candidates.len();
+        match event.code {
+            KeyCode::Space if event.modifiers.shift => conversion.index = (conversion.index + count - 1) % count,
+            KeyCode::Space | KeyCode::Henkan | KeyCode::Down => conversion.index = (conversion.index + 1) % count,
+            KeyCode::Up => conversion.index = (conversion.index + count - 1) % count,
+            KeyCode::Enter | KeyCode::KeypadEnter => return self.commit_all(),
+            // 変換を取り消してかなの入力に戻る
+            KeyCode::Escape | KeyCode::Backspace => self.conversion = None,
+            _ => match event.ch {
+                Some(c @ '1'..='9') => {
+                    let page = conversion.index / PAGE_SIZE * PAGE_SIZE;
+                    let index = page + (c as usize - '1' as usize);
+                    if index < count {
+                        conversion.index = index;
+                        return self.commit_all();
+                    }
+                }
+                // 次の入力が始まったら選択中の候補を確定する
+                Some(c) if is_input_char(c) && !has_command_modifier(event) => {
+                    let committed = self.commit_all();
+                    self.romaji.push(c, &mut self.kana);
+                    return committed;
+                }
+                _ => {}
+            },
+        }
+        None
+    }
+
+    fn start_conversion(&mut self) {
+        self.romaji.flush(&mut self.kana);
+        if self.kana.is_empty() {
+            return;
+        }
+        let mut candidates: Vec<String> = dict::lookup(&self.kana).into_iter().map(String::from).collect();
+        for fallback in [self.kana.clone(), romaji::to_katakana(&self.kana)] {
+            if !candidates.contains(&fallback) {
+                candidates.push(fallback);
+            }
+        }
+        self.conversion = Some(Conversion { candidates, index: 0 });
+    }
+
+    /// 未確定の文字列 (変換中は選択中の候補) を確定する
+    fn commit_all(&mut self) -> Option<Output> {
+        self.romaji.flush(&mut self.kana);
+        let text = match self.conversion.take() {
+            Some(mut conversion) => conversion.candidates.swap_remove(conversion.index),
+            None => self.kana_for_mode(),
+        };
+        self.reset();
+        (!text.is_empty()).then_some(Output::Commit(text))
+    }
+
+    /// 変換せずにひらがな・カタカナで確定する
+    fn commit_kana(&mut self, mode: Mode) -> Option<Output> {
+        self.romaji.flush(&mut self.kana);
+        let text = match mode {
+            Mode::Katakana => romaji::to_katakana(&self.kana),
+            _ => self.kana.clone(),
+        };
+        self.reset();
+        (!text.is_empty()).then_some(Output::Commit(text))
+    }
+
+    fn kana_for_mode(&self) -> String {
+        match self.mode {
+            Mode::Katakana => romaji::to_katakana(&self.kana),
+            _ => self.kana.clone(),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.romaji.clear();
+        self.kana.clear();
+        self.conversion = None;
+    }
+}
+
+/// 半角/全角キー
+fn is_toggle(event: &KeyEvent) -> bool {
+    event.code == KeyCode::Grave && (event.modifiers.alt || keyboard::layout() == Some(Layout::Jis))
+}
+
+/// ローマ字入力に使う文字 (制御文字・空白以外)
+fn is_input_char(c: char) -> bool {
+    c.is_ascii_graphic()
+}
+
+/// Ctrl などのショートカット用の修飾キーが押されている
+fn has_command_modifier(event: &KeyEvent) -> bool {
+    event.modifiers.ctrl || event.modifiers.alt || event.modifiers.gui
+}
@@ -0,0 +1,143 @@
+//! ローマ字からひらがなへの変換
+//!
+//! - 入力中のアルファベットが表の見出しの接頭辞である間は保留し、一致した時点でかなを出す。
+//! - 同じ子音の連続 (`tt` など) は「っ」、`n` の後に母音・`y`・`n` 以外が来たら「ん」にする。
+//! - 表に当てはまらない文字はそのまま出す。
+
+use alloc::string::String;
+
+/// (ローマ字, かな)
+const TABLE: &[(&str, &str)] = &[
+    ("a", "あ"), ("i", "い"), ("u", "う"), ("e", "え"), ("o", "お"),
+    ("ka", "か"), ("ki", "き"), ("ku", "く"), ("ke", "け"), ("ko", "こ"),
+    ("sa", "さ"), ("si", "し"), ("shi", "し"), ("su", "す"), ("se", "せ"), ("so", "そ"),
+    ("ta", "た"), ("ti", "ち"), ("chi", "ち"), ("tu", "つ"), ("tsu", "つ"), ("te", "て"), ("to", "と"),
+    ("na", "な"), ("ni", "に"), ("nu", "ぬ"), ("ne", "ね"), ("no", "の"),
+    ("ha", "は"), ("hi", "ひ"), ("hu", "ふ"), ("fu", "ふ"), ("he", "へ"), ("ho", "ほ"),
+    ("ma", "ま"), ("mi", "み"), ("mu", "む"), ("me", "め"), ("mo", "も"),
+    ("ya", "や"), ("yu", "ゆ"), ("yo", "よ"),
+    ("ra", "ら"), ("ri", "り"), ("ru", "る"), ("re", "れ"), ("ro", "ろ"),
+    ("wa", "わ"), ("wi", "うぃ"), ("we", "うぇ"), ("wo", "を"),
+    ("nn", "ん"), ("n'", "ん"), ("xn", "ん"),
+    ("ga", "が"), ("gi", "ぎ"), ("gu", "ぐ"), ("ge", "げ"), ("go", "ご"),
+    ("za", "ざ"), ("zi", "じ"), ("ji", "じ"), ("zu", "ず"), ("ze", "ぜ"), ("zo", "ぞ"),
+    ("da", "だ"), ("di", "ぢ"), ("du", "づ"), ("de", "で"), ("do", "ど"),
+    ("ba", "ば"), ("bi", "び"), ("bu", "ぶ"), ("be", "べ"), ("bo", "ぼ"),
+    ("pa", "ぱ"), ("pi", "ぴ"), ("pu", "ぷ"), ("pe", "ぺ"), ("po", "ぽ"),
+    ("va", "ゔぁ"), ("vi", "ゔぃ"), ("vu", "ゔ"), ("ve", "ゔぇ"), ("vo", "ゔぉ"),
+    ("kya", "きゃ"), ("kyu", "きゅ"), ("kyo", "きょ"),
+    ("sya", "しゃ"), ("syu", "しゅ"), ("syo", "しょ"),
+    ("sha", "しゃ"), ("shu", "しゅ"), ("she", "しぇ"), ("sho", "しょ"),
+    ("tya", "ちゃ"), ("tyu", "ちゅ"), ("tyo", "ちょ"),
+    ("cha", "ちゃ"), ("chu", "ちゅ"), ("che", "ちぇ"), ("cho", "ちょ"),
+    ("nya", "にゃ"), ("nyu", "にゅ"), ("nyo", "にょ"),
+    ("hya", "ひゃ"), ("hyu", "ひゅ"), ("hyo", "ひょ"),
+    ("mya", "みゃ"), ("myu", "みゅ"), ("myo", "みょ"),
+    ("rya", "りゃ"), ("ryu", "りゅ"), ("ryo", "りょ"),
+    ("gya", "ぎゃ"), ("gyu", "ぎゅ"), ("gyo", "ぎょ"),
+    ("zya", "じゃ"), ("zyu", "じゅ"), ("zyo", "じょ"),
+    ("ja", "じゃ"), ("ju", "じゅ"), ("je", "じぇ"), ("jo", "じょ"),
+    ("jya", "じゃ"), ("jyu", "じゅ"), ("jyo", "じょ"),
+    ("dya", "ぢゃ"), ("dyu", "ぢゅ"), ("dyo", "ぢょ"),
+    ("bya", "びゃ"), ("byu", "びゅ"), ("byo", "びょ"),
+    ("pya", "ぴゃ"), ("pyu", "ぴゅ"), ("pyo", "ぴょ"),
+    ("fa", "ふぁ"), ("fi", "ふぃ"), ("fe", "ふぇ"), ("fo", "ふぉ"),
+    ("thi", "てぃ"), ("dhi", "でぃ"), ("twu", "とぅ"), ("dwu", "どぅ"),
+    ("xa", "ぁ"), ("xi", "ぃ"), ("xu", "ぅ"), ("xe", "ぇ"), ("xo", "ぉ"),
+    ("la", "ぁ"), ("li", "ぃ"), ("lu", "ぅ"), ("le", "ぇ"), ("lo", "ぉ"),
+    ("xya", "ゃ"), ("xyu", "ゅ"), ("xyo", "ょ"),
+    ("lya", "ゃ"), ("lyu", "ゅ"), ("lyo", "ょ"),
+    ("xtu", "っ"), ("xtsu", "っ"), ("ltu", "っ"), ("xwa", "ゎ"),
+    ("-", "ー"), (",", "、"), (".", "。"), ("[", "「"), ("]", "」"),
+    ("~", "〜"), ("/", "・"),
+];
+
+/// 入力途中のローマ字
+#[derive(Default)]
+pub struct Romaji {
+    pending: String,
+}
+
+impl Romaji {
+    pub const fn new() -> Self {
+        Self { pending: String::new() }
+    }
+
+    /// 保留中のローマ字
+    pub fn pending(&self) -> &str {
+        &self.pending
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// 保留中の最後の 1 文字を消す。消せたら `true`
+    pub fn backspace(&mut self) -> bool {
+        self.pending.pop().is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    /// 1 文字加え、確定したかなを `out` に追加する
+    pub fn push(&mut self, c: char, out: &mut String) {
+        self.pending.push(c.to_ascii_lowercase());
+        self.resolve(out, false);
+    }
+
+    /// 保留中の文字を確定させる (`n` は「ん」、それ以外はそのまま)
+    pub fn flush(&mut self, out: &mut String) {
+        self.resolve(out, true);
+        if self.pending == "n" {
+            out.push('ん');
+        } else {
+            out.push_str(&self.pending);
+        }
+        self.pending.clear();
+    }
+
+    fn resolve(&mut self, out: &mut String, flushing: bool) {
+        while !self.pending.is_empty() {
+            if let Some(&(_, kana)) = TABLE.iter().find(|(romaji, _)| *romaji == self.pending) {
+                out.push_str(kana);
+                self.pending.clear();
+                return;
+            }
+            let is_prefix = TABLE.iter().any(|(romaji, _)| romaji.starts_with(self.pending.as_str()));
+            if is_prefix && !flushing {
+                return;
+            }
+            let mut chars = self.pending.chars();
+            let first = chars.next().unwrap_or_default();
+            let second = chars.next();
+            if first == 'n' && second.is_some_and(|c| !matches!(c, 'a' | 'i' | 'u' | 'e' | 'o' | 'y' | 'n')) {
+                out.push('ん');
+            } else if second == Some(first) && first.is_ascii_alphabetic() && !is_vowel(first) {
+                out.push('っ');
+            } else if flushing && first == 'n' && second.is_none() {
+                // flush で「ん」にする
+                return;
+            } else {
+                out.push(first);
+            }
+            self.pending.remove(0);
+        }
+    }
+}
+
+fn is_vowel(c: char) -> bool {
+    matches!(c, 'a' | 'i' | 'u' | 'e' | 'o')
+}
+
+/// ひらがなをカタカナにする (それ以外の文字はそのまま)
+pub fn to_katakana(hiragana: &str) -> String {
+    hiragana
+        .chars()
+        .map(|c| match c {
+            'ぁ'..='ゖ' => char::from_u32(c as u32 + 0x60).unwrap_or(c),
+            _ => c,
+        })
+        .collect()
+}
@@ -0,0 +1,70 @@
+//! 未確定文字列と変換候補の表示
+//!
+//! - 未確定文字列を下線付きで描き、変換中はその下に候補ウィンドウ (番号付き、1 ページ `PAGE_SIZE` 個) を開く。
+//! - 前回描いた範囲を背景色で消してから描き直す。
+//! - かな・漢字の字形は ESP から読み込んだ追加フォント (`font::extra`) で描く。
+//!   フォントが無い・収録されていない文字は空白になるので、文字列そのものは呼び出し側がシリアルなどにも出すこと。
+
+use crate::graphics::{FrameBuffer, COLOR_BLACK, COLOR_BLUE, COLOR_WHITE};
+use crate::graphics::text::CHAR_ADVANCE;
+use super::{Ime, PAGE_SIZE};
+
+/// 候補 1 行の高さ (下線・選択色の分だけ通常の行より広い)
+const LINE_HEIGHT: usize = 12;
+const PADDING: usize = 4;
+
+pub struct CandidateWindow {
+    x: usize,
+    y: usize,
+    background: u32,
+    /// 前回描いた範囲 (幅, 高さ)
+    drawn: Option<(usize, usize)>,
+}
+
+impl CandidateWindow {
+    /// `(x, y)` に未確定文字列を表示するウィンドウ
+    pub const fn new(x: usize, y: usize, background: u32) -> Self {
+        Self { x, y, background, drawn: None }
+    }
+
+    /// IME の状態に合わせて描き直す
+    pub fn update(&mut self, fb: &mut FrameBuffer, ime: &Ime) {
+        if let Some((w, h)) = self.drawn.take() {
+            fb.fill_rect(self.x, self.y, w, h, self.background);
+        }
+        if !ime.is_composing() {
+            return;
+        }
+        let preedit = ime.preedit();
+        let preedit_width = preedit.chars().count() * CHAR_ADVANCE;
+        fb.draw_text(self.x, self.y, &preedit, COLOR_BLACK);
+        fb.fill_rect(self.x, self.y + 9, preedit_width, 1, COLOR_BLACK);
+        let (mut width, mut height) = (preedit_width, LINE_HEIGHT);
+
+        if let Some((candidates, index)) = ime.candidates() {
+            let page = index / PAGE_SIZE * PAGE_SIZE;
+            let shown = &candidates[page..candidates.len().min(page + PAGE_SIZE)];
+            // "1 " の 2 文字分 + 最長の候補
+            let longest = shown.iter().map(|c| c.chars().count()).max().unwrap_or(0);
+            let box_w = (longest + 2) * CHAR_ADVANCE + PADDING * 2;
+            let box_h = shown.len() * LINE_HEIGHT + PADDING * 2;
+            let (bx, by) = (self.x, self.y + LINE_HEIGHT);
+            fb.fill_rect(bx, by, box_w, box_h, COLOR_WHITE);
+            fb.stroke_rect(bx, by, box_w, box_h, COLOR_BLACK);
+            for (i, candidate) in shown.iter().enumerate() {
+                let (cx, cy) = (bx + PADDING, by + PADDING + i * LINE_HEIGHT);
+                let color = if page + i == index {
+                    fb.fill_rect(cx, cy - 1, box_w - PADDING * 2, LINE_HEIGHT - 2, COLOR_BLUE);
+                    COLOR_WHITE
+                } else {
+                    COLOR_BLACK
+                };
+                fb.draw_char(cx, cy, (b'1' + i as u8) as char, color);
+                fb.draw_text(cx + CHAR_ADVANCE * 2, cy, candidate, color);
+            }
+            width = width.max(box_w);
+            height += box_h;
+        }
+        self.drawn = Some((width, height));
+    }
+}
@@ -0,0 +1,167 @@
+#![allow(dead_code)]
+//! 入力イベント
+//!
+//! - キーボード・マウスなどのドライバは割り込みハンドラから `report` でイベントを送る。
+//! - 受け手 (コンソール・ウィンドウマネージャ・ユーザープロセスなど) は `subscribe` で購読し、
+//!   購読ごとのリングバッファからイベントを取り出す。満杯の購読者の分だけイベントを捨てる。
+//! - `Subscriber::next` は非同期に次のイベントを待つ Future を返す。
+//!   Waker は割り込みハンドラから起こすので、割り込み禁止状態で呼んでも問題ないものを渡すこと。
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+use spin::{Mutex, RwLock};
+use x86_64::instructions::interrupts::without_interrupts;
+use crate::drivers::keyboard::KeyEvent;
+use crate::sync::ring::RingBuffer;
+
+/// 購読者ごとに溜められるイベント数
+const CHANNEL_LEN: usize = 256;
+
+/// マウスのボタン
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+    /// 第 4 ボタン (戻る)
+    Back,
+    /// 第 5 ボタン (進む)
+    Forward,
+}
+
+/// 入力イベント
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputEvent {
+    /// キーの押下・解放 (キーコードと入力された文字)
+    Key(KeyEvent),
+    /// ポインタの相対移動 (右・下が正)
+    Motion { dx: i32, dy: i32 },
+    /// ポインタのボタンの押下・解放
+    Button { button: MouseButton, pressed: bool },
+    /// ホイールの回転 (手前が正)
+    Wheel { delta: i32 },
+}
+
+struct Channel {
+    name: &'static str,
+    events: RingBuffer<InputEvent, CHANNEL_LEN>,
+    dropped: AtomicU64,
+    /// 空のときに待っているタスク
+    waker: Mutex<Option<Waker>>,
+}
+
+static SUBSCRIBERS: RwLock<Vec<Arc<Channel>>> = RwLock::new(Vec::new());
+/// 購読者一覧の更新中に届いて配れなかったイベントの数
+static LOST: AtomicU64 = AtomicU64::new(0);
+
+/// イベントを全ての購読者へ送る (割り込みハンドラから呼べる)
+pub fn report(event: InputEvent) {
+    // 購読者一覧の更新は割り込みを止めて行うので、ここで取れないことは無い
+    let Some(subscribers) = SUBSCRIBERS.try_read() else {
+        LOST.fetch_add(1, Ordering::Relaxed);
+        return;
+    };
+    for channel in subscribers.iter() {
+        if channel.events.push(event).is_err() {
+            channel.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some(waker) = channel.waker.try_lock().and_then(|mut waker| waker.take()) {
+            waker.wake();
+        }
+    }
+}
+
+/// 購読を始める (以後のイベントを受け取る)
+pub fn subscribe(name: &'static str) -> Subscriber {
+    let channel = Arc::new(Channel {
+        name,
+        events: RingBuffer::new(),
+        dropped: AtomicU64::new(0),
+        waker: Mutex::new(None),
+    });
+    without_interrupts(|| SUBSCRIBERS.write().push(channel.clone()));
+    Subscriber { channel }
+}
+
+/// 購読者の名前と、溢れて捨てたイベントの数
+pub fn subscribers() -> Vec<(&'static str, u64)> {
+    without_interrupts(|| {
+        SUBSCRIBERS
+            .read()
+            .iter()
+            .map(|channel| (channel.name, channel.dropped.load(Ordering::Relaxed)))
+            .collect()
+    })
+}
+
+/// 購読者一覧の更新中で配れなかったイベントの数
+pub fn lost() -> u64 {
+    LOST.load(Ordering::Relaxed)
+}
+
+/// イベントの購読 (drop すると購読をやめる)
+pub struct Subscriber {
+    channel: Arc<Channel>,
+}
+
+impl Subscriber {
+    /// 溜まっているイベントを 1 つ取り出す
+    pub fn try_next(&self) -> Option<InputEvent> {
+        self.channel.events.pop()
+    }
+
+    /// 溜まっているイベントが無いか
+    pub fn is_empty(&self) -> bool {
+        self.channel.events.is_empty()
+    }
+
+    /// 次のイベントを待つ
+    pub fn next(&self) -> Next<'_> {
+        Next { subscriber: self }
+    }
+
+    /// 次のイベントをポーリングする (空なら Waker を登録して `Pending`)
+    ///
+    /// 購読は終わらないので、ストリームとしては常に次の値がある。
+    pub fn poll_next(&self, cx: &mut Context<'_>) -> Poll<InputEvent> {
+        if let Some(event) = self.try_next() {
+            return Poll::Ready(event);
+        }
+        without_interrupts(|| *self.channel.waker.lock() = Some(cx.waker().clone()));
+        // 登録前に届いたイベントを取りこぼさないよう、もう一度確かめる
+        match self.try_next() {
+            Some(event) => Poll::Ready(event),
+            None => Poll::Pending,
+        }
+    }
+
+    /// 溢れて捨てたイベントの数
+    pub fn dropped(&self) -> u64 {
+        self.channel.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        without_interrupts(|| {
+            SUBSCRIBERS.write().retain(|channel| !Arc::ptr_eq(channel, &self.channel));
+        });
+    }
+}
+
+/// `Subscriber::next` の Future
+pub struct Next<'a> {
+    subscriber: &'a Subscriber,
+}
+
+impl Future for Next<'_> {
+    type Output = InputEvent;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<InputEvent> {
+        self.subscriber.poll_next(cx)
+    }
+}
@@ -1,14 +1,181 @@
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use core::fmt;
+use core::ops::RangeInclusive;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use spin::RwLock;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::registers::control::{Cr0, Cr2, Cr3, Cr4};
+use x86_64::structures::idt::InterruptDescriptorTable;
+use x86_64::VirtAddr;
 use lazy_static::lazy_static;
-use crate::graphics::{self, FrameBuffer, COLOR_YELLOW}; // 仮。fb 取得方法が必要
+use crate::apic::{self, pic};
+use crate::exceptions;
+use crate::gdt::DOUBLE_FAULT_IST_INDEX;
+
+// 全 256 ベクタ共通の入口スタブ
+//
+// - 各スタブは 16 byte 境界に並び、エラーコードを積まない例外ではダミーの 0 を積んで
+//   スタックレイアウトを揃えてから、ベクタ番号を積んで `interrupt_common` に飛ぶ。
+// - `interrupt_common` は汎用レジスタと FPU/SSE 状態 (fxsave) を保存し、
+//   `InterruptContext` へのポインタを引数に `interrupt_dispatch` を呼ぶ。
+core::arch::global_asm!(
+    ".balign 16",
+    "interrupt_stubs:",
+    ".set vector, 0",
+    ".rept 256",
+    "    .balign 16",
+    "    .if (vector == 8) || ((vector >= 10) && (vector <= 14)) || (vector == 17) || (vector == 21) || (vector == 29) || (vector == 30)",
+    "    .else",
+    "    pushq $0",
+    "    .endif",
+    "    pushq $vector",
+    "    jmp interrupt_common",
+    "    .set vector, vector + 1",
+    ".endr",
+    "",
+    "interrupt_common:",
+    "    pushq %rax",
+    "    pushq %rcx",
+    "    pushq %rdx",
+    "    pushq %rbx",
+    "    pushq %rbp",
+    "    pushq %rsi",
+    "    pushq %rdi",
+    "    pushq %r8",
+    "    pushq %r9",
+    "    pushq %r10",
+    "    pushq %r11",
+    "    pushq %r12",
+    "    pushq %r13",
+    "    pushq %r14",
+    "    pushq %r15",
+    "    subq $512, %rsp",
+    "    fxsave64 (%rsp)",
+    "    cld",
+    "    leaq 512(%rsp), %rdi",
+    "    call interrupt_dispatch",
+    "    fxrstor64 (%rsp)",
+    "    addq $512, %rsp",
+    "    popq %r15",
+    "    popq %r14",
+    "    popq %r13",
+    "    popq %r12",
+    "    popq %r11",
+    "    popq %r10",
+    "    popq %r9",
+    "    popq %r8",
+    "    popq %rdi",
+    "    popq %rsi",
+    "    popq %rbp",
+    "    popq %rbx",
+    "    popq %rdx",
+    "    popq %rcx",
+    "    popq %rax",
+    "    addq $16, %rsp",
+    "    iretq",
+    options(att_syntax),
+);
+
+extern "C" {
+    static interrupt_stubs: u8;
+}
+
+/// 各スタブの大きさ (`.balign 16`)
+const STUB_SIZE: u64 = 16;
+
+/// ベクタ `vector` の入口スタブのアドレス
+fn stub_addr(vector: u8) -> VirtAddr {
+    // Safety: interrupt_stubs は global_asm で定義したテキスト上のラベル
+    let base = unsafe { &interrupt_stubs as *const u8 as u64 };
+    VirtAddr::new(base + vector as u64 * STUB_SIZE)
+}
+
+/// 割り込み発生時に保存されたレジスタ (スタック上のレイアウトそのもの)
+#[repr(C)]
+#[derive(Debug)]
+pub struct InterruptContext {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rbp: u64,
+    pub rbx: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rax: u64,
+    pub vector: u64,
+    /// エラーコードを積まない例外・割り込みでは 0
+    pub error_code: u64,
+    // 以下は CPU が積む InterruptStackFrame
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+impl fmt::Display for InterruptContext {
+    /// 例外レポート用のレジスタダンプ
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "RIP={:016x} CS={:04x} RFLAGS={:016x}", self.rip, self.cs, self.rflags)?;
+        writeln!(f, "RSP={:016x} SS={:04x} ERR={:016x}", self.rsp, self.ss, self.error_code)?;
+        writeln!(f, "RAX={:016x} RBX={:016x} RCX={:016x}", self.rax, self.rbx, self.rcx)?;
+        writeln!(f, "RDX={:016x} RSI={:016x} RDI={:016x}", self.rdx, self.rsi, self.rdi)?;
+        writeln!(f, "RBP={:016x} R8 ={:016x} R9 ={:016x}", self.rbp, self.r8, self.r9)?;
+        writeln!(f, "R10={:016x} R11={:016x} R12={:016x}", self.r10, self.r11, self.r12)?;
+        writeln!(f, "R13={:016x} R14={:016x} R15={:016x}", self.r13, self.r14, self.r15)?;
+        writeln!(
+            f,
+            "CR0={:016x} CR2={:016x} CR3={:016x} CR4={:016x}",
+            Cr0::read_raw(),
+            Cr2::read().as_u64(),
+            Cr3::read().0.start_address().as_u64(),
+            Cr4::read_raw()
+        )
+    }
+}
 
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
+        // Safety: 各スタブは interrupt_common を経由して iretq で戻る正しいハンドラ
         unsafe {
+            idt.divide_error.set_handler_addr(stub_addr(0));
+            idt.debug.set_handler_addr(stub_addr(1));
+            idt.non_maskable_interrupt.set_handler_addr(stub_addr(2));
+            idt.breakpoint.set_handler_addr(stub_addr(3));
+            idt.overflow.set_handler_addr(stub_addr(4));
+            idt.bound_range_exceeded.set_handler_addr(stub_addr(5));
+            idt.invalid_opcode.set_handler_addr(stub_addr(6));
+            idt.device_not_available.set_handler_addr(stub_addr(7));
             idt.double_fault
-                .set_handler_fn(double_fault_handler)
-                .set_stack_index(0);
+                .set_handler_addr(stub_addr(8))
+                .set_stack_index(DOUBLE_FAULT_IST_INDEX);
+            idt[9].set_handler_addr(stub_addr(9));
+            idt.invalid_tss.set_handler_addr(stub_addr(10));
+            idt.segment_not_present.set_handler_addr(stub_addr(11));
+            idt.stack_segment_fault.set_handler_addr(stub_addr(12));
+            idt.general_protection_fault.set_handler_addr(stub_addr(13));
+            idt.page_fault.set_handler_addr(stub_addr(14));
+            idt.x87_floating_point.set_handler_addr(stub_addr(16));
+            idt.alignment_check.set_handler_addr(stub_addr(17));
+            idt.machine_check.set_handler_addr(stub_addr(18));
+            idt.simd_floating_point.set_handler_addr(stub_addr(19));
+            idt.virtualization.set_handler_addr(stub_addr(20));
+            idt.cp_protection_exception.set_handler_addr(stub_addr(21));
+            idt.hv_injection_exception.set_handler_addr(stub_addr(28));
+            idt.vmm_communication_exception.set_handler_addr(stub_addr(29));
+            idt.security_exception.set_handler_addr(stub_addr(30));
+            for vector in 32..=255u8 {
+                idt[vector as usize].set_handler_addr(stub_addr(vector));
+            }
         }
         idt
     };
@@ -19,18 +186,134 @@ pub fn init() {
     IDT.load();
 }
 
-extern "x86-interrupt" fn double_fault_handler(
-    _stack_frame: InterruptStackFrame,
-    _error_code: u64,
-) -> ! {
-    // ダブルフォルト発生時に画面を黄色にする (デバッグ用)
-    // Safety: フレームバッファアドレスが既知でアクセス可能と仮定
-    // 本来は main から安全に渡すか、static などで共有する必要あり
-    // const FB_ADDR: usize = 0x????????????; // GOP から取得したアドレス
-    // unsafe {
-    //    if FB_ADDR != 0 {
-    //        (*(FB_ADDR as *mut FrameBuffer)).clear(COLOR_YELLOW);
-    //    }
-    // }
-    loop {}
-} 
\ No newline at end of file
+/// 割り込みハンドラ
+///
+/// `Fn(&InterruptContext)` のクロージャはそのままハンドラとして登録できる。
+/// 状態を持つドライバは構造体に実装して登録する。
+pub trait InterruptHandler: Send + Sync {
+    fn handle(&self, ctx: &InterruptContext);
+}
+
+impl<F: Fn(&InterruptContext) + Send + Sync> InterruptHandler for F {
+    fn handle(&self, ctx: &InterruptContext) {
+        self(ctx)
+    }
+}
+
+/// 動的に割り当てるベクタの範囲
+///
+/// 0x20-0x2F はレガシー PIC、0xFE/0xFF は APIC のエラー・スプリアス割り込みが使う。
+pub const DYNAMIC_VECTORS: RangeInclusive<u8> = 0x30..=0xEF;
+
+/// 登録済みハンドラの識別子 (`unregister_handler` に渡す)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HandlerId {
+    pub vector: u8,
+    id: u64,
+}
+
+/// ベクタごとの状態
+struct VectorSlot {
+    /// `allocate_vector` で割り当て済みか
+    allocated: AtomicBool,
+    /// 発生回数
+    count: AtomicU64,
+    /// 登録順に呼び出すハンドラの連鎖 (共有 IRQ)
+    handlers: RwLock<Vec<(u64, Box<dyn InterruptHandler>)>>,
+}
+
+static VECTORS: [VectorSlot; 256] = [const {
+    VectorSlot { allocated: AtomicBool::new(false), count: AtomicU64::new(0), handlers: RwLock::new(Vec::new()) }
+}; 256];
+static NEXT_HANDLER_ID: AtomicU64 = AtomicU64::new(1);
+
+/// 空いている動的ベクタを 1 つ割り当てる
+pub fn allocate_vector() -> Option<u8> {
+    DYNAMIC_VECTORS.into_iter().find(|&vector| {
+        VECTORS[vector as usize]
+            .allocated
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Relaxed)
+            .is_ok()
+    })
+}
+
+/// `allocate_vector` で割り当てたベクタを解放する (登録済みハンドラも外す)
+pub fn free_vector(vector: u8) {
+    let slot = &VECTORS[vector as usize];
+    // 割り込みハンドラは読み取りロックを取るので、書き込み中は割り込みを止める。
+    // 割り当ての解除もロック中に行い、`register_handler` の確認と入れ違わないようにする
+    without_interrupts(|| {
+        let mut handlers = slot.handlers.write();
+        handlers.clear();
+        slot.allocated.store(false, Ordering::Release);
+    });
+}
+
+/// `vector` にハンドラを追加する
+///
+/// 既にハンドラがあれば連鎖に加え、割り込みのたびに登録順にすべて呼び出す。
+/// `allocate_vector` で割り当て済みの動的ベクタにだけ登録できる
+/// (例外・PIC・APIC 予約ベクタはディスパッチャがハンドラを呼ばないため)。
+pub fn register_handler<H: InterruptHandler + 'static>(vector: u8, handler: H) -> Result<HandlerId, &'static str> {
+    if !DYNAMIC_VECTORS.contains(&vector) {
+        return Err("vector is reserved");
+    }
+    let slot = &VECTORS[vector as usize];
+    let id = NEXT_HANDLER_ID.fetch_add(1, Ordering::Relaxed);
+    let handler: Box<dyn InterruptHandler> = Box::new(handler);
+    without_interrupts(|| {
+        let mut handlers = slot.handlers.write();
+        if !slot.allocated.load(Ordering::Acquire) {
+            return Err("vector is not allocated");
+        }
+        handlers.push((id, handler));
+        Ok(HandlerId { vector, id })
+    })
+}
+
+/// ハンドラを外す。外した後にそのベクタに残っているハンドラ数を返す
+pub fn unregister_handler(handler: HandlerId) -> usize {
+    without_interrupts(|| {
+        let mut handlers = VECTORS[handler.vector as usize].handlers.write();
+        handlers.retain(|(id, _)| *id != handler.id);
+        handlers.len()
+    })
+}
+
+/// `vector` の発生回数
+pub fn interrupt_count(vector: u8) -> u64 {
+    VECTORS[vector as usize].count.load(Ordering::Relaxed)
+}
+
+/// 一度でも発生したベクタとその回数
+pub fn interrupt_counts() -> impl Iterator<Item = (u8, u64)> {
+    (0..=255u8).map(|v| (v, interrupt_count(v))).filter(|&(_, count)| count != 0)
+}
+
+/// 登録済みハンドラを呼び出す。ハンドラが無ければ `false`
+fn run_handlers(vector: u8, ctx: &InterruptContext) -> bool {
+    let handlers = VECTORS[vector as usize].handlers.read();
+    for (_, handler) in handlers.iter() {
+        handler.handle(ctx);
+    }
+    !handlers.is_empty()
+}
+
+/// 全ベクタ共通のディスパッチャ (`interrupt_common` から呼ばれる)
+#[no_mangle]
+extern "sysv64" fn interrupt_dispatch(ctx: &mut InterruptContext) {
+    VECTORS[ctx.vector as usize].count.fetch_add(1, Ordering::Relaxed);
+    match ctx.vector as u8 {
+        0..=31 => exceptions::handle(ctx),
+        // スプリアス割り込み・マスク済み PIC のスプリアス IRQ には APIC の EOI を送らない
+        apic::SPURIOUS_VECTOR => {}
+        vector if pic::is_pic_vector(vector) => pic::handle_spurious(vector),
+        apic::ERROR_VECTOR => apic::handle_error(),
+        vector => {
+            if !run_handlers(vector, ctx) {
+                log::warn!("unexpected interrupt vector {}", vector);
+            }
+            apic::eoi();
+        }
+    }
+}
@@ -0,0 +1,130 @@
+#![allow(dead_code)]
+//! 外部割り込み (GSI) の登録
+//!
+//! - `register(gsi, handler)` は空きベクタを割り当ててハンドラを登録し、
+//!   IOAPIC のリダイレクションエントリを設定してマスクを解除する。
+//! - 既に登録済みの GSI (共有された PCI INTx など) には同じベクタでハンドラを連鎖させる。
+//! - 極性・トリガモードは MADT の割り込みソースオーバーライドに従い、
+//!   指定が無ければ ISA (GSI 0-15) はアクティブハイ・エッジ、それ以外は PCI INTx として
+//!   アクティブロー・レベルとみなす。
+//! - 宛先は BSP のローカル APIC。EOI は割り込みディスパッチャが送る。
+
+use crate::acpi::{self, Madt, Polarity, TriggerMode};
+use crate::apic::{self, ioapic, ioapic::Redirection};
+use crate::interrupts::{self, HandlerId, InterruptHandler};
+use alloc::vec::Vec;
+use spin::Mutex;
+
+pub type Result<T> = core::result::Result<T, &'static str>;
+
+/// ISA IRQ の数
+const ISA_IRQS: u32 = 16;
+
+/// 登録済みの割り込み
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Irq {
+    pub gsi: u32,
+    pub vector: u8,
+    handler: HandlerId,
+}
+
+/// IOAPIC に設定済みの経路
+struct Route {
+    gsi: u32,
+    vector: u8,
+    trigger: TriggerMode,
+}
+
+static ROUTES: Mutex<Vec<Route>> = Mutex::new(Vec::new());
+
+/// MADT の IOAPIC を初期化する (ローカル APIC の初期化後に呼ぶ)
+pub fn init(madt: &Madt) -> Result<usize> {
+    ioapic::init(madt)
+}
+
+/// `gsi` の既定の極性・トリガモード
+fn default_config(gsi: u32) -> (Polarity, TriggerMode) {
+    let isa_default = if gsi < ISA_IRQS {
+        (Polarity::ActiveHigh, TriggerMode::Edge)
+    } else {
+        (Polarity::ActiveLow, TriggerMode::Level)
+    };
+    let Some(madt) = acpi::madt() else { return isa_default };
+    match madt.overrides.iter().find(|o| o.gsi == gsi) {
+        Some(o) => (o.flags.polarity(isa_default.0), o.flags.trigger_mode(isa_default.1)),
+        None => isa_default,
+    }
+}
+
+/// `gsi` にハンドラを登録する (極性・トリガモードは MADT と既定値から決める)
+pub fn register<H: InterruptHandler + 'static>(gsi: u32, handler: H) -> Result<Irq> {
+    let (polarity, trigger) = default_config(gsi);
+    register_with(gsi, polarity, trigger, handler)
+}
+
+/// ISA IRQ 番号でハンドラを登録する (オーバーライドにより GSI が異なる場合がある)
+pub fn register_isa<H: InterruptHandler + 'static>(irq: u8, handler: H) -> Result<Irq> {
+    let (gsi, polarity, trigger) = match acpi::madt() {
+        Some(madt) => madt.isa_irq(irq),
+        None => (irq as u32, Polarity::ActiveHigh, TriggerMode::Edge),
+    };
+    register_with(gsi, polarity, trigger, handler)
+}
+
+/// 極性・トリガモードを指定してハンドラを登録する
+///
+/// 既に経路がある GSI ではトリガモードが一致する場合のみハンドラを連鎖させる
+/// (エッジトリガの共有は割り込みを取りこぼすため認めない)。
+pub fn register_with<H: InterruptHandler + 'static>(
+    gsi: u32,
+    polarity: Polarity,
+    trigger: TriggerMode,
+    handler: H,
+) -> Result<Irq> {
+    let mut routes = ROUTES.lock();
+    if let Some(route) = routes.iter().find(|r| r.gsi == gsi) {
+        if route.trigger != TriggerMode::Level || trigger != TriggerMode::Level {
+            return Err("GSI is already in use and cannot be shared");
+        }
+        let handler = interrupts::register_handler(route.vector, handler)?;
+        return Ok(Irq { gsi, vector: route.vector, handler });
+    }
+
+    let dest = u8::try_from(apic::id()).map_err(|_| "APIC ID does not fit in IOAPIC destination")?;
+    let vector = interrupts::allocate_vector().ok_or("no free interrupt vector")?;
+    let handler = match interrupts::register_handler(vector, handler) {
+        Ok(id) => id,
+        Err(e) => {
+            interrupts::free_vector(vector);
+            return Err(e);
+        }
+    };
+    let redirection = Redirection { vector, polarity, trigger, dest, masked: false };
+    if let Err(e) = ioapic::set_redirection(gsi, redirection) {
+        interrupts::free_vector(vector);
+        return Err(e);
+    }
+    routes.push(Route { gsi, vector, trigger });
+    Ok(Irq { gsi, vector, handler })
+}
+
+/// 登録を解除する
+///
+/// その GSI の最後のハンドラだった場合は、エントリをマスクしてからベクタを解放する。
+pub fn unregister(irq: Irq) {
+    let mut routes = ROUTES.lock();
+    if interrupts::unregister_handler(irq.handler) == 0 {
+        ioapic::mask(irq.gsi);
+        interrupts::free_vector(irq.vector);
+        routes.retain(|r| r.gsi != irq.gsi);
+    }
+}
+
+/// 割り込みを一時的に止める (共有している他のハンドラにも届かなくなる)
+pub fn mask(irq: Irq) {
+    ioapic::mask(irq.gsi);
+}
+
+pub fn unmask(irq: Irq) {
+    ioapic::unmask(irq.gsi);
+}
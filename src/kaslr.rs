@@ -0,0 +1,242 @@
+#![allow(dead_code)]
+//! カーネルアドレス空間配置のランダム化 (KASLR)
+//!
+//! - RDRAND と EFI_RNG_PROTOCOL (どちらも無ければ TSC) からエントロピーを集め、
+//!   ダイレクトマップ・ヒープ・vmalloc 領域・カーネルスタック領域の仮想ベースを
+//!   それぞれ独立に 2MiB 境界でランダムに選ぶ。スタックは vmalloc とは別の領域から確保する。
+//! - カーネル本体 (テキスト) は `relocate_kernel` で、4GiB 未満の空き物理メモリ
+//!   (アイデンティティマップなので仮想アドレスも同じ) から 2MiB 境界をランダムに選んで複製し、
+//!   ベース再配置を適用した複製側で起動を続ける。ファームウェアが置いた元のイメージは使わずに残る。
+//!   リンク時アドレスとのずれ (slide) はシンボル解決に使う。
+//! - `relocate_kernel` と `init` は EFI_RNG_PROTOCOL を使うため ExitBootServices 前に呼ぶこと。
+
+use core::arch::asm;
+use spin::Once;
+
+use crate::cpu::CpuFeatures;
+use crate::efi::{self, EfiHandle, EfiSystemTable, MemoryMapHolder};
+use crate::image::KernelImage;
+
+const ALIGN_2MIB: u64 = 2 * 1024 * 1024;
+
+/// カーネルイメージを置く物理アドレスの範囲 (ファームウェアが使う低位 16MiB を避け、
+/// `memory::init_paging` のアイデンティティマップ 4GiB に収める)
+const KERNEL_PHYS_RANGE: (u64, u64) = (16 << 20, 4 << 30);
+/// 選んだ位置の確保に失敗したとき (メモリマップ取得後に使われた場合) に選び直す回数
+const RELOCATE_ATTEMPTS: usize = 8;
+
+/// 各領域を配置する候補範囲 (開始アドレス, 範囲の大きさ)
+const DIRECT_MAP_WINDOW: (u64, u64) = (0xFFFF_8800_0000_0000, 1 << 44);
+const HEAP_WINDOW: (u64, u64) = (0xFFFF_A000_0000_0000, 1 << 40);
+const VMALLOC_WINDOW: (u64, u64) = (0xFFFF_C000_0000_0000, 1 << 44);
+const STACK_WINDOW: (u64, u64) = (0xFFFF_E000_0000_0000, 1 << 40);
+
+/// ダイレクトマップでマップする物理メモリの大きさ
+pub const DIRECT_MAP_SIZE: u64 = 1 << 32;
+/// vmalloc 領域の大きさ
+pub const VMALLOC_SIZE: u64 = 1 << 40;
+/// カーネルスタック領域の大きさ
+pub const STACK_AREA_SIZE: u64 = 1 << 32;
+
+/// エントロピーの取得元
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntropySource {
+    Rdrand,
+    EfiRng,
+    /// RDRAND と EFI_RNG の両方
+    Both,
+    /// 乱数源が無く TSC のみ (予測可能)
+    Tsc,
+}
+
+/// カーネルテキストの再配置の結果
+#[derive(Clone, Copy, Debug)]
+pub enum Relocation {
+    /// ファームウェアのロードアドレス `from` から移した
+    Moved { from: u64 },
+    /// ファームウェアの配置のまま (理由)
+    Kept(&'static str),
+}
+
+/// 決定したカーネル仮想アドレス配置
+#[derive(Clone, Copy, Debug)]
+pub struct KernelLayout {
+    /// カーネルイメージの実ロードアドレス
+    pub kernel_base: u64,
+    /// リンク時アドレスからのずれ (バックトレースのシンボル解決用)
+    pub kernel_slide: i64,
+    pub kernel_relocation: Relocation,
+    /// 物理アドレス 0 をマップする仮想アドレス
+    pub direct_map_base: u64,
+    pub heap_base: u64,
+    pub vmalloc_base: u64,
+    /// カーネルスタック (ガードページ付き) を確保する領域
+    pub stack_base: u64,
+    pub entropy: EntropySource,
+}
+
+static LAYOUT: Once<KernelLayout> = Once::new();
+
+/// カーネルイメージをランダムな物理アドレスへ複製し、複製側の `entry` で起動を続ける
+///
+/// efi_main の先頭で、static を 1 つも書き換えないうちに呼ぶこと (複製は呼び出し時の
+/// メモリ内容を写すため)。複製できなければファームウェアの配置のまま `entry` を呼ぶ。
+pub fn relocate_kernel(
+    image_handle: EfiHandle,
+    system_table: &'static EfiSystemTable,
+    entry: fn(EfiHandle, &'static EfiSystemTable, Relocation) -> !,
+) -> ! {
+    let image = KernelImage::current();
+    match place_image(&image, system_table) {
+        Ok(dest) => {
+            // 複製側の同じ関数 (イメージ先頭からのオフセットは変わらない)
+            let relocated = (entry as usize as u64 - image.base + dest) as usize;
+            // Safety: 複製は再配置済みで、`entry` と同じ関数を指す
+            let entry: fn(EfiHandle, &'static EfiSystemTable, Relocation) -> ! =
+                unsafe { core::mem::transmute(relocated) };
+            entry(image_handle, system_table, Relocation::Moved { from: image.base })
+        }
+        Err(e) => entry(image_handle, system_table, Relocation::Kept(e)),
+    }
+}
+
+/// 空き物理メモリからイメージの置き場所を選んで確保し、複製する
+fn place_image(image: &KernelImage, system_table: &EfiSystemTable) -> efi::Result<u64> {
+    let mut mmap = MemoryMapHolder::new();
+    if system_table.boot_services.call_get_memory_map(&mut mmap) != efi::EfiStatus::Success {
+        return Err("failed to get the memory map");
+    }
+    let pages = image.size.div_ceil(4096) as usize;
+    let mut rng = SplitMix64(seed(system_table).0);
+    for _ in 0..RELOCATE_ATTEMPTS {
+        let dest = pick_free(&mmap, &mut rng, image.size).ok_or("no free memory for the kernel image")?;
+        if efi::allocate_code_pages_at(system_table, dest, pages).is_err() {
+            continue;
+        }
+        // Safety: 確保したばかりの領域で、元のイメージ (使用中) とは重ならない
+        return match unsafe { image.copy_to(dest) } {
+            Ok(()) => Ok(dest),
+            Err(e) => {
+                efi::free_pages(system_table, dest, pages);
+                Err(e)
+            }
+        };
+    }
+    Err("failed to allocate memory for the kernel image")
+}
+
+/// 空き (EfiConventionalMemory) で `span` バイトが収まる 2MiB 境界のうち 1 つを一様に選ぶ
+fn pick_free(mmap: &MemoryMapHolder, rng: &mut SplitMix64, span: u64) -> Option<u64> {
+    // 各空き領域の中で置ける先頭アドレスの (最初, 個数)
+    let slots = |d: efi::EfiMemoryDescriptor| {
+        let start = d.physical_start.max(KERNEL_PHYS_RANGE.0).next_multiple_of(ALIGN_2MIB);
+        let end = (d.physical_start + d.number_of_pages * 4096).min(KERNEL_PHYS_RANGE.1);
+        let count = if d.memory_type == efi::EFI_CONVENTIONAL_MEMORY && start + span <= end {
+            (end - span - start) / ALIGN_2MIB + 1
+        } else {
+            0
+        };
+        (start, count)
+    };
+    let total: u64 = mmap.descriptors().map(|d| slots(d).1).sum();
+    if total == 0 {
+        return None;
+    }
+    let mut n = rng.next() % total;
+    for (start, count) in mmap.descriptors().map(slots) {
+        if n < count {
+            return Some(start + n * ALIGN_2MIB);
+        }
+        n -= count;
+    }
+    None
+}
+
+/// 乱数源からシードを集める
+fn seed(system_table: &EfiSystemTable) -> (u64, EntropySource) {
+    let hw = if CpuFeatures::detect().rdrand { rdrand64() } else { None };
+    let fw = efi::random_u64(system_table);
+    let entropy = match (hw, fw) {
+        (Some(_), Some(_)) => EntropySource::Both,
+        (Some(_), None) => EntropySource::Rdrand,
+        (None, Some(_)) => EntropySource::EfiRng,
+        (None, None) => EntropySource::Tsc,
+    };
+    (hw.unwrap_or(0) ^ fw.unwrap_or(0) ^ rdtsc(), entropy)
+}
+
+/// 乱数源からシードを集めて配置を決定する
+///
+/// `kernel_relocation` は `relocate_kernel` が `entry` に渡した結果。
+pub fn init(system_table: &EfiSystemTable, kernel_relocation: Relocation) -> &'static KernelLayout {
+    LAYOUT.call_once(|| {
+        let (seed, entropy) = seed(system_table);
+        let mut rng = SplitMix64(seed);
+        let image = KernelImage::current();
+        KernelLayout {
+            kernel_base: image.base,
+            kernel_slide: image.slide(),
+            kernel_relocation,
+            direct_map_base: pick(&mut rng, DIRECT_MAP_WINDOW, DIRECT_MAP_SIZE),
+            heap_base: pick(&mut rng, HEAP_WINDOW, crate::memory::HEAP_SIZE as u64),
+            vmalloc_base: pick(&mut rng, VMALLOC_WINDOW, VMALLOC_SIZE),
+            stack_base: pick(&mut rng, STACK_WINDOW, STACK_AREA_SIZE),
+            entropy,
+        }
+    })
+}
+
+/// 決定済みの配置
+///
+/// `init` より前に呼ぶとパニックする (固定配置で黙って続行しないように)。
+pub fn layout() -> &'static KernelLayout {
+    LAYOUT.get().expect("kaslr::layout() called before kaslr::init()")
+}
+
+/// カーネルイメージのずれ
+///
+/// イメージヘッダから直接求めるので `init` 前 (早期のパニック時など) でも使える。
+pub fn kernel_slide() -> i64 {
+    KernelImage::current().slide()
+}
+
+/// `window` 内で `span` バイトが収まる 2MiB 境界のアドレスを選ぶ
+fn pick(rng: &mut SplitMix64, window: (u64, u64), span: u64) -> u64 {
+    let span = (span + ALIGN_2MIB - 1) & !(ALIGN_2MIB - 1);
+    let slots = (window.1 - span) / ALIGN_2MIB + 1;
+    window.0 + (rng.next() % slots) * ALIGN_2MIB
+}
+
+/// シードから複数の値を導出するための簡易 PRNG
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+/// RDRAND で 64bit 乱数を得る (一時的な枯渇に備えて数回リトライ)
+fn rdrand64() -> Option<u64> {
+    for _ in 0..10 {
+        let value: u64;
+        let ok: u8;
+        // Safety: 呼び出し元で CPUID により RDRAND 対応を確認済み
+        unsafe {
+            asm!("rdrand {v}", "setc {ok}", v = out(reg) value, ok = out(reg_byte) ok, options(nomem, nostack));
+        }
+        if ok != 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn rdtsc() -> u64 {
+    // Safety: RDTSC は常に使用可能
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
@@ -0,0 +1,283 @@
+//! メモリ上のログ (dmesg)
+//!
+//! - レコードを通し番号付きで固定長のリングバッファに残す。一杯になったら古い方から上書きする。
+//! - ヒープ初期化前から使えるよう静的な配列に置き、ロックを取らない。
+//!   書き手は通し番号を `fetch_add` で取り、その番号のスロットを「書き込み中」にしてから内容を書く。
+//!   読み手はスロットの状態を読む前後で比べ (seqlock)、途中で書き換えられたものは捨てる。
+//! - 本文 (ターゲット名とメッセージ) は `MESSAGE_LEN` バイトで切り詰める。
+//! - `Reader` で続きから読み出せる (シェルの `dmesg`、将来のシステムコールやファイルから使う)。
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{fence, AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use log::Level;
+use super::Line;
+
+/// 保持するレコード数 (2 のべき乗)
+pub const CAPACITY: usize = 512;
+/// 1 レコードの本文の最大長
+pub const MESSAGE_LEN: usize = 120;
+
+/// スロットの状態: 0 = 未使用、`2 * seq + 1` = 書き込み中、`2 * seq + 2` = 書き込み済み
+struct Slot {
+    state: AtomicU64,
+    timestamp_ns: AtomicU64,
+    cpu: AtomicU32,
+    level: AtomicU8,
+    len: AtomicUsize,
+    text: [AtomicU8; MESSAGE_LEN],
+}
+
+impl Slot {
+    const fn new() -> Self {
+        Self {
+            state: AtomicU64::new(0),
+            timestamp_ns: AtomicU64::new(0),
+            cpu: AtomicU32::new(0),
+            level: AtomicU8::new(0),
+            len: AtomicUsize::new(0),
+            text: [const { AtomicU8::new(0) }; MESSAGE_LEN],
+        }
+    }
+}
+
+/// 本文をスロットへ書き込む (入り切らない分は捨てる)
+struct SlotWriter<'a> {
+    slot: &'a Slot,
+    len: usize,
+}
+
+impl fmt::Write for SlotWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            if self.len == MESSAGE_LEN {
+                break;
+            }
+            self.slot.text[self.len].store(byte, Ordering::Relaxed);
+            self.len += 1;
+        }
+        Ok(())
+    }
+}
+
+static SLOTS: [Slot; CAPACITY] = [const { Slot::new() }; CAPACITY];
+/// 次に割り当てる通し番号
+static NEXT_SEQ: AtomicU64 = AtomicU64::new(0);
+/// 書き込み中のスロットに追いついて捨てたレコード数
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+fn slot(seq: u64) -> &'static Slot {
+    &SLOTS[seq as usize % CAPACITY]
+}
+
+pub(super) fn write_line(line: &Line) {
+    let seq = NEXT_SEQ.fetch_add(1, Ordering::Relaxed);
+    let slot = slot(seq);
+    let writing = 2 * seq + 1;
+    // 1 周前のレコードを書き込み中なら (割り込まれた書き手がいる) 諦める
+    let mut current = slot.state.load(Ordering::Relaxed);
+    loop {
+        if current % 2 == 1 || current > writing {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        match slot.state.compare_exchange_weak(current, writing, Ordering::Acquire, Ordering::Relaxed) {
+            Ok(_) => break,
+            Err(actual) => current = actual,
+        }
+    }
+    fence(Ordering::Release);
+
+    slot.timestamp_ns.store(line.timestamp_ns, Ordering::Relaxed);
+    slot.cpu.store(line.cpu, Ordering::Relaxed);
+    slot.level.store(line.level as u8, Ordering::Relaxed);
+    let mut writer = SlotWriter { slot, len: 0 };
+    let _ = write!(writer, "{}: {}", line.target, line.args);
+    slot.len.store(writer.len, Ordering::Relaxed);
+
+    slot.state.store(writing + 1, Ordering::Release);
+}
+
+/// 読み出したレコード
+#[derive(Clone)]
+pub struct Entry {
+    pub seq: u64,
+    pub timestamp_ns: u64,
+    pub cpu: u32,
+    pub level: Level,
+    len: usize,
+    text: [u8; MESSAGE_LEN],
+}
+
+impl Entry {
+    /// `ターゲット: メッセージ` (切り詰めで壊れた末尾の文字は除く)
+    pub fn text(&self) -> &str {
+        let bytes = &self.text[..self.len];
+        match core::str::from_utf8(bytes) {
+            Ok(text) => text,
+            Err(e) => core::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or(""),
+        }
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let micros = self.timestamp_ns / 1000;
+        write!(
+            f,
+            "[{:>5}.{:06}] cpu{} {:<5} {}",
+            micros / 1_000_000,
+            micros % 1_000_000,
+            self.cpu,
+            self.level,
+            self.text()
+        )
+    }
+}
+
+enum ReadError {
+    /// 上書きされたか、書き込みに失敗して欠番になった
+    Missing,
+    /// まだ書き込み中
+    InProgress,
+}
+
+fn read(seq: u64) -> Result<Entry, ReadError> {
+    let slot = slot(seq);
+    let committed = 2 * seq + 2;
+    let before = slot.state.load(Ordering::Acquire);
+    if before != committed {
+        return Err(if before == committed - 1 { ReadError::InProgress } else { ReadError::Missing });
+    }
+    let len = slot.len.load(Ordering::Relaxed).min(MESSAGE_LEN);
+    let mut entry = Entry {
+        seq,
+        timestamp_ns: slot.timestamp_ns.load(Ordering::Relaxed),
+        cpu: slot.cpu.load(Ordering::Relaxed),
+        level: level_from_u8(slot.level.load(Ordering::Relaxed)),
+        len,
+        text: [0; MESSAGE_LEN],
+    };
+    for (dst, src) in entry.text[..len].iter_mut().zip(&slot.text) {
+        *dst = src.load(Ordering::Relaxed);
+    }
+    fence(Ordering::Acquire);
+    if slot.state.load(Ordering::Relaxed) != before {
+        return Err(ReadError::Missing);
+    }
+    Ok(entry)
+}
+
+fn level_from_u8(level: u8) -> Level {
+    match level {
+        1 => Level::Error,
+        2 => Level::Warn,
+        3 => Level::Info,
+        4 => Level::Debug,
+        _ => Level::Trace,
+    }
+}
+
+/// 残っている最も古いレコードの通し番号
+pub fn first_seq() -> u64 {
+    NEXT_SEQ.load(Ordering::Relaxed).saturating_sub(CAPACITY as u64)
+}
+
+/// 次に書かれるレコードの通し番号 (これまでに書かれたレコード数)
+pub fn next_seq() -> u64 {
+    NEXT_SEQ.load(Ordering::Relaxed)
+}
+
+/// 書き込み中のスロットと衝突して捨てたレコード数
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// 通し番号順にレコードを読み出すカーソル
+pub struct Reader {
+    next: u64,
+    /// 読む前に上書きされて読めなかったレコード数
+    lost: u64,
+}
+
+impl Reader {
+    /// 残っている最も古いレコードから読む
+    pub fn new() -> Self {
+        Self::from_seq(first_seq())
+    }
+
+    /// 通し番号 `seq` のレコードから読む
+    pub fn from_seq(seq: u64) -> Self {
+        Self { next: seq, lost: 0 }
+    }
+
+    /// 直近の `count` 件から読む
+    pub fn last(count: usize) -> Self {
+        Self::from_seq(next_seq().saturating_sub(count as u64).max(first_seq()))
+    }
+
+    /// 次に読む通し番号
+    pub fn position(&self) -> u64 {
+        self.next
+    }
+
+    pub fn lost(&self) -> u64 {
+        self.lost
+    }
+
+    /// 書き込み中のレコードを待たずに飛ばす
+    pub fn skip_in_progress(&mut self) {
+        self.next += 1;
+    }
+}
+
+impl Default for Reader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Iterator for Reader {
+    type Item = Entry;
+
+    /// 次のレコード (書き込み中のレコードに追いついたら `None`、後で続きから読める)
+    fn next(&mut self) -> Option<Entry> {
+        while self.next < next_seq() {
+            let first = first_seq();
+            if self.next < first {
+                self.lost += first - self.next;
+                self.next = first;
+            }
+            match read(self.next) {
+                Ok(entry) => {
+                    self.next += 1;
+                    return Some(entry);
+                }
+                Err(ReadError::InProgress) => return None,
+                Err(ReadError::Missing) => self.next += 1,
+            }
+        }
+        None
+    }
+}
+
+/// 直近 `.0` 件のレコードを古い順に並べたもの (パニック時の出力用)
+///
+/// 書き込み中のまま止まったレコードは飛ばす。
+pub struct Dump(pub usize);
+
+impl fmt::Display for Dump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut reader = Reader::last(self.0);
+        loop {
+            match reader.next() {
+                Some(entry) => writeln!(f, "{}", entry)?,
+                None if reader.position() < next_seq() => reader.skip_in_progress(),
+                None => break,
+            }
+        }
+        if reader.lost() > 0 {
+            writeln!(f, "({} records overwritten while reading)", reader.lost())?;
+        }
+        Ok(())
+    }
+}
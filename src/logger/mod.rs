@@ -0,0 +1,219 @@
+#![allow(dead_code)]
+//! カーネルのログ出力 (`log` クレートのバックエンド)
+//!
+//! - `log::info!` などで出したレコードに、TSC から求めた起動後の時刻と CPU (APIC ID) を付けて
+//!   シリアル・フレームバッファのコンソール・メモリ上のリングバッファ (`dmesg`) へ配る。
+//! - 出力先ごとにレベルを絞り込める。コマンドラインの `loglevel=debug` で全体を、
+//!   `log.serial=` `log.console=` `log.dmesg=` で出力先ごとに指定する (`off` `error` ... `trace`)。
+//! - ヒープを使わないので、`efi_main` の先頭で登録する。それ以降のレコードは `dmesg` に必ず残り、
+//!   シリアルへは `configure` (シリアルの初期化後) から、コンソールへは `attach_console` から出す。
+//!   コンソールを登録したときは、それまでに dmesg に溜まった分をまとめて表示する。
+
+pub mod dmesg;
+
+use core::fmt;
+use core::str::FromStr;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use crate::graphics::FrameBuffer;
+use crate::time::tsc;
+
+/// 出力先
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sink {
+    Serial,
+    Console,
+    Dmesg,
+}
+
+impl Sink {
+    pub const ALL: [Sink; 3] = [Sink::Serial, Sink::Console, Sink::Dmesg];
+
+    fn name(self) -> &'static str {
+        match self {
+            Sink::Serial => "serial",
+            Sink::Console => "console",
+            Sink::Dmesg => "dmesg",
+        }
+    }
+
+    fn default_level(self) -> LevelFilter {
+        match self {
+            Sink::Serial => LevelFilter::Debug,
+            Sink::Console => LevelFilter::Info,
+            Sink::Dmesg => LevelFilter::Trace,
+        }
+    }
+
+    fn filter(self) -> &'static AtomicUsize {
+        &FILTERS[self as usize]
+    }
+}
+
+/// 出力先ごとのレベル (`LevelFilter as usize`)
+static FILTERS: [AtomicUsize; 3] = [
+    AtomicUsize::new(LevelFilter::Debug as usize),
+    AtomicUsize::new(LevelFilter::Info as usize),
+    AtomicUsize::new(LevelFilter::Trace as usize),
+];
+
+/// シリアルを初期化済みか (UEFI が使っている間は書かない)
+static SERIAL_READY: AtomicBool = AtomicBool::new(false);
+
+static LOGGER: KernelLogger = KernelLogger;
+
+struct KernelLogger;
+
+impl Log for KernelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        Sink::ALL.iter().any(|&sink| metadata.level() <= level(sink))
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = Line {
+            timestamp_ns: tsc::cycles_to_ns(tsc::read()),
+            cpu: crate::apic::id(),
+            level: record.level(),
+            target: record.target(),
+            args: *record.args(),
+        };
+        if record.level() <= level(Sink::Serial) && SERIAL_READY.load(Ordering::Acquire) {
+            crate::serial_println!("{}", line);
+        }
+        if record.level() <= level(Sink::Console) {
+            print_console(line.level, &line);
+        }
+        if record.level() <= level(Sink::Dmesg) {
+            dmesg::write_line(&line);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// 出力する 1 行 (`[    1.234567] cpu0 INFO  ferr_os::time: message`)
+pub struct Line<'a> {
+    pub timestamp_ns: u64,
+    pub cpu: u32,
+    pub level: Level,
+    pub target: &'a str,
+    pub args: fmt::Arguments<'a>,
+}
+
+impl fmt::Display for Line<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let micros = self.timestamp_ns / 1000;
+        write!(
+            f,
+            "[{:>5}.{:06}] cpu{} {:<5} {}: {}",
+            micros / 1_000_000,
+            micros % 1_000_000,
+            self.cpu,
+            self.level,
+            self.target,
+            self.args
+        )
+    }
+}
+
+/// ロガーを既定のレベルで登録する
+pub fn init() -> Result<(), &'static str> {
+    log::set_logger(&LOGGER).map_err(|_| "logger already registered")?;
+    update_max_level();
+    Ok(())
+}
+
+/// コマンドラインからレベルを設定し、シリアルへの出力を始める (シリアルの初期化後に呼ぶ)
+pub fn configure() {
+    let global = crate::cmdline::get("loglevel").and_then(|s| LevelFilter::from_str(s).ok());
+    for sink in Sink::ALL {
+        let key = match sink {
+            Sink::Serial => "log.serial",
+            Sink::Console => "log.console",
+            Sink::Dmesg => "log.dmesg",
+        };
+        let level = crate::cmdline::get(key)
+            .and_then(|s| LevelFilter::from_str(s).ok())
+            .or(global)
+            .unwrap_or(sink.default_level());
+        sink.filter().store(level as usize, Ordering::Relaxed);
+    }
+    update_max_level();
+    SERIAL_READY.store(true, Ordering::Release);
+}
+
+/// 出力先 `sink` のレベル
+pub fn level(sink: Sink) -> LevelFilter {
+    match sink.filter().load(Ordering::Relaxed) {
+        0 => LevelFilter::Off,
+        1 => LevelFilter::Error,
+        2 => LevelFilter::Warn,
+        3 => LevelFilter::Info,
+        4 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// 出力先 `sink` のレベルを変える
+pub fn set_level(sink: Sink, level: LevelFilter) {
+    sink.filter().store(level as usize, Ordering::Relaxed);
+    update_max_level();
+}
+
+/// 出力先ごとのレベルの一覧 (`serial=debug console=info dmesg=trace`)
+pub struct Levels;
+
+impl fmt::Display for Levels {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, sink) in Sink::ALL.into_iter().enumerate() {
+            let separator = if i == 0 { "" } else { " " };
+            write!(f, "{}{}={}", separator, sink.name(), level_name(level(sink)))?;
+        }
+        Ok(())
+    }
+}
+
+/// コマンドラインと同じ小文字のレベル名
+fn level_name(level: LevelFilter) -> &'static str {
+    match level {
+        LevelFilter::Off => "off",
+        LevelFilter::Error => "error",
+        LevelFilter::Warn => "warn",
+        LevelFilter::Info => "info",
+        LevelFilter::Debug => "debug",
+        LevelFilter::Trace => "trace",
+    }
+}
+
+/// どれかの出力先が受け付ける最も詳しいレベルを `log` クレートに伝える
+fn update_max_level() {
+    let max = Sink::ALL.into_iter().map(level).max().unwrap_or(LevelFilter::Off);
+    log::set_max_level(max);
+}
+
+/// コンソールでのレベルごとの色 (SGR、Info は既定の色のまま)
+fn level_sgr(level: Level) -> &'static str {
+    match level {
+        Level::Error => "\x1b[91m",
+        Level::Warn => "\x1b[93m",
+        Level::Info => "",
+        Level::Debug | Level::Trace => "\x1b[90m",
+    }
+}
+
+/// レベルの色を付けてコンソールへ出す (シェルなどが設定した色は書き終えたら元に戻る)
+fn print_console(level: Level, line: impl fmt::Display) {
+    crate::console::try_print_isolated(format_args!("{}{}\n", level_sgr(level), line));
+}
+
+/// `fb` をコンソールにしてログを出し始める (ヒープ初期化後に呼ぶ)
+pub fn attach_console(fb: FrameBuffer<'static>) {
+    crate::console::init(fb);
+    let max_level = level(Sink::Console);
+    for entry in dmesg::Reader::new().filter(|entry| entry.level <= max_level) {
+        print_console(entry.level, &entry);
+    }
+}
@@ -1,7 +1,6 @@
 #![no_std]
 #![no_main]
 #![feature(offset_of)]
-#![feature(abi_x86_interrupt)]
 #![feature(alloc_error_handler)]
 
 extern crate alloc;
@@ -11,35 +10,59 @@ use x86_64::structures::paging::FrameAllocator;
 
 mod font;
 mod graphics;
-use graphics::{FrameBuffer, COLOR_BLACK, COLOR_BLUE, COLOR_GREEN, COLOR_RED, COLOR_WHITE};
+use graphics::{FrameBuffer, COLOR_BLUE, COLOR_GREEN, COLOR_RED, COLOR_WHITE};
+use graphics::text::{Align, Rect, GLYPH_HEIGHT, LINE_HEIGHT};
 
 mod efi;
 use efi::{EfiHandle, EfiSystemTable, framebuffer, MemoryMapHolder, EfiStatus};
 
+mod acpi;
+mod apic;
+mod backtrace;
+mod cmdline;
+mod console;
+mod cpu;
+mod drivers;
+mod emergency;
+mod exceptions;
 mod gdt;
+mod image;
+mod ime;
+mod input;
 mod interrupts;
+mod irq;
+mod kaslr;
+mod logger;
 mod memory;
+mod panic_screen;
+mod shell;
+mod sync;
+mod time;
 
 use alloc::vec::Vec;
 use memory::BitmapFrameAllocator;
+use memory::stack::{KernelStack, KERNEL_STACK_SIZE};
 use alloc::format;
 
 // ------------------------------------------------------------
 // 簡易 UI モジュール（暫定）
 mod ui {
     use crate::graphics::{FrameBuffer, COLOR_BLUE, COLOR_WHITE, COLOR_RED, COLOR_GREEN};
+    use crate::graphics::text::{Align, Rect, LINE_HEIGHT};
 
     /// ホーム画面を描画
     pub fn home(fb: &mut FrameBuffer) {
         // 背景
         fb.clear(COLOR_BLUE);
 
-        // テキスト
-        let label = "Hello, World!";
-        let text_width = label.len() * 8 + (label.len() - 1) * 2;
-        let x = (fb.width - text_width) / 2;
-        let y = fb.height / 2 - 4;
-        fb.draw_text(x, y, label, COLOR_WHITE);
+        // テキスト (画面中央)
+        let label = fb.draw_text_in(fb.bounds(), "Hello, World!", Align::CENTER, COLOR_WHITE);
+
+        // 現在時刻 (fb_print! はヒープを使わない)
+        if let Ok(now) = crate::time::rtc::read() {
+            let line = Rect::new(0, label.y + 16, fb.width, LINE_HEIGHT);
+            crate::fb_print!(fb, line, Align::TOP_CENTER, COLOR_WHITE, "{} UTC", now);
+        }
 
         // 図形例 (円・矩形など)
         fb.fill_rect(20, 20, 80, 50, COLOR_RED);
@@ -50,8 +73,20 @@ mod ui {
 // ------------------------------------------------------------
 
 #[no_mangle]
-fn efi_main(image_handle: EfiHandle, system_table: &EfiSystemTable) {
+fn efi_main(image_handle: EfiHandle, system_table: &'static EfiSystemTable) {
+    // 何も初期化しないうちにカーネル本体をランダムな位置へ移し、そちらで起動を続ける
+    kaslr::relocate_kernel(image_handle, system_table, boot)
+}
+
+/// 再配置後のカーネルでの起動処理
+fn boot(image_handle: EfiHandle, system_table: &'static EfiSystemTable, relocation: kaslr::Relocation) -> ! {
+    time::boot::mark("efi_main");
+    // シリアルやコンソールの準備前のログも dmesg に残す
+    let logger = logger::init();
     let mut fb = framebuffer(system_table).expect("GOP unavailable");
+    log::info!("framebuffer: {}x{}", fb.width, fb.height);
+    // 例外・パニック時にロックなしで描画できるよう登録しておく
+    emergency::register(&mut fb);
 
     // ホーム画面を描画
     ui::home(&mut fb);
@@ -62,50 +97,77 @@ fn efi_main(image_handle: EfiHandle, system_table: &EfiSystemTable) {
     // BootServices ポインタ (ExitBootServices 前)
     let _bs_before = system_table.boot_services as *const _ as usize;
 
+    // 乱数源 (EFI_RNG_PROTOCOL を含む) が使えるうちにアドレス配置を決める
+    let layout = kaslr::init(system_table, relocation);
+
+    // バックトレース用のシンボル表 (ESP 上に無ければアドレスのみ表示する)
+    let symbols = backtrace::load_symbols(image_handle, system_table);
+    cmdline::init(image_handle, system_table);
+    let ime_dictionary = ime::dict::load(image_handle, system_table);
+    // かな・漢字の字形 (候補ウィンドウ用)
+    let extra_font = font::extra::load(image_handle, system_table);
+    // ACPI テーブル (RSDP) の位置は UEFI の構成テーブルからしか得られない
+    let rsdp_found = acpi::find_rsdp(system_table);
+
     // BootServices との決別: ExitBootServices を呼び出す
     let mut mmap = MemoryMapHolder::new();
     exit_from_efi_boot_services(image_handle, system_table, &mut mmap);
+    time::boot::mark("ExitBootServices");
 
 
     fb.clear(COLOR_WHITE);
+    drivers::serial::init();
+    logger::configure();
+    if let Err(e) = logger {
+        log::warn!("{}", e);
+    }
+    // 画面の下 1/3 をコンソールにする (ヒープ初期化後に登録する)
+    let console_rows = fb.height / 3;
+    let console_fb = fb.split_off_bottom(console_rows);
+    log::info!("boot services exited");
+    log::debug!("log levels: {}", logger::Levels);
+    match layout.kernel_relocation {
+        kaslr::Relocation::Moved { from } => {
+            log::info!("kernel relocated from {:#x} to {:#x}", from, layout.kernel_base)
+        }
+        kaslr::Relocation::Kept(e) => log::warn!("kernel left at {:#x} ({})", layout.kernel_base, e),
+    }
+    match symbols {
+        Ok(count) => log::info!("loaded {} kernel symbols", count),
+        Err(e) => log::warn!("kernel symbols unavailable ({})", e),
+    }
+    log::info!("command line: \"{}\"", cmdline::as_str());
+    match ime_dictionary {
+        Ok(count) => log::info!("loaded {} IME dictionary entries", count),
+        Err(e) => log::warn!("IME dictionary unavailable ({})", e),
+    }
+    match extra_font {
+        Ok(count) => log::info!("loaded {} extra glyphs", count),
+        Err(e) => log::warn!("extra font unavailable, non-ASCII text will not be drawn ({})", e),
+    }
+    if !rsdp_found {
+        log::warn!("ACPI RSDP not found");
+    }
     // CPU 初期化: GDT/TSS・IDT 設定
     gdt::init();
-    fb.draw_text(10, 10, "GDT OK", COLOR_BLACK);
+    log::info!("GDT OK");
+    time::boot::mark("GDT");
     interrupts::init();
-    fb.draw_text(10, 20, "IDT OK", COLOR_BLACK);
+    log::info!("IDT OK");
+    time::boot::mark("IDT");
+    // #BP ハンドラが報告して復帰できることを確認
+    x86_64::instructions::interrupts::int3();
+    log::info!("Breakpoint OK");
     unsafe { memory::init_paging(); }
-    fb.draw_text(10, 30, "Paging Init OK", COLOR_BLACK);
+    log::info!("Paging Init OK");
+    time::boot::mark("paging");
+    cpu::enable_hardening();
+    log::info!("Hardening OK");
     unsafe { paging_smoke_test(&mut fb); }
-    fb.draw_text(10, 40, "Paging Test Done", COLOR_BLACK);
-    unsafe { memory::init_heap(); }
-    fb.draw_text(10, 50, "Heap Init OK", COLOR_BLACK);
-    fb.draw_text(10, 60, "Heap Test Done", COLOR_BLACK);
-
-    // 動的確保テスト: reserve 1KiB 分の Vec
-    let mut test_vec: Vec<u64> = Vec::new();
-    let msg;
-    let color;
-    match test_vec.try_reserve_exact(1024) {
-        Ok(_) => {
-            msg = "Heap OK";
-            color = COLOR_GREEN;
-        }
-        Err(_) => {
-            msg = "Heap NG";
-            color = COLOR_RED;
-        }
-    }
-
-    // fb.clear(COLOR_RED);
-    // 結果を描画
-    let msg_w = msg.len() * 8 + (msg.len() - 1) * 2;
-    let hx = (fb.width - msg_w) / 2;
-    let hy = fb.height / 2 - 4 + 16;
-    fb.draw_text(hx, hy, msg, color);
-    fb.draw_text(10, 70, "Heap Draw Done", COLOR_BLACK);
+    log::info!("Paging Test Done");
 
     // 物理フレームアロケータテスト
-    fb.draw_text(10, 110, "Allocator Init Start", COLOR_BLACK); // 目印
+    log::debug!("frame allocator init start");
 
     let mut fa_ok = false;
     let mut fa; // unsafe ブロックの外で宣言
@@ -117,22 +179,172 @@ fn efi_main(image_handle: EfiHandle, system_table: &EfiSystemTable) {
         fa_ok = f1.is_some() && f2.is_some() && f1 != f2;
     }
 
-    let msg = if fa_ok { "FrameAlloc OK" } else { "FrameAlloc NG" };
-    let color = if fa_ok { COLOR_GREEN } else { COLOR_RED };
-    let msg_w = msg.len()*8 + (msg.len()-1)*2;
-    let fx = (fb.width - msg_w)/2;
-    let fy = fb.height/2 - 4 + 32;
-    fb.draw_text(fx, fy, msg, color);
+    show_result(&mut fb, 2, "FrameAlloc", fa_ok);
+
+    // 以降はグローバルなフレームアロケータ経由で確保する
+    memory::install_frame_allocator(fa);
+    memory::init_direct_map().expect("direct map setup failed");
+    log::info!("Direct Map OK");
+    time::boot::mark("frame allocator / direct map");
+
+    unsafe { memory::init_heap(); }
+    log::info!("Heap Init OK");
+    // vmalloc が使えるようになったので、すぐに起動用のダブルフォルトスタックから切り替える
+    gdt::install_guarded_stacks().expect("IST stack allocation failed");
+    logger::attach_console(console_fb);
+    time::boot::mark("heap");
+    log::info!("Heap Test Done");
+
+    // 動的確保テスト: reserve 1KiB 分の Vec
+    let mut test_vec: Vec<u64> = Vec::new();
+    let heap_ok = test_vec.try_reserve_exact(1024).is_ok();
 
-    // 以降は Non-UEFI 世界。画面をクリアしてメッセージ表示
     // fb.clear(COLOR_RED);
-    // let label = "Hello, NonUEFI!";
-    // let text_width = label.len() * 8 + (label.len() - 1) * 2;
-    // let x = (fb.width - text_width) / 2;
-    // let y = fb.height / 2 - 4;
-    // fb.draw_text(x, y, label, COLOR_WHITE);
+    // 結果を描画
+    show_result(&mut fb, 1, "Heap", heap_ok);
+    log::info!("Heap Draw Done");
 
-    loop {}
+    // UEFI から渡されたスタックを離れ、ガードページ付きのカーネルスタックで続行する
+    let stack = KernelStack::new("kernel_main", KERNEL_STACK_SIZE).expect("kernel stack allocation failed");
+    log::info!("Kernel Stack OK");
+    time::boot::mark("kernel stack");
+    // Safety: efi_main のフレーム (fb を含む) は戻らないため以後も有効
+    unsafe { stack.switch_to(kernel_main_entry, &mut fb as *mut FrameBuffer<'static> as usize) }
+}
+
+/// カーネルスタック切り替え後の入口
+extern "sysv64" fn kernel_main_entry(fb: usize) -> ! {
+    // Safety: efi_main が渡した FrameBuffer へのポインタ
+    let fb = unsafe { &mut *(fb as *mut FrameBuffer<'static>) };
+    kernel_main(fb)
+}
+
+fn kernel_main(fb: &mut FrameBuffer<'static>) -> ! {
+    vmalloc_smoke_test(fb);
+    log::info!("Vmalloc Test Done");
+    dma_smoke_test(fb);
+    log::info!("DMA Test Done");
+    time::boot::mark("vmalloc / DMA tests");
+
+    if let Err(e) = acpi::init() {
+        log::warn!("ACPI: {}", e);
+    }
+    match apic::init(acpi::madt()) {
+        Ok(mode) => log::info!("Local APIC: {:?}, id {}", mode, apic::id()),
+        Err(e) => log::error!("Local APIC: {}", e),
+    }
+    match acpi::madt().map(irq::init) {
+        Some(Ok(_)) => log::info!("IOAPIC OK"),
+        Some(Err(e)) => log::error!("IOAPIC: {}", e),
+        None => log::error!("IOAPIC: no MADT"),
+    }
+    time::boot::mark("ACPI / APIC / IOAPIC");
+    x86_64::instructions::interrupts::enable();
+    interrupt_smoke_test(fb);
+    log::info!("Interrupt Test Done");
+
+    match time::init() {
+        Ok(tick) => {
+            log::info!(
+                "Timer: {:?} on vector {:#x}, {} APIC counts/ms, {} TSC/ms (calibrated by {})",
+                tick.source,
+                tick.vector,
+                tick.calibration.counts_per_ms,
+                tick.calibration.tsc_per_ms,
+                tick.reference
+            );
+            for source in time::clocksource::available() {
+                log::debug!("clocksource {} (rating {}, {} Hz)", source.name(), source.rating(), source.frequency());
+            }
+            if let Some(tsc) = time::tsc::get() {
+                log::info!("TSC: {} Hz ({:?}), invariant: {}", time::tsc::frequency(), tsc.source, tsc.invariant);
+            }
+            log::info!("Clocksource: {}", tick.clocksource);
+            log::info!("Time: {} UTC", time::rtc::DateTime::from_unix(time::now()));
+        }
+        Err(e) => log::error!("Timer: {}", e),
+    }
+    time::boot::mark("timer");
+    timer_smoke_test(fb);
+
+    match drivers::keyboard::init() {
+        Ok(irq) => {
+            log::info!(
+                "Keyboard: {:?}, {:?} layout, vector {:#x}",
+                drivers::keyboard::scancode_set().unwrap(),
+                drivers::keyboard::layout().unwrap(),
+                irq.vector
+            );
+        }
+        Err(e) => log::error!("Keyboard: {}", e),
+    }
+    match drivers::mouse::init() {
+        Ok((kind, irq)) => log::info!("Mouse: {:?}, vector {:#x}", kind, irq.vector),
+        Err(e) => log::error!("Mouse: {}", e),
+    }
+    match drivers::serial::enable_interrupts() {
+        Ok(irq) => log::info!("Serial: interrupt-driven, vector {:#x}", irq.vector),
+        Err(e) => log::warn!("Serial: {}", e),
+    }
+    time::boot::mark("keyboard / mouse");
+    // 段階ごとの時刻は TSC の値で記録しているので、較正後にまとめて表示する
+    serial_print!("Boot stages:\n{}", time::boot::Report);
+
+    // キー入力 (IME を通したもの) とシリアルからの入力をシェルへ渡す。出力はシリアルとコンソールの両方へ
+    let console = input::subscribe("console");
+    let mut ime = ime::Ime::new();
+    let mut candidate_window = ime::window::CandidateWindow::new(10, 190, COLOR_WHITE);
+    let mut shell = shell::Shell::new();
+    let out = &mut ShellOutput;
+    let _ = shell.prompt(out);
+    loop {
+        while let Some(event) = console.try_next() {
+            match event {
+                input::InputEvent::Key(key) => {
+                    if console::handle_key(&key) {
+                        continue;
+                    }
+                    match ime.process(&key) {
+                        Some(ime::Output::Commit(text)) => {
+                            for c in text.chars() {
+                                let _ = shell.feed(c, out);
+                            }
+                        }
+                        Some(ime::Output::Key(key)) => {
+                            if let Some(c) = key.ch {
+                                let _ = shell.feed(c, out);
+                            }
+                        }
+                        None => {}
+                    }
+                    candidate_window.update(fb, &ime);
+                }
+                input::InputEvent::Motion { .. } => {}
+                other => log::trace!("input: {:?}", other),
+            }
+        }
+        while let Some(byte) = drivers::serial::read_byte() {
+            let _ = shell.feed(byte as char, out);
+        }
+        // 確かめてから hlt までの間に届いた割り込みで眠り込まないよう、割り込みを止めて見直し、
+        // 何も無ければ割り込みの許可と hlt を続けて行う (sti の直後の 1 命令は割り込まれない)
+        x86_64::instructions::interrupts::disable();
+        if console.is_empty() && !drivers::serial::has_input() {
+            x86_64::instructions::interrupts::enable_and_hlt();
+        } else {
+            x86_64::instructions::interrupts::enable();
+        }
+    }
+}
+
+/// シェルの出力をシリアルとコンソールの両方へ書く
+struct ShellOutput;
+
+impl core::fmt::Write for ShellOutput {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        drivers::serial::Writer.write_str(s)?;
+        console::Writer.write_str(s)
+    }
 }
 
 /// ExitBootServices を安全に呼び出すヘルパ
@@ -154,8 +366,8 @@ fn exit_from_efi_boot_services(
 }
 
 #[panic_handler]
-fn panic(_info: &PanicInfo) -> ! {
-    loop {}
+fn panic(info: &PanicInfo) -> ! {
+    panic_screen::handle(info)
 }
 
 #[alloc_error_handler]
@@ -165,6 +377,14 @@ fn alloc_error(_layout: core::alloc::Layout) -> ! {
 }
 
 // ===== テスト関数 =====
+/// テスト結果を画面中央から `row` 行目 (16px 単位、負なら上) に表示する
+fn show_result(fb: &mut FrameBuffer, row: isize, name: &str, ok: bool) {
+    let (status, color) = if ok { ("OK", COLOR_GREEN) } else { ("NG", COLOR_RED) };
+    let center = (fb.height - GLYPH_HEIGHT) / 2;
+    let line = Rect::new(0, center.saturating_add_signed(row * 16), fb.width, LINE_HEIGHT);
+    fb_print!(fb, line, Align::TOP_CENTER, color, "{} {}", name, status);
+}
+
 unsafe fn paging_smoke_test(fb: &mut FrameBuffer) {
     x86_64::instructions::interrupts::disable();
     let test_addr = 0x3FF0_0000 as *mut u64; // 4GiB-1MiB
@@ -172,10 +392,89 @@ unsafe fn paging_smoke_test(fb: &mut FrameBuffer) {
     let ok = test_addr.read_volatile() == 0xDEAD_BEEF_DEAD_BEEF;
     x86_64::instructions::interrupts::enable();
 
-    let msg = if ok { "Paging OK" } else { "Paging NG" };
-    let color = if ok { COLOR_GREEN } else { COLOR_RED };
-    let msg_w = msg.len()*8 + (msg.len()-1)*2;
-    let x = (fb.width - msg_w)/2;
-    let y = fb.height/2 - 4 - 16; // 既存メッセージ上
-    fb.draw_text(x, y, msg, color);
+    show_result(fb, -1, "Paging", ok);
+}
+
+fn vmalloc_smoke_test(fb: &mut FrameBuffer) {
+    // 複数ページにまたがる領域を確保し、先頭と末尾に書き込めるか確認
+    let ok = match memory::vmalloc(64 * 1024) {
+        Some(mut area) => {
+            let last = area.len() - 1;
+            area[0] = 0xAA;
+            area[last] = 0x55;
+            area[0] == 0xAA && area[last] == 0x55 && area[1] == 0
+        }
+        None => false,
+    };
+
+    show_result(fb, 3, "Vmalloc", ok);
+}
+
+fn dma_smoke_test(fb: &mut FrameBuffer) {
+    use memory::dma::{DmaAttrs, DmaSlice};
+
+    // 4GiB 未満・64KiB アライン・キャッシュ無効の 16KiB 領域
+    let attrs = DmaAttrs::new().below_4g().align(0x10000).uncached();
+    let ok = match DmaSlice::<u32>::new(4096, attrs) {
+        Some(mut buf) => {
+            let phys = buf.phys_addr().as_u64();
+            buf[4095] = 0xCAFE_BABE;
+            phys % 0x10000 == 0 && phys < 0x1_0000_0000 && buf[0] == 0 && buf[4095] == 0xCAFE_BABE
+        }
+        None => false,
+    };
+
+    show_result(fb, 4, "DMA", ok);
+}
+
+/// 自分宛て IPI で動的ベクタ・ハンドラ連鎖・割り込みカウンタを確認する
+fn interrupt_smoke_test(fb: &mut FrameBuffer) {
+    use core::sync::atomic::{AtomicU32, Ordering};
+    static HITS: AtomicU32 = AtomicU32::new(0);
+
+    let ok = match interrupts::allocate_vector() {
+        Some(vector) => {
+            let first = interrupts::register_handler(vector, |_: &interrupts::InterruptContext| {
+                HITS.fetch_add(1, Ordering::Relaxed);
+            });
+            let second = interrupts::register_handler(vector, |_: &interrupts::InterruptContext| {
+                HITS.fetch_add(10, Ordering::Relaxed);
+            });
+            let sent = apic::send_ipi(apic::IpiDestination::SelfOnly, apic::Ipi::Fixed(vector)).is_ok();
+            // 配送を待つ (割り込みは許可済み)
+            for _ in 0..1_000_000 {
+                if HITS.load(Ordering::Relaxed) != 0 {
+                    break;
+                }
+                core::hint::spin_loop();
+            }
+            let ok = sent && first.is_ok() && second.is_ok()
+                && HITS.load(Ordering::Relaxed) == 11
+                && interrupts::interrupt_count(vector) == 1;
+            interrupts::free_vector(vector);
+            ok
+        }
+        None => false,
+    };
+    for (vector, count) in interrupts::interrupt_counts() {
+        log::debug!("vector {:#04x}: {} interrupts", vector, count);
+    }
+
+    show_result(fb, 5, "Interrupt", ok);
+}
+
+/// sleep とタイマコールバックの動作を確認する
+fn timer_smoke_test(fb: &mut FrameBuffer) {
+    use core::sync::atomic::{AtomicBool, Ordering};
+    use core::time::Duration;
+    static FIRED: AtomicBool = AtomicBool::new(false);
+
+    time::add_timer(Duration::from_millis(50), || FIRED.store(true, Ordering::Relaxed));
+    let start = time::uptime();
+    time::sleep(Duration::from_millis(100));
+    let elapsed = time::uptime() - start;
+    log::debug!("sleep(100ms) took {:?}", elapsed);
+
+    let ok = FIRED.load(Ordering::Relaxed) && elapsed >= Duration::from_millis(100);
+    show_result(fb, 6, "Timer Test", ok);
 }
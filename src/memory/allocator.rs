@@ -1,6 +1,6 @@
 use bitvec::vec::BitVec;
 use bitvec::prelude::*;
-use x86_64::structures::paging::{PhysFrame, FrameAllocator, Size4KiB};
+use x86_64::structures::paging::{PhysFrame, FrameAllocator, FrameDeallocator, Size4KiB};
 use x86_64::PhysAddr;
 use crate::efi::{MemoryMapHolder, EfiMemoryDescriptor};
 use crate::graphics::{FrameBuffer, COLOR_BLACK, COLOR_RED, COLOR_GREEN};
@@ -12,6 +12,7 @@ static mut BITMAP_STORAGE: [u8; BITMAP_STORAGE_SIZE_BYTES] = [0; BITMAP_STORAGE_
 pub struct BitmapFrameAllocator<'a> {
     bitmap: &'a mut BitSlice<u8, Lsb0>,
     frame_count: usize, // アロケーション時に境界チェックするため保持
+    next: usize,        // 次回探索を開始するフレーム番号 (線形探索の短縮用)
 }
 
 impl<'a> BitmapFrameAllocator<'a> {
@@ -48,7 +49,7 @@ impl<'a> BitmapFrameAllocator<'a> {
 
         
         // 静的ストレージから BitVec を作成
-        let bytes_needed = (frame_count + 7) / 8;
+        let bytes_needed = frame_count.div_ceil(8);
         if bytes_needed > BITMAP_STORAGE_SIZE_BYTES {
             // ここで panic する代わりにエラー処理をするのが望ましい
             panic!("Bitmap storage too small! Needed: {}, Available: {}", bytes_needed, BITMAP_STORAGE_SIZE_BYTES);
@@ -87,7 +88,7 @@ impl<'a> BitmapFrameAllocator<'a> {
 
         // fb.draw_text(10, 180, "HLT Executed (Should not see)", COLOR_RED); // hlt 実行確認 (後)
 
-        Self { bitmap, frame_count }
+        Self { bitmap, frame_count, next: 0 }
     }
 
     pub fn count_free_frames(&self) -> usize {
@@ -97,17 +98,67 @@ impl<'a> BitmapFrameAllocator<'a> {
     fn mark_used(&mut self, index: usize) {
         self.bitmap.set(index, true);
     }
+
+    /// 物理的に連続した `count` フレームを確保し、先頭フレームを返す
+    ///
+    /// 先頭は `align` バイト境界に揃え、範囲全体が `limit` 未満の物理アドレスに収まるものを探す。
+    pub fn allocate_contiguous(&mut self, count: usize, align: u64, limit: u64) -> Option<PhysFrame> {
+        if count == 0 {
+            return None;
+        }
+        let step = (align / 4096).max(1) as usize;
+        let end = self.frame_count.min((limit / 4096) as usize);
+        let mut idx = 0;
+        while idx + count <= end {
+            match (idx..idx + count).rev().find(|&i| self.bitmap[i]) {
+                // 使用中フレームの次から、アラインメントを保って探索を再開
+                Some(used) => idx = (used + 1).div_ceil(step) * step,
+                None => {
+                    for i in idx..idx + count {
+                        self.mark_used(i);
+                    }
+                    let addr = (idx as u64) * 4096;
+                    return Some(PhysFrame::containing_address(PhysAddr::new(addr)));
+                }
+            }
+        }
+        None
+    }
+
+    /// 物理範囲 `[start, start + size)` を含むフレームを使用中にする
+    pub fn reserve_range(&mut self, start: u64, size: u64) {
+        let first = (start / 4096) as usize;
+        let last = (start + size).div_ceil(4096) as usize;
+        for idx in first..last.min(self.frame_count) {
+            self.mark_used(idx);
+        }
+    }
 }
 
 unsafe impl<'a> FrameAllocator<Size4KiB> for BitmapFrameAllocator<'a> {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
-        for idx in 0..self.frame_count { // bitmap.len() の代わりに保持している frame_count を使う
+        // 前回の位置から末尾まで、見つからなければ先頭から探索する
+        let start = self.next;
+        for idx in (start..self.frame_count).chain(0..start) { // bitmap.len() の代わりに保持している frame_count を使う
             if !self.bitmap[idx] {
                 self.mark_used(idx);
+                self.next = idx + 1;
                 let addr = (idx as u64) * 4096;
                 return Some(PhysFrame::containing_address(PhysAddr::new(addr)));
             }
         }
         None
     }
-} 
\ No newline at end of file
+}
+
+impl<'a> FrameDeallocator<Size4KiB> for BitmapFrameAllocator<'a> {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let idx = (frame.start_address().as_u64() / 4096) as usize;
+        if idx < self.frame_count {
+            self.bitmap.set(idx, false);
+            if idx < self.next {
+                self.next = idx;
+            }
+        }
+    }
+}
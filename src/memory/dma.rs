@@ -0,0 +1,224 @@
+#![allow(dead_code)]
+//! DMA 用バッファ
+//!
+//! - virtio / AHCI / NVMe などのドライバ向けに、物理的に連続した領域を
+//!   フレームアロケータから確保し、仮想アドレスと物理アドレスの両方を公開する。
+//! - アラインメント・物理アドレス上限 (4GiB 未満など)・キャッシュ無効化を指定できる。
+//! - 確保時にゼロクリアし、drop でフレームを返却する。
+
+use core::marker::PhantomData;
+use core::mem::{align_of, size_of};
+use core::ops::{Deref, DerefMut};
+use x86_64::structures::paging::{FrameDeallocator, PageTableFlags, PhysFrame};
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::vmalloc::{self, VmArea};
+use super::{phys_to_virt, with_frame_allocator};
+
+const PAGE_SIZE: u64 = 4096;
+
+/// DMA 領域に置いてよい型 (全ビット 0 が有効な値で、ポインタを含まない)
+///
+/// # Safety
+///
+/// 実装する型は次をすべて満たすこと。
+/// - 全ビット 0 が有効な値である (領域はゼロ初期化して渡す)。
+/// - 任意のビットパターンが有効な値である (デバイスがどんな値を書いても未定義動作にならない)。
+/// - 参照・ポインタ・`Drop` を持つ値を含まない。
+pub unsafe trait DmaSafe: Sized {}
+
+macro_rules! impl_dma_safe {
+    ($($t:ty),*) => { $(unsafe impl DmaSafe for $t {})* };
+}
+impl_dma_safe!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+unsafe impl<T: DmaSafe, const N: usize> DmaSafe for [T; N] {}
+
+/// DMA 領域の確保条件
+#[derive(Clone, Copy, Debug)]
+pub struct DmaAttrs {
+    /// 物理アドレスのアラインメント (バイト、4KiB 未満は 4KiB として扱う)
+    pub align: u64,
+    /// 領域全体が収まるべき物理アドレスの上限 (排他的)
+    pub limit: u64,
+    /// キャッシュ無効 (PCD|PWT) でマップするか
+    pub uncached: bool,
+}
+
+impl DmaAttrs {
+    /// 4KiB アライン・上限なし・キャッシュ有効
+    pub const fn new() -> Self {
+        Self { align: PAGE_SIZE, limit: u64::MAX, uncached: false }
+    }
+
+    /// 物理アドレスを 4GiB 未満に制限する (32bit DMA しかできないデバイス向け)
+    pub const fn below_4g(mut self) -> Self {
+        self.limit = 0x1_0000_0000;
+        self
+    }
+
+    pub const fn align(mut self, align: u64) -> Self {
+        self.align = align;
+        self
+    }
+
+    pub const fn uncached(mut self) -> Self {
+        self.uncached = true;
+        self
+    }
+}
+
+/// 物理的に連続した DMA 領域 (型なし)
+struct DmaRegion {
+    phys: PhysFrame,
+    frames: u64,
+    /// キャッシュ無効で別名マップした場合のマッピング
+    mapping: Option<VmArea>,
+}
+
+impl DmaRegion {
+    fn new(size: usize, attrs: DmaAttrs) -> Option<Self> {
+        let frames = (size.max(1) as u64).div_ceil(PAGE_SIZE);
+        let phys = with_frame_allocator(|fa| {
+            fa.allocate_contiguous(frames as usize, attrs.align, attrs.limit)
+        })??;
+
+        let mapping = if attrs.uncached {
+            let flags = PageTableFlags::WRITABLE
+                | PageTableFlags::NO_CACHE
+                | PageTableFlags::WRITE_THROUGH;
+            // Safety: phys は今確保したフレームで、DmaRegion の drop まで返却しない
+            // NOTE: アイデンティティマップ・ダイレクトマップ側 (WB) との別名になるため、CPU からは必ずこちらを使う
+            match unsafe { vmalloc::map_phys(phys.start_address(), (frames * PAGE_SIZE) as usize, flags) } {
+                Some(area) => Some(area),
+                None => {
+                    free_frames(phys, frames);
+                    return None;
+                }
+            }
+        } else {
+            None
+        };
+
+        let region = Self { phys, frames, mapping };
+        // Safety: 領域全体がマップ済みで、まだ誰にも共有していない
+        unsafe { core::ptr::write_bytes(region.virt_addr().as_mut_ptr::<u8>(), 0, region.size()) };
+        Some(region)
+    }
+
+    fn phys_addr(&self) -> PhysAddr {
+        self.phys.start_address()
+    }
+
+    fn virt_addr(&self) -> VirtAddr {
+        match &self.mapping {
+            Some(area) => area.start_addr(),
+            None => phys_to_virt(self.phys.start_address()),
+        }
+    }
+
+    fn size(&self) -> usize {
+        (self.frames * PAGE_SIZE) as usize
+    }
+}
+
+impl Drop for DmaRegion {
+    fn drop(&mut self) {
+        // 先に別名マッピングを外してからフレームを返却する
+        self.mapping = None;
+        free_frames(self.phys, self.frames);
+    }
+}
+
+fn free_frames(first: PhysFrame, frames: u64) {
+    with_frame_allocator(|fa| {
+        for i in 0..frames {
+            unsafe { fa.deallocate_frame(first + i) };
+        }
+    });
+}
+
+/// 単一の `T` を保持する DMA バッファ
+pub struct DmaBuffer<T: DmaSafe> {
+    region: DmaRegion,
+    _marker: PhantomData<T>,
+}
+
+impl<T: DmaSafe> DmaBuffer<T> {
+    /// ゼロ初期化された `T` を確保する
+    pub fn new(attrs: DmaAttrs) -> Option<Self> {
+        let attrs = attrs.align(attrs.align.max(align_of::<T>() as u64));
+        let region = DmaRegion::new(size_of::<T>(), attrs)?;
+        Some(Self { region, _marker: PhantomData })
+    }
+
+    /// デバイスに渡す物理アドレス
+    pub fn phys_addr(&self) -> PhysAddr {
+        self.region.phys_addr()
+    }
+
+    /// CPU からアクセスする仮想アドレス
+    pub fn virt_addr(&self) -> VirtAddr {
+        self.region.virt_addr()
+    }
+}
+
+impl<T: DmaSafe> Deref for DmaBuffer<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safety: 領域は T のサイズ・アラインメントを満たし、ゼロ初期化済み
+        unsafe { &*self.region.virt_addr().as_ptr::<T>() }
+    }
+}
+
+impl<T: DmaSafe> DerefMut for DmaBuffer<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.region.virt_addr().as_mut_ptr::<T>() }
+    }
+}
+
+/// `T` の配列を保持する DMA バッファ (ディスクリプタリングなど)
+pub struct DmaSlice<T: DmaSafe> {
+    region: DmaRegion,
+    len: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T: DmaSafe> DmaSlice<T> {
+    /// ゼロ初期化された要素 `len` 個分を確保する
+    pub fn new(len: usize, attrs: DmaAttrs) -> Option<Self> {
+        let size = size_of::<T>().checked_mul(len)?;
+        let attrs = attrs.align(attrs.align.max(align_of::<T>() as u64));
+        let region = DmaRegion::new(size, attrs)?;
+        Some(Self { region, len, _marker: PhantomData })
+    }
+
+    /// 先頭要素の物理アドレス
+    pub fn phys_addr(&self) -> PhysAddr {
+        self.region.phys_addr()
+    }
+
+    /// `index` 番目の要素の物理アドレス
+    pub fn phys_addr_of(&self, index: usize) -> PhysAddr {
+        self.region.phys_addr() + (index * size_of::<T>()) as u64
+    }
+
+    pub fn virt_addr(&self) -> VirtAddr {
+        self.region.virt_addr()
+    }
+}
+
+impl<T: DmaSafe> Deref for DmaSlice<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        // Safety: 領域は len 個の T を格納でき、ゼロ初期化済み
+        unsafe { core::slice::from_raw_parts(self.region.virt_addr().as_ptr(), self.len) }
+    }
+}
+
+impl<T: DmaSafe> DerefMut for DmaSlice<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { core::slice::from_raw_parts_mut(self.region.virt_addr().as_mut_ptr(), self.len) }
+    }
+}
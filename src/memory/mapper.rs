@@ -1,29 +1,100 @@
+use core::ptr::addr_of_mut;
+use spin::Mutex;
 use x86_64::registers::control::{Cr3, Cr3Flags};
-use x86_64::structures::paging::{PhysFrame};
+use x86_64::structures::paging::{OffsetPageTable, PageTable as X86PageTable, PhysFrame};
 use x86_64::{PhysAddr, VirtAddr};
+use crate::cpu;
+use crate::image::{KernelImage, SectionKind};
 
 const PAGE_PRESENT: u64 = 1;
 const PAGE_WRITABLE: u64 = 1 << 1;
 const PAGE_HUGE: u64 = 1 << 7;
+const PAGE_NO_EXECUTE: u64 = 1 << 63;
+
+const SIZE_2MIB: u64 = 2 * 1024 * 1024;
 
 #[repr(align(4096))]
 struct PageTable([u64; 512]);
 
 static mut PML4_TABLE: PageTable = PageTable([0; 512]);
 static mut PDP_TABLE: PageTable = PageTable([0; 512]);
+// 4GiB 分の 2MiB ページディレクトリ
+static mut PD_TABLES: [PageTable; 4] = [const { PageTable([0; 512]) }; 4];
+// カーネルイメージを 4KiB 単位で保護するための PT (最大 64MiB 分)
+const PT_POOL_SIZE: usize = 32;
+static mut PT_POOL: [PageTable; PT_POOL_SIZE] = [const { PageTable([0; 512]) }; PT_POOL_SIZE];
 
-/// 簡易アイデンティティマッピング (4GiB) を設定し CR3 を更新
+/// アイデンティティマッピング (4GiB) を設定し CR3 を更新
+///
+/// 全体を 2MiB ページの RW+NX でマップし、カーネルイメージを含む範囲だけ 4KiB ページに
+/// 分割してセクション属性 (text: RX / rodata: R / data: RW+NX) を反映する。
+/// NX は CPU が対応している場合のみ有効化する。
 pub unsafe fn init_paging() {
-    for i in 0..4 {
-        let addr = (i as u64) << 30;
-        PDP_TABLE.0[i] = addr | PAGE_PRESENT | PAGE_WRITABLE | PAGE_HUGE;
+    let nx = if cpu::enable_nx() { PAGE_NO_EXECUTE } else { 0 };
+
+    let pd_tables = &mut *addr_of_mut!(PD_TABLES);
+    for (i, pd) in pd_tables.iter_mut().enumerate() {
+        for (j, entry) in pd.0.iter_mut().enumerate() {
+            let addr = ((i as u64) << 30) + (j as u64) * SIZE_2MIB;
+            *entry = addr | PAGE_PRESENT | PAGE_WRITABLE | PAGE_HUGE | nx;
+        }
+        PDP_TABLE.0[i] = (pd as *const _ as u64) | PAGE_PRESENT | PAGE_WRITABLE;
     }
-    PML4_TABLE.0[0] = (&PDP_TABLE as *const _ as u64) | PAGE_PRESENT | PAGE_WRITABLE;
 
-    let pml4_frame = PhysFrame::containing_address(PhysAddr::new(&PML4_TABLE as *const _ as u64));
+    protect_kernel_image(nx);
+
+    PML4_TABLE.0[0] = (&raw const PDP_TABLE as u64) | PAGE_PRESENT | PAGE_WRITABLE;
+
+    let pml4_frame = PhysFrame::containing_address(PhysAddr::new(&raw const PML4_TABLE as u64));
     Cr3::write(pml4_frame, Cr3Flags::empty());
 }
 
+/// カーネルイメージを含む 2MiB ページを 4KiB ページに分割し、セクションごとの属性を設定する
+unsafe fn protect_kernel_image(nx: u64) {
+    let image = KernelImage::current();
+    let first = image.base & !(SIZE_2MIB - 1);
+    let last = (image.end() + SIZE_2MIB - 1) & !(SIZE_2MIB - 1);
+    let regions = ((last - first) / SIZE_2MIB) as usize;
+    assert!(regions <= PT_POOL_SIZE, "kernel image too large for PT_POOL");
+
+    let pt_pool = &mut *addr_of_mut!(PT_POOL);
+    let pd_tables = &mut *addr_of_mut!(PD_TABLES);
+    for (k, pt) in pt_pool.iter_mut().enumerate().take(regions) {
+        let region = first + (k as u64) * SIZE_2MIB;
+        for (j, entry) in pt.0.iter_mut().enumerate() {
+            let addr = region + (j as u64) * 4096;
+            let flags = if (image.base..image.end()).contains(&addr) {
+                match image.kind_of(addr) {
+                    Some(SectionKind::Text) => PAGE_PRESENT,
+                    Some(SectionKind::Data) => PAGE_PRESENT | PAGE_WRITABLE | nx,
+                    // rodata とヘッダなどセクション外の部分は読み取り専用
+                    Some(SectionKind::ReadOnly) | None => PAGE_PRESENT | nx,
+                }
+            } else {
+                PAGE_PRESENT | PAGE_WRITABLE | nx
+            };
+            *entry = addr | flags;
+        }
+        let pd = &mut pd_tables[(region >> 30) as usize];
+        pd.0[((region >> 21) & 511) as usize] = (pt as *const _ as u64) | PAGE_PRESENT | PAGE_WRITABLE;
+    }
+}
+
+/// カーネルページテーブル操作の排他用ロック
+static KERNEL_PAGE_TABLE_LOCK: Mutex<()> = Mutex::new(());
+
+/// カーネルのページテーブルを `OffsetPageTable` として操作する
+///
+/// ページテーブル用フレームは 4GiB のアイデンティティマップ内にあるため offset 0 で扱える。
+/// `init_paging` 後に呼び出すこと。ロック順は ページテーブル → フレームアロケータ。
+pub fn with_kernel_page_table<R>(f: impl FnOnce(&mut OffsetPageTable<'static>) -> R) -> R {
+    let _guard = KERNEL_PAGE_TABLE_LOCK.lock();
+    // Safety: PML4_TABLE は init_paging で CR3 にロード済みで、ロックにより可変参照は 1 つに限られる
+    let pml4 = unsafe { &mut *(&raw mut PML4_TABLE as *mut X86PageTable) };
+    let mut table = unsafe { OffsetPageTable::new(pml4, VirtAddr::zero()) };
+    f(&mut table)
+}
+
 /// 小便利メソッド: 丸め処理など
 pub trait VirtAddrExt {
     fn align_down(self, align: u64) -> Self;
@@ -1,20 +1,100 @@
 // ---- submodules ----
 pub mod mapper;
 pub mod allocator;
+pub mod vmalloc;
+pub mod dma;
+pub mod stack;
+pub mod usercopy;
 
-pub use mapper::{init_paging, VirtAddrExt};
+pub use mapper::{init_paging, with_kernel_page_table, VirtAddrExt};
 pub use allocator::BitmapFrameAllocator;
+pub use vmalloc::vmalloc;
 
+use core::alloc::{GlobalAlloc, Layout};
 use linked_list_allocator::LockedHeap;
+use x86_64::instructions::interrupts::without_interrupts;
+use spin::Mutex;
+use x86_64::structures::paging::{Mapper, Page, PageTableFlags, PhysFrame, Size2MiB};
+use x86_64::{PhysAddr, VirtAddr};
+use crate::{cpu, kaslr};
 
-// ヒープ領域 (仮): 0x0080_0000 (8MiB) - +100KiB
-pub const HEAP_START: usize = 0x0080_0000;
+// ヒープ領域: 仮想ベースは KASLR で決定する (`kaslr::layout().heap_base`)
 pub const HEAP_SIZE: usize = 100 * 1024; // 100KiB
 
 #[global_allocator]
-static GLOBAL_ALLOCATOR: LockedHeap = LockedHeap::empty();
+static GLOBAL_ALLOCATOR: IrqSafeHeap = IrqSafeHeap(LockedHeap::empty());
 
-/// ヒープを初期化
+/// 割り込みハンドラ (タイマコールバックなど) からも確保・解放できるよう、
+/// ヒープのロック中は割り込みを禁止する
+struct IrqSafeHeap(LockedHeap);
+
+unsafe impl GlobalAlloc for IrqSafeHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        without_interrupts(|| self.0.alloc(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        without_interrupts(|| self.0.dealloc(ptr, layout))
+    }
+}
+
+/// ヒープの先頭仮想アドレス
+pub fn heap_start() -> usize {
+    kaslr::layout().heap_base as usize
+}
+
+/// ヒープ領域にフレームをマップして初期化
+///
+/// フレームアロケータの登録後に呼び出すこと。
 pub unsafe fn init_heap() {
-    GLOBAL_ALLOCATOR.lock().init(HEAP_START as *mut u8, HEAP_SIZE);
-} 
\ No newline at end of file
+    let start = VirtAddr::new(heap_start() as u64);
+    let pages = (HEAP_SIZE as u64).div_ceil(4096);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    if vmalloc::map_pages(start, pages, None, flags) != pages {
+        panic!("failed to map kernel heap");
+    }
+    GLOBAL_ALLOCATOR.0.lock().init(start.as_mut_ptr(), HEAP_SIZE);
+}
+
+/// 物理メモリ 4GiB を KASLR で決めたベースに 2MiB ページでマップする
+///
+/// フレームアロケータの登録後に呼び出すこと。
+pub fn init_direct_map() -> Option<()> {
+    let base = kaslr::layout().direct_map_base;
+    let mut flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    if cpu::nx_enabled() {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+    with_kernel_page_table(|table| {
+        with_frame_allocator(|fa| {
+            for offset in (0..kaslr::DIRECT_MAP_SIZE).step_by(1 << 21) {
+                let page = Page::<Size2MiB>::containing_address(VirtAddr::new(base + offset));
+                let frame = PhysFrame::<Size2MiB>::containing_address(PhysAddr::new(offset));
+                // Safety: ダイレクトマップ領域は他の用途に使われていない
+                unsafe { table.map_to(page, frame, flags, fa) }.ok()?.flush();
+            }
+            Some(())
+        })?
+    })
+}
+
+/// 物理アドレスをダイレクトマップ上の仮想アドレスに変換する
+pub fn phys_to_virt(phys: PhysAddr) -> VirtAddr {
+    VirtAddr::new(kaslr::layout().direct_map_base + phys.as_u64())
+}
+
+/// カーネル全体で共有する物理フレームアロケータ
+static FRAME_ALLOCATOR: Mutex<Option<BitmapFrameAllocator<'static>>> = Mutex::new(None);
+
+/// 初期化済みのフレームアロケータをグローバルに登録する
+///
+/// フレーム 0 は以後払い出されないよう予約しておく。
+pub fn install_frame_allocator(mut fa: BitmapFrameAllocator<'static>) {
+    fa.reserve_range(0, 4096);
+    *FRAME_ALLOCATOR.lock() = Some(fa);
+}
+
+/// グローバルフレームアロケータを借用して `f` を実行する (未登録なら `None`)
+pub fn with_frame_allocator<R>(f: impl FnOnce(&mut BitmapFrameAllocator<'static>) -> R) -> Option<R> {
+    FRAME_ALLOCATOR.lock().as_mut().map(f)
+}
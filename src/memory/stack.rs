@@ -0,0 +1,115 @@
+#![allow(dead_code)]
+//! カーネルスタック
+//!
+//! - vmalloc とは別のスタック領域 (ベースは KASLR で決定) から確保し、各スタックの直下には
+//!   未マップのガードページがある。
+//! - 確保したスタックは名前付きで登録され、ページフォルト/ダブルフォルトハンドラが
+//!   フォルトアドレスからどのスタックのガードに当たったかを引けるようにする。
+
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::VirtAddr;
+
+use super::vmalloc::{self, VmArea, GUARD_PAGES};
+
+/// カーネルメインスタックの既定サイズ
+pub const KERNEL_STACK_SIZE: usize = 64 * 1024;
+/// 例外用 IST スタックの既定サイズ
+pub const IST_STACK_SIZE: usize = 16 * 1024;
+
+const PAGE_SIZE: u64 = 4096;
+
+/// 登録済みスタックの情報
+#[derive(Clone, Copy, Debug)]
+pub struct StackInfo {
+    pub name: &'static str,
+    /// 使用可能領域の最下位アドレス (この直下がガードページ)
+    pub bottom: VirtAddr,
+    /// スタックトップ (RSP の初期値)
+    pub top: VirtAddr,
+}
+
+impl StackInfo {
+    /// `addr` が直下のガードページに含まれるか
+    pub fn guard_contains(&self, addr: VirtAddr) -> bool {
+        let guard_start = self.bottom.as_u64() - GUARD_PAGES * PAGE_SIZE;
+        (guard_start..self.bottom.as_u64()).contains(&addr.as_u64())
+    }
+
+    /// `addr` がスタック本体に含まれるか
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        (self.bottom..self.top).contains(&addr)
+    }
+}
+
+static STACKS: Mutex<Vec<StackInfo>> = Mutex::new(Vec::new());
+
+/// ガードページ付きのカーネルスタック
+pub struct KernelStack {
+    area: VmArea,
+    name: &'static str,
+}
+
+impl KernelStack {
+    /// `size` バイト (ページ単位に切り上げ) のスタックを確保して登録する
+    pub fn new(name: &'static str, size: usize) -> Option<Self> {
+        let area = vmalloc::vmalloc_stack(size)?;
+        let stack = Self { area, name };
+        STACKS.lock().push(stack.info());
+        Some(stack)
+    }
+
+    pub fn info(&self) -> StackInfo {
+        StackInfo { name: self.name, bottom: self.area.start_addr(), top: self.area.end_addr() }
+    }
+
+    pub fn top(&self) -> VirtAddr {
+        self.area.end_addr()
+    }
+
+    /// 解放せずに永続化し、スタックトップを返す (IST やメインスタック用)
+    pub fn leak(self) -> VirtAddr {
+        let top = self.top();
+        core::mem::forget(self);
+        top
+    }
+
+    /// このスタックに切り替えて `entry(arg)` を実行する (戻らない)
+    ///
+    /// Safety: 現在のスタック上のデータを `arg` 経由で参照する場合、呼び出し元の
+    /// フレームは破棄されないので有効なままだが、二度と戻らない点に注意すること。
+    pub unsafe fn switch_to(self, entry: extern "sysv64" fn(usize) -> !, arg: usize) -> ! {
+        let top = self.leak();
+        core::arch::asm!(
+            "mov rsp, {top}",
+            "xor ebp, ebp",
+            "call {entry}",
+            "ud2",
+            top = in(reg) top.as_u64(),
+            entry = in(reg) entry,
+            in("rdi") arg,
+            options(noreturn),
+        )
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        let bottom = self.area.start_addr();
+        STACKS.lock().retain(|s| s.bottom != bottom);
+    }
+}
+
+/// `addr` がいずれかのスタックのガードページに当たっていればそのスタックを返す
+///
+/// 例外ハンドラから呼ばれるため、ロックが取れない場合は諦めて `None` を返す。
+pub fn guard_hit(addr: VirtAddr) -> Option<StackInfo> {
+    let stacks = STACKS.try_lock()?;
+    stacks.iter().copied().find(|s| s.guard_contains(addr))
+}
+
+/// `addr` を含む登録済みスタックを返す
+pub fn find(addr: VirtAddr) -> Option<StackInfo> {
+    let stacks = STACKS.try_lock()?;
+    stacks.iter().copied().find(|s| s.contains(addr))
+}
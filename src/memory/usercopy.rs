@@ -0,0 +1,68 @@
+#![allow(dead_code)]
+//! ユーザ空間メモリへのアクセスヘルパ
+//!
+//! - SMAP 有効時、カーネルはユーザページに直接触れられないため `stac`/`clac` で
+//!   一時的にアクセスを許可する。許可区間は `UserAccessGuard` の生存期間に限定する。
+//! - ポインタがユーザ空間 (下位半分) に収まっているかを検査してからコピーする。
+
+use core::arch::asm;
+use crate::cpu;
+
+/// ユーザ空間の上限 (正準アドレスの下位半分)
+pub const USER_SPACE_END: u64 = 0x0000_8000_0000_0000;
+
+/// 生存中だけユーザページへのアクセスを許可するガード
+pub struct UserAccessGuard(());
+
+impl UserAccessGuard {
+    pub fn new() -> Self {
+        if cpu::smap_enabled() {
+            // Safety: SMAP 対応 CPU でのみ実行する (非対応なら #UD)。
+            // nomem を付けないことでメモリバリアとし、ユーザ領域へのアクセスが stac より前に移されないようにする
+            unsafe { asm!("stac", options(nostack)) };
+        }
+        Self(())
+    }
+}
+
+impl Drop for UserAccessGuard {
+    fn drop(&mut self) {
+        if cpu::smap_enabled() {
+            // Safety: SMAP 対応 CPU でのみ実行する。stac と同じくメモリバリアとして扱わせ、
+            // ユーザ領域へのアクセスが clac より後に移されないようにする
+            unsafe { asm!("clac", options(nostack)) };
+        }
+    }
+}
+
+/// `[addr, addr + len)` がユーザ空間に収まるか
+pub fn is_user_range(addr: u64, len: usize) -> bool {
+    match addr.checked_add(len as u64) {
+        Some(end) => end <= USER_SPACE_END,
+        None => false,
+    }
+}
+
+/// ユーザ空間の `src` から `dst.len()` バイトをコピーする
+///
+/// Safety: `src` の範囲がマップ済みであること (フォルト時の回復処理はまだない)。
+pub unsafe fn copy_from_user(dst: &mut [u8], src: *const u8) -> Result<(), &'static str> {
+    if !is_user_range(src as u64, dst.len()) {
+        return Err("source is not a user address");
+    }
+    let _guard = UserAccessGuard::new();
+    core::ptr::copy_nonoverlapping(src, dst.as_mut_ptr(), dst.len());
+    Ok(())
+}
+
+/// `src` をユーザ空間の `dst` にコピーする
+///
+/// Safety: `dst` の範囲が書き込み可能でマップ済みであること。
+pub unsafe fn copy_to_user(dst: *mut u8, src: &[u8]) -> Result<(), &'static str> {
+    if !is_user_range(dst as u64, src.len()) {
+        return Err("destination is not a user address");
+    }
+    let _guard = UserAccessGuard::new();
+    core::ptr::copy_nonoverlapping(src.as_ptr(), dst, src.len());
+    Ok(())
+}
@@ -0,0 +1,318 @@
+#![allow(dead_code)]
+//! vmalloc: 仮想的に連続した大きな領域の確保
+//!
+//! - カーネル仮想領域 (ベースは KASLR で決定) から範囲を予約し、
+//!   4KiB フレームを 1 枚ずつ `BitmapFrameAllocator` から確保してマップする。
+//! - 領域の前後には未マップのガードページを置き、オーバーランをページフォルトにする。
+//! - `VmArea` の drop でアンマップ・フレーム返却・仮想範囲の返却を行う。
+//! - カーネルスタックは `vmalloc_stack` で別の領域 (ベースは KASLR で vmalloc と独立に決定) から確保する。
+//! - `map_phys` で既存の物理範囲を属性付き (キャッシュ無効など) でマップすることもできる。
+//!   解放しない MMIO 領域は `map_mmio_permanent` でマップし、仮想アドレスだけ受け取る。
+
+use alloc::vec::Vec;
+use core::ops::{Deref, DerefMut};
+use spin::Mutex;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::{with_frame_allocator, with_kernel_page_table};
+use crate::{cpu, kaslr};
+
+const PAGE_SIZE: u64 = 4096;
+
+/// vmalloc 用カーネル仮想領域の先頭
+pub fn vmalloc_start() -> u64 {
+    kaslr::layout().vmalloc_base
+}
+
+/// vmalloc 用カーネル仮想領域の終端
+pub fn vmalloc_end() -> u64 {
+    vmalloc_start() + kaslr::VMALLOC_SIZE
+}
+
+/// カーネルスタック領域の先頭・終端
+fn stack_bounds() -> (u64, u64) {
+    let base = kaslr::layout().stack_base;
+    (base, base + kaslr::STACK_AREA_SIZE)
+}
+
+fn vmalloc_bounds() -> (u64, u64) {
+    (vmalloc_start(), vmalloc_end())
+}
+
+/// 各領域の前後に置くガードページ数
+pub const GUARD_PAGES: u64 = 1;
+
+/// 仮想範囲アロケータ (空きリスト + バンプポインタ)
+struct VirtRangeAllocator {
+    /// 返却済みの空き範囲 `(start, end)`。開始アドレス順に並び、隣接範囲は結合済み
+    free: Vec<(u64, u64)>,
+    /// 未使用領域の先頭 (0 なら未初期化)
+    next: u64,
+    /// 管理する仮想領域 (KASLR の決定後に読む)
+    bounds: fn() -> (u64, u64),
+}
+
+impl VirtRangeAllocator {
+    const fn new(bounds: fn() -> (u64, u64)) -> Self {
+        Self { free: Vec::new(), next: 0, bounds }
+    }
+
+    /// `size` バイト (ページ単位) の範囲を first-fit で確保
+    fn alloc(&mut self, size: u64) -> Option<u64> {
+        if let Some(i) = self.free.iter().position(|&(s, e)| e - s >= size) {
+            let (start, end) = self.free[i];
+            if end - start == size {
+                self.free.remove(i);
+            } else {
+                self.free[i].0 += size;
+            }
+            return Some(start);
+        }
+        let (region_start, region_end) = (self.bounds)();
+        if self.next == 0 {
+            self.next = region_start;
+        }
+        let start = self.next;
+        let end = start.checked_add(size)?;
+        if end > region_end {
+            return None;
+        }
+        self.next = end;
+        Some(start)
+    }
+
+    /// 範囲を返却し、前後の空き範囲と結合する
+    fn free(&mut self, start: u64, size: u64) {
+        let end = start + size;
+        let i = self.free.partition_point(|&(s, _)| s < start);
+        self.free.insert(i, (start, end));
+        if i + 1 < self.free.len() && self.free[i + 1].0 == end {
+            self.free[i].1 = self.free[i + 1].1;
+            self.free.remove(i + 1);
+        }
+        if i > 0 && self.free[i - 1].1 == start {
+            self.free[i - 1].1 = self.free[i].1;
+            self.free.remove(i);
+        }
+    }
+}
+
+static VIRT_RANGES: Mutex<VirtRangeAllocator> = Mutex::new(VirtRangeAllocator::new(vmalloc_bounds));
+static STACK_RANGES: Mutex<VirtRangeAllocator> = Mutex::new(VirtRangeAllocator::new(stack_bounds));
+
+/// vmalloc で確保した仮想的に連続な領域
+///
+/// drop 時にアンマップされ、フレームと仮想範囲が返却される。
+pub struct VmArea {
+    /// マップした先頭ページ (先頭ガードページの直後)
+    start: VirtAddr,
+    /// 利用可能領域の先頭ページ内オフセット (`map_phys` の物理アドレスの端数)
+    offset: u64,
+    /// マップ済みページ数 (ガードページを含まない)
+    pages: u64,
+    /// drop 時にフレームを返却するか (`map_phys` で借りた物理範囲なら false)
+    owns_frames: bool,
+    /// 仮想範囲の返却先
+    ranges: &'static Mutex<VirtRangeAllocator>,
+}
+
+impl VmArea {
+    /// 利用可能領域の先頭 (`map_phys` なら渡した物理アドレスに対応する仮想アドレス)
+    pub fn start_addr(&self) -> VirtAddr {
+        self.start + self.offset
+    }
+
+    /// 利用可能領域の終端 (末尾ガードページの先頭)
+    pub fn end_addr(&self) -> VirtAddr {
+        self.start + self.pages * PAGE_SIZE
+    }
+
+    /// 利用可能なバイト数 (末尾はページ境界まで切り上げ済み)
+    pub fn size(&self) -> usize {
+        (self.pages * PAGE_SIZE - self.offset) as usize
+    }
+
+    pub fn as_ptr(&self) -> *const u8 {
+        self.start_addr().as_ptr()
+    }
+
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.start_addr().as_mut_ptr()
+    }
+
+    /// ガードページを含む予約範囲全体の先頭
+    fn reserved_start(&self) -> u64 {
+        self.start.as_u64() - GUARD_PAGES * PAGE_SIZE
+    }
+
+    /// ガードページを含む予約範囲全体のバイト数
+    fn reserved_size(&self) -> u64 {
+        (self.pages + 2 * GUARD_PAGES) * PAGE_SIZE
+    }
+}
+
+impl Deref for VmArea {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // Safety: [start, start + size) は drop までマップされ続ける
+        unsafe { core::slice::from_raw_parts(self.as_ptr(), self.size()) }
+    }
+}
+
+impl DerefMut for VmArea {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        let size = self.size();
+        unsafe { core::slice::from_raw_parts_mut(self.as_mut_ptr(), size) }
+    }
+}
+
+impl Drop for VmArea {
+    fn drop(&mut self) {
+        unmap_pages(self.start, self.pages, self.owns_frames);
+        self.ranges.lock().free(self.reserved_start(), self.reserved_size());
+    }
+}
+
+/// `size` バイト以上の仮想的に連続な領域を確保する (内容はゼロクリア済み)
+///
+/// フレームアロケータ未登録時や物理メモリ・仮想領域が不足した場合は `None`。
+pub fn vmalloc(size: usize) -> Option<VmArea> {
+    alloc_in(&VIRT_RANGES, size)
+}
+
+/// カーネルスタック用に、スタック領域から `vmalloc` と同じ要領で確保する
+pub fn vmalloc_stack(size: usize) -> Option<VmArea> {
+    alloc_in(&STACK_RANGES, size)
+}
+
+fn alloc_in(ranges: &'static Mutex<VirtRangeAllocator>, size: usize) -> Option<VmArea> {
+    if size == 0 {
+        return None;
+    }
+    let pages = (size as u64).div_ceil(PAGE_SIZE);
+    let (base, reserved) = reserve(ranges, pages)?;
+    let start = VirtAddr::new(base + GUARD_PAGES * PAGE_SIZE);
+
+    let mapped = map_pages(start, pages, None, default_flags());
+    if mapped < pages {
+        unmap_pages(start, mapped, true);
+        ranges.lock().free(base, reserved);
+        return None;
+    }
+
+    let mut area = VmArea { start, offset: 0, pages, owns_frames: true, ranges };
+    area.fill(0);
+    Some(area)
+}
+
+/// 物理範囲 `[phys, phys + size)` を vmalloc 領域に `flags` でマップする
+///
+/// フレームの所有権は移らず、drop 時にはアンマップのみ行う。
+///
+/// Safety: 呼び出し側は物理範囲が確保済み (または MMIO) であり、
+/// 返した `VmArea` より長く有効であることを保証すること。
+pub unsafe fn map_phys(phys: PhysAddr, size: usize, flags: PageTableFlags) -> Option<VmArea> {
+    let offset = phys.as_u64() % PAGE_SIZE;
+    let first = PhysFrame::<Size4KiB>::containing_address(phys);
+    let pages = (offset + size as u64).div_ceil(PAGE_SIZE);
+    let (base, reserved) = reserve(&VIRT_RANGES, pages)?;
+    let start = VirtAddr::new(base + GUARD_PAGES * PAGE_SIZE);
+
+    let mapped = map_pages(start, pages, Some(first), flags | PageTableFlags::PRESENT);
+    if mapped < pages {
+        unmap_pages(start, mapped, false);
+        VIRT_RANGES.lock().free(base, reserved);
+        return None;
+    }
+    Some(VmArea { start, offset, pages, owns_frames: false, ranges: &VIRT_RANGES })
+}
+
+/// MMIO 領域 `[phys, phys + size)` をキャッシュ無効でマップし、`phys` に対応する仮想アドレスを返す
+///
+/// マッピングは解放しない (APIC・IOAPIC・HPET のように以後ずっと使うレジスタ用)。
+///
+/// Safety: 物理範囲は RAM ではなくデバイスのレジスタであること。
+pub unsafe fn map_mmio_permanent(phys: PhysAddr, size: usize) -> Option<VirtAddr> {
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH;
+    let area = map_phys(phys, size, flags)?;
+    let virt = area.start_addr();
+    core::mem::forget(area);
+    Some(virt)
+}
+
+/// vmalloc 領域の既定フラグ
+fn default_flags() -> PageTableFlags {
+    PageTableFlags::PRESENT | PageTableFlags::WRITABLE
+}
+
+/// ガードページを含めて `pages` ページ分の仮想範囲を予約する
+fn reserve(ranges: &Mutex<VirtRangeAllocator>, pages: u64) -> Option<(u64, u64)> {
+    let reserved = (pages + 2 * GUARD_PAGES) * PAGE_SIZE;
+    let base = ranges.lock().alloc(reserved)?;
+    Some((base, reserved))
+}
+
+/// `vmalloc` で確保した領域を解放する (`drop` と同じ)
+pub fn vfree(area: VmArea) {
+    drop(area);
+}
+
+/// `start` から `pages` ページ分をマップする
+///
+/// `phys` が `None` なら新しいフレームを 1 枚ずつ確保し、`Some` ならそこから連続する
+/// フレームを使う。実際にマップできたページ数を返す。
+pub(super) fn map_pages(start: VirtAddr, pages: u64, phys: Option<PhysFrame>, mut flags: PageTableFlags) -> u64 {
+    // vmalloc 領域にコードは置かない
+    if cpu::nx_enabled() {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+    with_kernel_page_table(|table| {
+        with_frame_allocator(|fa| {
+            for i in 0..pages {
+                let page = Page::<Size4KiB>::containing_address(start + i * PAGE_SIZE);
+                let frame = match phys {
+                    Some(first) => first + i,
+                    None => match fa.allocate_frame() {
+                        Some(frame) => frame,
+                        None => return i,
+                    },
+                };
+                // Safety: page は vmalloc 領域内の未使用ページで、frame は呼び出し側が確保したフレーム
+                match unsafe { table.map_to(page, frame, flags, fa) } {
+                    Ok(flush) => flush.flush(),
+                    Err(_) => {
+                        if phys.is_none() {
+                            unsafe { fa.deallocate_frame(frame) };
+                        }
+                        return i;
+                    }
+                }
+            }
+            pages
+        })
+        .unwrap_or(0)
+    })
+}
+
+/// `start` から `pages` ページ分をアンマップし、`free` ならフレームを返却する
+fn unmap_pages(start: VirtAddr, pages: u64, free: bool) {
+    with_kernel_page_table(|table| {
+        for i in 0..pages {
+            let page = Page::<Size4KiB>::containing_address(start + i * PAGE_SIZE);
+            if let Ok((frame, flush)) = table.unmap(page) {
+                flush.flush();
+                if free {
+                    free_frame(frame);
+                }
+            }
+        }
+    });
+}
+
+fn free_frame(frame: PhysFrame) {
+    with_frame_allocator(|fa| unsafe { fa.deallocate_frame(frame) });
+}
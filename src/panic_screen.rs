@@ -0,0 +1,124 @@
+//! パニック画面
+//!
+//! - panic ハンドラから呼ばれ、緊急コンソールとシリアルの両方に
+//!   メッセージ・発生箇所・CPU 状態・バックトレースを出力して停止する。
+//! - 致命的な例外の場合は例外発生時のレジスタを CPU 状態として表示する。
+//! - 最後にカーネルログ (dmesg) を、シリアルには残っている分すべて、画面には直近の数件だけ出す。
+
+use core::arch::asm;
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use x86_64::registers::control::{Cr0, Cr2, Cr3, Cr4};
+use crate::backtrace::Backtrace;
+use crate::emergency::EmergencyConsole;
+use crate::graphics::{COLOR_BLACK, COLOR_YELLOW};
+use crate::interrupts::InterruptContext;
+use crate::logger::dmesg::{self, Dump};
+
+/// 画面に出すカーネルログの件数
+const SCREEN_LOG_LINES: usize = 8;
+
+static PANICKING: AtomicBool = AtomicBool::new(false);
+static EXCEPTION_CONTEXT: AtomicPtr<InterruptContext> = AtomicPtr::new(core::ptr::null_mut());
+
+/// 直後の panic が例外によるものであることを記録する
+pub fn set_exception_context(ctx: &InterruptContext) {
+    EXCEPTION_CONTEXT.store(ctx as *const _ as *mut _, Ordering::Release);
+}
+
+/// パニック情報を表示して停止する
+pub fn handle(info: &PanicInfo) -> ! {
+    x86_64::instructions::interrupts::disable();
+    if PANICKING.swap(true, Ordering::AcqRel) {
+        // 表示処理中の二重パニック: これ以上の描画は諦める
+        crate::drivers::serial::_print_emergency(format_args!("\nnested panic: {}\n", info));
+        halt();
+    }
+
+    // Safety: set_exception_context に渡された例外ハンドラのフレームはまだ生きている
+    let ctx = unsafe { EXCEPTION_CONTEXT.load(Ordering::Acquire).as_ref() };
+    let report = Report { info, ctx, regs: CpuState::capture() };
+
+    crate::drivers::serial::_print_emergency(format_args!("\n{}", report));
+    crate::drivers::serial::_print_emergency(format_args!("\nKernel log:\n{}", Dump(dmesg::CAPACITY)));
+    if let Some(mut console) = EmergencyConsole::get() {
+        console.clear(COLOR_YELLOW);
+        console.set_color(COLOR_BLACK);
+        let _ = write!(console, "{}", report);
+        let _ = write!(console, "\nKernel log:\n{}", Dump(SCREEN_LOG_LINES));
+    }
+    halt()
+}
+
+fn halt() -> ! {
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// panic ハンドラ内で取得したレジスタ
+struct CpuState {
+    rsp: u64,
+    rbp: u64,
+    rflags: u64,
+}
+
+impl CpuState {
+    #[inline(always)]
+    fn capture() -> Self {
+        let (rsp, rbp, rflags): (u64, u64, u64);
+        unsafe {
+            asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack));
+            asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack));
+        }
+        rflags = x86_64::registers::rflags::read_raw();
+        Self { rsp, rbp, rflags }
+    }
+}
+
+impl fmt::Display for CpuState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "RSP={:016x} RBP={:016x} RFLAGS={:016x}", self.rsp, self.rbp, self.rflags)?;
+        writeln!(
+            f,
+            "CR0={:016x} CR2={:016x} CR3={:016x} CR4={:016x}",
+            Cr0::read_raw(),
+            Cr2::read().as_u64(),
+            Cr3::read().0.start_address().as_u64(),
+            Cr4::read_raw()
+        )
+    }
+}
+
+struct Report<'a> {
+    info: &'a PanicInfo<'a>,
+    ctx: Option<&'a InterruptContext>,
+    regs: CpuState,
+}
+
+impl fmt::Display for Report<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "*** KERNEL PANIC ***")?;
+        writeln!(f)?;
+        writeln!(f, "{}", self.info.message())?;
+        if let Some(location) = self.info.location() {
+            writeln!(f, "at {}:{}:{}", location.file(), location.line(), location.column())?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "-- CPU state --")?;
+        match self.ctx {
+            Some(ctx) => write!(f, "{}", ctx)?,
+            None => write!(f, "{}", self.regs)?,
+        }
+
+        writeln!(f)?;
+        writeln!(f, "-- backtrace --")?;
+        let backtrace = match self.ctx {
+            Some(ctx) => Backtrace { rip: Some(ctx.rip), rbp: ctx.rbp },
+            None => Backtrace { rip: None, rbp: self.regs.rbp },
+        };
+        write!(f, "{}", backtrace)
+    }
+}
@@ -0,0 +1,152 @@
+#![allow(dead_code)]
+//! カーネルシェル
+//!
+//! - キーボード (IME 経由) やシリアルから 1 文字ずつ受け取り、行を組み立てて実行する。
+//! - 出力先は呼び出し側が渡す `fmt::Write` (入力のエコーも同じ出力先へ書く)。
+//! - コマンドは `COMMANDS` に並べる。
+//! - シリアル端末の矢印キーなどのエスケープシーケンスは行に入れずに読み捨てる。
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::str::FromStr;
+use log::{Level, LevelFilter};
+use crate::console::ansi::{Action, Parser};
+use crate::logger::{self, dmesg, Sink};
+
+const PROMPT: &str = "> ";
+
+struct Command {
+    name: &'static str,
+    usage: &'static str,
+    run: fn(&[&str], &mut dyn Write) -> fmt::Result,
+}
+
+const COMMANDS: &[Command] = &[
+    Command { name: "help", usage: "help", run: help },
+    Command { name: "dmesg", usage: "dmesg [-n COUNT] [-l LEVEL]", run: dmesg },
+    Command { name: "loglevel", usage: "loglevel [serial|console|dmesg LEVEL]", run: loglevel },
+];
+
+pub struct Shell {
+    line: String,
+    escape: Parser,
+    /// 直前の文字が `\r` だったか (CRLF の `\n` を読み捨てる)
+    after_cr: bool,
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shell {
+    pub const fn new() -> Self {
+        Self { line: String::new(), escape: Parser::new(), after_cr: false }
+    }
+
+    pub fn prompt(&self, out: &mut dyn Write) -> fmt::Result {
+        out.write_str(PROMPT)
+    }
+
+    /// 1 文字処理する (改行で実行し、次のプロンプトを出す)
+    ///
+    /// `\r` `\n` のどちらでも実行するが、`\r\n` は 1 回の改行として扱う。
+    pub fn feed(&mut self, c: char, out: &mut dyn Write) -> fmt::Result {
+        let after_cr = core::mem::replace(&mut self.after_cr, c == '\r');
+        if after_cr && c == '\n' {
+            return Ok(());
+        }
+        match self.escape.advance(c) {
+            Some(Action::Print(c)) => {
+                self.line.push(c);
+                out.write_char(c)
+            }
+            Some(Action::Execute('\r' | '\n')) => {
+                out.write_char('\n')?;
+                let line = core::mem::take(&mut self.line);
+                execute(&line, out)?;
+                self.prompt(out)
+            }
+            Some(Action::Execute('\x08' | '\x7f')) => {
+                if self.line.pop().is_some() {
+                    out.write_str("\x08 \x08")?;
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn execute(line: &str, out: &mut dyn Write) -> fmt::Result {
+    let args: Vec<&str> = line.split_whitespace().collect();
+    let Some(&name) = args.first() else { return Ok(()) };
+    match COMMANDS.iter().find(|command| command.name == name) {
+        Some(command) => (command.run)(&args[1..], out),
+        None => writeln!(out, "{}: command not found (try `help`)", name),
+    }
+}
+
+fn help(_args: &[&str], out: &mut dyn Write) -> fmt::Result {
+    for command in COMMANDS {
+        writeln!(out, "  {}", command.usage)?;
+    }
+    Ok(())
+}
+
+/// カーネルログを表示する (`-n` で直近の件数、`-l` で表示するレベルを絞る)
+fn dmesg(args: &[&str], out: &mut dyn Write) -> fmt::Result {
+    let mut count = dmesg::CAPACITY;
+    let mut max_level = Level::Trace;
+    let mut args = args.iter();
+    while let Some(&arg) = args.next() {
+        let value = args.next().copied();
+        match (arg, value) {
+            ("-n", Some(value)) => match value.parse() {
+                Ok(n) => count = n,
+                Err(_) => return writeln!(out, "dmesg: invalid count `{}`", value),
+            },
+            ("-l", Some(value)) => match Level::from_str(value) {
+                Ok(level) => max_level = level,
+                Err(_) => return writeln!(out, "dmesg: invalid level `{}`", value),
+            },
+            _ => return writeln!(out, "usage: dmesg [-n COUNT] [-l LEVEL]"),
+        }
+    }
+    let mut reader = dmesg::Reader::last(count);
+    for entry in reader.by_ref().filter(|entry| entry.level <= max_level) {
+        writeln!(out, "{}", entry)?;
+    }
+    if reader.lost() > 0 {
+        writeln!(out, "({} records overwritten while reading)", reader.lost())?;
+    }
+    if dmesg::dropped() > 0 {
+        writeln!(out, "({} records dropped)", dmesg::dropped())?;
+    }
+    Ok(())
+}
+
+/// 出力先ごとのログレベルを表示・変更する
+fn loglevel(args: &[&str], out: &mut dyn Write) -> fmt::Result {
+    match args {
+        [] => writeln!(out, "{}", logger::Levels),
+        [sink, level] => {
+            let sink = match *sink {
+                "serial" => Sink::Serial,
+                "console" => Sink::Console,
+                "dmesg" => Sink::Dmesg,
+                _ => return writeln!(out, "loglevel: unknown sink `{}`", sink),
+            };
+            match LevelFilter::from_str(level) {
+                Ok(level) => {
+                    logger::set_level(sink, level);
+                    writeln!(out, "{}", logger::Levels)
+                }
+                Err(_) => writeln!(out, "loglevel: invalid level `{}`", level),
+            }
+        }
+        _ => writeln!(out, "usage: loglevel [serial|console|dmesg LEVEL]"),
+    }
+}
@@ -0,0 +1,4 @@
+#![allow(dead_code)]
+//! 割り込みハンドラと通常の処理の間で共有するデータ構造
+
+pub mod ring;
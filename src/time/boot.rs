@@ -0,0 +1,59 @@
+//! 起動処理の所要時間の記録
+//!
+//! - 各段階の完了時に `mark` で TSC の値を記録し、TSC の較正後に `Report` で一覧を表示する。
+//! - ヒープ初期化前から使うため固定長の配列に保持する (超えた分は捨てる)。
+
+use core::fmt;
+use spin::Mutex;
+use super::Instant;
+
+const MAX_STAGES: usize = 32;
+
+struct Stages {
+    entries: [(&'static str, Instant); MAX_STAGES],
+    len: usize,
+}
+
+static STAGES: Mutex<Stages> = Mutex::new(Stages {
+    entries: [("", Instant(0)); MAX_STAGES],
+    len: 0,
+});
+
+/// 段階 `name` が完了した時刻を記録する
+pub fn mark(name: &'static str) {
+    let now = Instant::now();
+    let mut stages = STAGES.lock();
+    let len = stages.len;
+    if len < MAX_STAGES {
+        stages.entries[len] = (name, now);
+        stages.len += 1;
+    }
+}
+
+/// 記録した段階の名前と時刻 (記録順)
+pub fn stages() -> impl Iterator<Item = (&'static str, Instant)> {
+    let stages = STAGES.lock();
+    let (entries, len) = (stages.entries, stages.len);
+    entries.into_iter().take(len)
+}
+
+/// 最初の記録からの経過時間と、直前の段階からの所要時間の一覧
+pub struct Report;
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut stages = stages();
+        let Some((first, start)) = stages.next() else {
+            return writeln!(f, "no boot stages recorded");
+        };
+        writeln!(f, "{:>6}.{:03} ms  {}", 0, 0, first)?;
+        let mut previous = start;
+        for (name, at) in stages {
+            let total = at.duration_since(start).as_micros();
+            let delta = at.duration_since(previous).as_micros();
+            writeln!(f, "{:>6}.{:03} ms  {} (+{} us)", total / 1000, total % 1000, name, delta)?;
+            previous = at;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+//! クロックソース
+//!
+//! - 自由に走り続けるカウンタ (TSC / HPET / ACPI PM タイマ) を `ClockSource` として登録し、
+//!   精度・読み出しコストに基づく評価値 (rating) が最も高いものを使う。
+//! - コマンドラインの `clocksource=<name>` で使用するものを指定できる。
+//! - カウンタの桁あふれは、ティックごとの `update` で累積カウントに繰り込んで扱う。
+//!   (24bit の PM タイマでも約 4.6 秒ごとなので 1 ティックの間には一周しない)
+
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+/// 時刻の基準となるカウンタ
+pub trait ClockSource: Sync {
+    fn name(&self) -> &'static str;
+    /// 評価値 (高いほど優先。Linux と同じく 300: TSC、250: HPET、200: ACPI PM)
+    fn rating(&self) -> u32;
+    /// カウンタの周波数 (Hz)
+    fn frequency(&self) -> u64;
+    /// カウンタの現在値
+    fn read(&self) -> u64;
+    /// カウンタの有効ビット
+    fn mask(&self) -> u64;
+}
+
+static SOURCES: Mutex<Vec<&'static dyn ClockSource>> = Mutex::new(Vec::new());
+static CLOCK: Mutex<Option<Clock>> = Mutex::new(None);
+
+/// 選択中のクロックソースと累積カウント
+struct Clock {
+    source: &'static dyn ClockSource,
+    /// 前回 `update` 時のカウンタ値
+    last: u64,
+    /// 選択してからのカウント数 (`last` まで)
+    cycles: u64,
+    /// 選択した時点の経過時間 (ナノ秒)。切り替えても時刻が戻らないようにする
+    base_ns: u64,
+}
+
+impl Clock {
+    fn elapsed_cycles(&self) -> u64 {
+        self.cycles + (self.source.read().wrapping_sub(self.last) & self.source.mask())
+    }
+
+    fn now_ns(&self) -> u64 {
+        let ns = self.elapsed_cycles() as u128 * 1_000_000_000 / self.source.frequency() as u128;
+        self.base_ns + ns as u64
+    }
+}
+
+/// クロックソースを登録する
+pub fn register(source: &'static dyn ClockSource) {
+    without_interrupts(|| SOURCES.lock().push(source));
+}
+
+/// 登録済みのクロックソース
+pub fn available() -> Vec<&'static dyn ClockSource> {
+    without_interrupts(|| SOURCES.lock().clone())
+}
+
+/// 名前でクロックソースを選ぶ
+pub fn select(name: &str) -> Result<&'static dyn ClockSource, &'static str> {
+    let source = available()
+        .into_iter()
+        .find(|s| s.name() == name)
+        .ok_or("unknown clocksource")?;
+    switch_to(source);
+    Ok(source)
+}
+
+/// コマンドラインの指定、無ければ評価値が最も高いクロックソースを選ぶ
+pub fn select_default() -> Option<&'static dyn ClockSource> {
+    if let Some(name) = crate::cmdline::get("clocksource") {
+        match select(name) {
+            Ok(source) => return Some(source),
+            Err(e) => log::warn!("clocksource={}: {}", name, e),
+        }
+    }
+    let best = available().into_iter().max_by_key(|s| s.rating())?;
+    switch_to(best);
+    Some(best)
+}
+
+fn switch_to(source: &'static dyn ClockSource) {
+    without_interrupts(|| {
+        let mut clock = CLOCK.lock();
+        let base_ns = clock.as_ref().map_or(0, |c| c.now_ns());
+        *clock = Some(Clock { source, last: source.read(), cycles: 0, base_ns });
+    });
+}
+
+/// 選択中のクロックソース
+pub fn current() -> Option<&'static dyn ClockSource> {
+    without_interrupts(|| CLOCK.lock().as_ref().map(|c| c.source))
+}
+
+/// クロックソースを選んでからの経過時間 (ナノ秒、未選択なら `None`)
+pub fn now_ns() -> Option<u64> {
+    without_interrupts(|| CLOCK.lock().as_ref().map(|c| c.now_ns()))
+}
+
+/// 桁あふれに備えて累積カウントを更新する (ティックごとに呼ばれる)
+pub fn update() {
+    let Some(mut clock) = CLOCK.try_lock() else { return };
+    if let Some(clock) = clock.as_mut() {
+        let now = clock.source.read();
+        clock.cycles += now.wrapping_sub(clock.last) & clock.source.mask();
+        clock.last = now;
+    }
+}
+
+/// クロックソースで `us` マイクロ秒待つ
+pub fn busy_wait_us(source: &dyn ClockSource, us: u64) {
+    let target = (us as u128 * source.frequency() as u128 / 1_000_000) as u64;
+    let start = source.read();
+    while source.read().wrapping_sub(start) & source.mask() < target {
+        core::hint::spin_loop();
+    }
+}
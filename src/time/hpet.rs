@@ -0,0 +1,92 @@
+//! HPET (High Precision Event Timer)
+//!
+//! - ACPI の HPET テーブルからレジスタのアドレスを得て、キャッシュ無効でマップする。
+//! - メインカウンタを動かしてクロックソースとして使う (コンパレータによる割り込みは使わない)。
+
+use spin::Once;
+use x86_64::PhysAddr;
+use super::clocksource::ClockSource;
+use crate::acpi;
+use crate::memory::vmalloc;
+
+const REG_CAPABILITIES: u64 = 0x000;
+const REG_CONFIG: u64 = 0x010;
+const REG_MAIN_COUNTER: u64 = 0x0F0;
+
+/// メインカウンタが 64bit (COUNT_SIZE_CAP)
+const CAP_COUNTER_64: u64 = 1 << 13;
+const CONFIG_ENABLE: u64 = 1 << 0;
+const CONFIG_LEGACY_ROUTE: u64 = 1 << 1;
+/// 仕様上のカウンタ周期の上限 (100ns、フェムト秒単位)
+const MAX_PERIOD_FS: u64 = 100_000_000;
+
+static HPET: Once<Hpet> = Once::new();
+
+pub struct Hpet {
+    /// レジスタ領域の仮想アドレス
+    base: u64,
+    frequency: u64,
+    counter_64: bool,
+}
+
+impl Hpet {
+    fn read_reg(&self, reg: u64) -> u64 {
+        // Safety: init でマップした HPET のレジスタ領域
+        unsafe { core::ptr::read_volatile((self.base + reg) as *const u64) }
+    }
+
+    fn write_reg(&self, reg: u64, value: u64) {
+        unsafe { core::ptr::write_volatile((self.base + reg) as *mut u64, value) }
+    }
+}
+
+/// HPET をマップしてメインカウンタを開始する
+pub fn init() -> Result<&'static Hpet, &'static str> {
+    if let Some(hpet) = HPET.get() {
+        return Ok(hpet);
+    }
+    let info = acpi::hpet().ok_or("HPET table not found")?;
+    if info.base_address == 0 {
+        return Err("HPET is not memory mapped");
+    }
+    // Safety: HPET の MMIO 領域 (1KiB)
+    let base = unsafe { vmalloc::map_mmio_permanent(PhysAddr::new(info.base_address), 0x400) }
+        .ok_or("failed to map HPET")?
+        .as_u64();
+
+    let mut hpet = Hpet { base, frequency: 0, counter_64: false };
+    let caps = hpet.read_reg(REG_CAPABILITIES);
+    let period_fs = caps >> 32;
+    if period_fs == 0 || period_fs > MAX_PERIOD_FS {
+        return Err("invalid HPET counter period");
+    }
+    hpet.frequency = 1_000_000_000_000_000 / period_fs;
+    hpet.counter_64 = caps & CAP_COUNTER_64 != 0;
+
+    // レガシー置換ルーティングは使わず、カウンタだけを動かす
+    let config = hpet.read_reg(REG_CONFIG);
+    hpet.write_reg(REG_CONFIG, (config & !CONFIG_LEGACY_ROUTE) | CONFIG_ENABLE);
+    Ok(HPET.call_once(|| hpet))
+}
+
+impl ClockSource for Hpet {
+    fn name(&self) -> &'static str {
+        "hpet"
+    }
+
+    fn rating(&self) -> u32 {
+        250
+    }
+
+    fn frequency(&self) -> u64 {
+        self.frequency
+    }
+
+    fn read(&self) -> u64 {
+        self.read_reg(REG_MAIN_COUNTER)
+    }
+
+    fn mask(&self) -> u64 {
+        if self.counter_64 { u64::MAX } else { u32::MAX as u64 }
+    }
+}
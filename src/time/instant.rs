@@ -0,0 +1,59 @@
+//! 単調増加する高精度な時刻
+//!
+//! - TSC の値をそのまま保持するので、ヒープや周波数の較正より前から記録できる。
+//! - 経過時間への変換は TSC の周波数を使うため、`tsc::init` より前は 0 になる。
+
+use core::ops::{Add, Sub};
+use core::time::Duration;
+use super::tsc;
+
+/// ある時点の TSC の値 (ナノ秒精度)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(pub(super) u64);
+
+impl Instant {
+    /// 現在の時刻
+    pub fn now() -> Instant {
+        Instant(tsc::read())
+    }
+
+    /// `earlier` からの経過時間 (`earlier` の方が後なら 0)
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(tsc::cycles_to_ns(self.0.saturating_sub(earlier.0)))
+    }
+
+    /// この時刻からの経過時間
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    /// TSC の生の値
+    pub fn as_cycles(&self) -> u64 {
+        self.0
+    }
+
+    /// `duration` 後の時刻 (TSC が未較正なら `None`)
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        if tsc::frequency() == 0 {
+            return None;
+        }
+        let ns = u64::try_from(duration.as_nanos()).ok()?;
+        self.0.checked_add(tsc::ns_to_cycles(ns)).map(Instant)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        self.checked_add(duration).expect("overflow when adding duration to instant")
+    }
+}
+
+impl Sub for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
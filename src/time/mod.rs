@@ -0,0 +1,268 @@
+#![allow(dead_code)]
+//! 時間管理
+//!
+//! - ローカル APIC タイマで毎秒 `HZ` 回のティックを発生させ、起動からのティック数 (jiffies) を数える。
+//!   TSC デッドラインモードに対応していればそれを使い、無ければ周期モードで動かす。
+//! - `uptime` / `sleep` と、指定時間後・周期的に呼ばれるタイマコールバックを提供する。
+//! - 高精度な経過時間はクロックソース (`clocksource`) から得る。
+//!   TSC の値をそのまま保持する `Instant` は較正前から使え、起動処理の計測 (`boot`) にも使う。
+//! - 現在時刻 (UNIX 時間) は起動時に CMOS RTC から読んだ時刻を基準に求める。
+//! - コマンドラインで `tick=rtc` を指定すると、APIC タイマの代わりに RTC の周期割り込みでティックを進める。
+//! - タイマコールバックはティックの割り込みハンドラ内 (割り込み禁止状態) で呼ばれるため、
+//!   短時間で終わる処理だけを行うこと。
+
+pub mod boot;
+pub mod clocksource;
+pub mod hpet;
+pub mod instant;
+pub mod pit;
+pub mod pmtimer;
+pub mod rtc;
+pub mod tsc;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::instructions::interrupts::{self as cpu_interrupts, without_interrupts};
+use crate::apic::timer::{self as apic_timer, TimerMode};
+use crate::interrupts::{self, InterruptContext};
+
+pub use instant::Instant;
+
+/// 1 秒あたりのティック数
+pub const HZ: u64 = 1000;
+/// RTC をティック源にするときの周期割り込みのレート (1024Hz)
+const RTC_TICK_RATE: u8 = 6;
+
+static JIFFIES: AtomicU64 = AtomicU64::new(0);
+static RUNNING: AtomicBool = AtomicBool::new(false);
+static TIMERS: Mutex<Vec<Timer>> = Mutex::new(Vec::new());
+static NEXT_TIMER_ID: AtomicU64 = AtomicU64::new(1);
+/// RTC の周期割り込みの回数 (RTC がティック源の場合)
+static RTC_INTERRUPTS: AtomicU64 = AtomicU64::new(0);
+
+/// ティックを発生させる装置
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TickSource {
+    Apic(TimerMode),
+    /// RTC の周期割り込み (周波数 Hz)
+    Rtc(u32),
+}
+
+/// ティックの設定結果
+#[derive(Clone, Copy, Debug)]
+pub struct TickInfo {
+    pub vector: u8,
+    pub source: TickSource,
+    pub calibration: apic_timer::Calibration,
+    /// 較正の基準にしたタイマ
+    pub reference: &'static str,
+    /// 選択したクロックソース
+    pub clocksource: &'static str,
+}
+
+/// クロックソースを登録・選択し、APIC タイマを較正してティックを開始する
+///
+/// ACPI とローカル APIC・IOAPIC の初期化後に呼ぶ。
+pub fn init() -> Result<TickInfo, &'static str> {
+    if let Err(e) = rtc::init() {
+        log::warn!("RTC: {}", e);
+    }
+    // 周波数が既知のタイマを先に登録し、APIC タイマと TSC の較正の基準にする
+    match hpet::init() {
+        Ok(hpet) => clocksource::register(hpet),
+        Err(e) => log::warn!("HPET: {}", e),
+    }
+    match pmtimer::init() {
+        Ok(pm) => clocksource::register(pm),
+        Err(e) => log::warn!("ACPI PM timer: {}", e),
+    }
+    let reference = clocksource::available().into_iter().max_by_key(|s| s.rating());
+    let calibration = match reference {
+        Some(source) => apic_timer::calibrate(&|us| clocksource::busy_wait_us(source, us))?,
+        None => apic_timer::calibrate(&pit::wait_us)?,
+    };
+    match tsc::init(reference, calibration.tsc_per_ms) {
+        Ok(tsc) => clocksource::register(tsc),
+        Err(e) => log::warn!("TSC: {}", e),
+    }
+    let clocksource = clocksource::select_default().map_or("none", |s| s.name());
+
+    let (vector, source) = if crate::cmdline::get("tick") == Some("rtc") {
+        let irq = rtc::start_periodic(RTC_TICK_RATE, rtc_tick)?;
+        (irq.vector, TickSource::Rtc(rtc::periodic_frequency(RTC_TICK_RATE)))
+    } else {
+        start_apic_timer()?
+    };
+    RUNNING.store(true, Ordering::Release);
+    Ok(TickInfo {
+        vector,
+        source,
+        calibration,
+        reference: reference.map_or("pit", |s| s.name()),
+        clocksource,
+    })
+}
+
+fn start_apic_timer() -> Result<(u8, TickSource), &'static str> {
+    let vector = interrupts::allocate_vector().ok_or("no free interrupt vector")?;
+    interrupts::register_handler(vector, tick)?;
+
+    let mode = if crate::cpu::CpuFeatures::detect().tsc_deadline {
+        TimerMode::TscDeadline
+    } else {
+        TimerMode::Periodic
+    };
+    if let Err(e) = apic_timer::start(vector, HZ as u32, mode) {
+        interrupts::free_vector(vector);
+        return Err(e);
+    }
+    Ok((vector, TickSource::Apic(mode)))
+}
+
+/// APIC タイマの割り込みハンドラ
+fn tick(_ctx: &InterruptContext) {
+    apic_timer::rearm();
+    advance();
+}
+
+/// RTC の周期割り込みから呼ばれる
+///
+/// 割り込みの周波数は `HZ` と異なるので、割り込み回数から求めたティック数に追いつくまで進める。
+fn rtc_tick() {
+    let interrupts = RTC_INTERRUPTS.fetch_add(1, Ordering::Relaxed) + 1;
+    let due = interrupts * HZ / rtc::periodic_frequency(RTC_TICK_RATE) as u64;
+    while jiffies() < due {
+        advance();
+    }
+}
+
+/// ティックを 1 つ進める
+fn advance() {
+    let now = JIFFIES.fetch_add(1, Ordering::Relaxed) + 1;
+    clocksource::update();
+    run_timers(now);
+}
+
+/// 起動 (ティック開始) からのティック数
+pub fn jiffies() -> u64 {
+    JIFFIES.load(Ordering::Relaxed)
+}
+
+/// 起動 (ティック開始) からの経過時間
+pub fn uptime() -> Duration {
+    let ticks = jiffies();
+    Duration::from_secs(ticks / HZ) + Duration::from_nanos((ticks % HZ) * 1_000_000_000 / HZ)
+}
+
+/// クロックソースによる高精度な経過時間 (クロックソースが無ければティック単位)
+pub fn monotonic() -> Duration {
+    clocksource::now_ns().map_or_else(uptime, Duration::from_nanos)
+}
+
+/// 現在時刻 (UNIX 時間、秒)
+pub fn now() -> u64 {
+    rtc::unix_time()
+}
+
+/// `duration` をティック数に切り上げる
+fn to_ticks(duration: Duration) -> u64 {
+    let nanos = duration.as_nanos();
+    (nanos * HZ as u128).div_ceil(1_000_000_000) as u64
+}
+
+/// `duration` だけ待つ
+///
+/// ティックが動いていて割り込みが許可されていれば `hlt` で待ち、そうでなければ PIT でビジーウェイトする。
+pub fn sleep(duration: Duration) {
+    if !RUNNING.load(Ordering::Acquire) || !cpu_interrupts::are_enabled() {
+        pit::busy_wait(duration);
+        return;
+    }
+    let deadline = jiffies() + to_ticks(duration);
+    while jiffies() < deadline {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// タイマの識別子 (`cancel` に渡す)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimerId(u64);
+
+enum Callback {
+    Once(Box<dyn FnOnce() + Send>),
+    Periodic(Box<dyn FnMut() + Send>),
+}
+
+struct Timer {
+    id: u64,
+    /// 発火するティック
+    expires: u64,
+    /// 周期 (ティック数、`Callback::Periodic` のみ)
+    period: u64,
+    callback: Callback,
+}
+
+fn add(timer: Timer) -> TimerId {
+    let id = TimerId(timer.id);
+    // ティックの割り込みハンドラもロックを取るので、保持中は割り込みを止める
+    without_interrupts(|| TIMERS.lock().push(timer));
+    id
+}
+
+/// `delay` 後に一度だけ `callback` を呼ぶ
+pub fn add_timer(delay: Duration, callback: impl FnOnce() + Send + 'static) -> TimerId {
+    add(Timer {
+        id: NEXT_TIMER_ID.fetch_add(1, Ordering::Relaxed),
+        expires: jiffies() + to_ticks(delay).max(1),
+        period: 0,
+        callback: Callback::Once(Box::new(callback)),
+    })
+}
+
+/// `period` ごとに `callback` を呼ぶ
+pub fn add_periodic(period: Duration, callback: impl FnMut() + Send + 'static) -> TimerId {
+    let period = to_ticks(period).max(1);
+    add(Timer {
+        id: NEXT_TIMER_ID.fetch_add(1, Ordering::Relaxed),
+        expires: jiffies() + period,
+        period,
+        callback: Callback::Periodic(Box::new(callback)),
+    })
+}
+
+/// タイマを取り消す。まだ発火していなければ `true`
+pub fn cancel(id: TimerId) -> bool {
+    without_interrupts(|| {
+        let mut timers = TIMERS.lock();
+        let before = timers.len();
+        timers.retain(|t| t.id != id.0);
+        timers.len() != before
+    })
+}
+
+/// 期限の来たタイマを実行する
+///
+/// コールバックからタイマを追加できるよう、1 つずつ取り出してからロックを外して呼ぶ。
+fn run_timers(now: u64) {
+    loop {
+        let expired = {
+            let Some(mut timers) = TIMERS.try_lock() else { return };
+            match timers.iter().position(|t| t.expires <= now) {
+                Some(index) => timers.swap_remove(index),
+                None => return,
+            }
+        };
+        match expired.callback {
+            Callback::Once(callback) => callback(),
+            Callback::Periodic(mut callback) => {
+                callback();
+                // 処理が遅れて期限を過ぎていれば、溜まった分は飛ばす
+                let expires = (expired.expires + expired.period).max(now + 1);
+                TIMERS.lock().push(Timer { callback: Callback::Periodic(callback), expires, ..expired });
+            }
+        }
+    }
+}
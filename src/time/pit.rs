@@ -0,0 +1,57 @@
+#![allow(dead_code)]
+//! 8254 PIT (Programmable Interval Timer)
+//!
+//! - 割り込みは使わず、チャネル 2 (スピーカ用、ゲートをソフトウェアで制御できる) を
+//!   ワンショットで動かし、OUT2 をポーリングして一定時間を待つ。
+//! - APIC タイマ・TSC の較正と、タイマ割り込みが使えない段階での待機に使う。
+
+use core::time::Duration;
+use x86_64::instructions::port::Port;
+
+/// PIT の入力クロック (Hz)
+pub const FREQUENCY: u64 = 1_193_182;
+
+const CHANNEL2_DATA: u16 = 0x42;
+const COMMAND: u16 = 0x43;
+/// NMI ステータス / スピーカ制御ポート
+const SPEAKER_CONTROL: u16 = 0x61;
+
+const GATE2: u8 = 1 << 0;
+const SPEAKER_ENABLE: u8 = 1 << 1;
+const OUT2: u8 = 1 << 5;
+/// チャネル 2・下位/上位バイト・モード 0 (ターミナルカウント)・バイナリ
+const CMD_CHANNEL2_ONESHOT: u8 = 0b1011_0000;
+
+/// 1 回のカウントで待てる最大時間 (マイクロ秒)
+const MAX_WAIT_US: u64 = 0xFFFF * 1_000_000 / FREQUENCY;
+
+/// `us` マイクロ秒 (最大約 54ms) 待つ
+pub fn wait_us(us: u64) {
+    let count = (us.min(MAX_WAIT_US) * FREQUENCY / 1_000_000).max(1) as u16;
+    let mut control = Port::<u8>::new(SPEAKER_CONTROL);
+    let mut command = Port::<u8>::new(COMMAND);
+    let mut data = Port::<u8>::new(CHANNEL2_DATA);
+    // Safety: PIT チャネル 2 とスピーカ制御ポートは他で使っていない
+    unsafe {
+        // スピーカは鳴らさずにゲートだけ開ける
+        let value = control.read();
+        control.write((value & !SPEAKER_ENABLE) | GATE2);
+        command.write(CMD_CHANNEL2_ONESHOT);
+        data.write(count as u8);
+        data.write((count >> 8) as u8);
+        // カウントが 0 になると OUT2 が High になる
+        while control.read() & OUT2 == 0 {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// `duration` だけビジーウェイトする
+pub fn busy_wait(duration: Duration) {
+    let mut remaining = duration.as_micros() as u64;
+    while remaining > 0 {
+        let step = remaining.min(MAX_WAIT_US);
+        wait_us(step);
+        remaining -= step;
+    }
+}
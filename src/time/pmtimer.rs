@@ -0,0 +1,50 @@
+//! ACPI PM タイマ
+//!
+//! - FADT に記載された I/O ポートの 3.579545MHz のカウンタ (24bit または 32bit)。
+//! - 読み出しは遅いが、ほぼすべての ACPI 対応機に存在し周波数が固定なので、較正の基準に使える。
+
+use spin::Once;
+use x86_64::instructions::port::Port;
+use super::clocksource::ClockSource;
+use crate::acpi;
+
+/// PM タイマの周波数 (Hz)
+pub const FREQUENCY: u64 = 3_579_545;
+
+static PM_TIMER: Once<PmTimer> = Once::new();
+
+pub struct PmTimer {
+    port: u16,
+    mask: u64,
+}
+
+/// FADT から PM タイマを見つける
+pub fn init() -> Result<&'static PmTimer, &'static str> {
+    let fadt = acpi::fadt().ok_or("FADT not found")?;
+    let port = fadt.pm_timer_port.ok_or("PM timer not present")?;
+    let mask = if fadt.pm_timer_32bit { u32::MAX as u64 } else { 0x00FF_FFFF };
+    Ok(PM_TIMER.call_once(|| PmTimer { port, mask }))
+}
+
+impl ClockSource for PmTimer {
+    fn name(&self) -> &'static str {
+        "acpi_pm"
+    }
+
+    fn rating(&self) -> u32 {
+        200
+    }
+
+    fn frequency(&self) -> u64 {
+        FREQUENCY
+    }
+
+    fn read(&self) -> u64 {
+        // Safety: FADT が示す PM タイマのポートは読み出し専用
+        unsafe { Port::<u32>::new(self.port).read() as u64 & self.mask }
+    }
+
+    fn mask(&self) -> u64 {
+        self.mask
+    }
+}
@@ -0,0 +1,315 @@
+//! CMOS RTC (Real Time Clock)
+//!
+//! - 日時レジスタは更新中 (UIP) を避け、同じ値が 2 回続けて読めるまで読み直す。
+//! - BCD / バイナリ、12 / 24 時間表記はステータスレジスタ B に従って変換する。
+//!   世紀は FADT が示すレジスタから読み、無ければ下 2 桁から 1970〜2069 年とみなす。
+//! - RTC は UTC を保持しているものとする。
+//! - 起動時に読んだ時刻と TSC (`Instant`) の経過時間から現在時刻を求めるので、
+//!   `unix_time` のたびに CMOS を読むことはない。
+//! - 周期割り込み (IRQ 8) を有効にすると、APIC タイマの代わりのティック源として使える。
+
+use core::fmt;
+use spin::{Mutex, Once};
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+use crate::interrupts::InterruptContext;
+use crate::irq::{self, Irq};
+use super::Instant;
+
+const CMOS_INDEX: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+const REG_STATUS_C: u8 = 0x0C;
+
+/// ステータス A: 更新中
+const STATUS_A_UIP: u8 = 1 << 7;
+const STATUS_A_RATE_MASK: u8 = 0x0F;
+/// ステータス B: 周期割り込み有効
+const STATUS_B_PIE: u8 = 1 << 6;
+/// ステータス B: バイナリ表記 (無効なら BCD)
+const STATUS_B_BINARY: u8 = 1 << 2;
+/// ステータス B: 24 時間表記 (無効なら 12 時間表記で、時の bit 7 が午後)
+const STATUS_B_24HOUR: u8 = 1 << 1;
+/// ステータス C: 周期割り込みが発生した
+const STATUS_C_PF: u8 = 1 << 6;
+const HOUR_PM: u8 = 1 << 7;
+
+/// 更新中が終わるのを待つ最大回数
+const MAX_RETRIES: usize = 1_000_000;
+
+/// CMOS のインデックスとデータの組を不可分に扱うためのロック
+///
+/// 割り込みハンドラ以外は割り込み禁止中にしか取らないので、
+/// ハンドラが同じ CPU で保持中のロックを待つことはない。
+static CMOS: Mutex<()> = Mutex::new(());
+
+/// 起動時に読んだ時刻とそのときの `Instant`
+static BASE: Once<(u64, Instant)> = Once::new();
+
+/// CMOS レジスタを読む (CMOS のロックを保持して呼ぶこと)
+fn read_register(reg: u8) -> u8 {
+    let mut index = Port::<u8>::new(CMOS_INDEX);
+    let mut data = Port::<u8>::new(CMOS_DATA);
+    // Safety: CMOS のポートは CMOS のロックで排他している。bit 7 (NMI 無効) は立てない
+    unsafe {
+        index.write(reg & 0x7F);
+        data.read()
+    }
+}
+
+/// CMOS レジスタに書く (CMOS のロックを保持して呼ぶこと)
+fn write_register(reg: u8, value: u8) {
+    let mut index = Port::<u8>::new(CMOS_INDEX);
+    let mut data = Port::<u8>::new(CMOS_DATA);
+    // Safety: read_register と同じ
+    unsafe {
+        index.write(reg & 0x7F);
+        data.write(value);
+    }
+}
+
+fn bcd_to_binary(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0F)
+}
+
+/// 日時 (UTC)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+/// 日時レジスタの生の値
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Raw {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: u8,
+}
+
+/// 更新中でないときの日時レジスタを読む
+fn read_raw(century_reg: u8) -> Result<Raw, &'static str> {
+    for _ in 0..MAX_RETRIES {
+        if read_register(REG_STATUS_A) & STATUS_A_UIP != 0 {
+            continue;
+        }
+        return Ok(Raw {
+            second: read_register(REG_SECONDS),
+            minute: read_register(REG_MINUTES),
+            hour: read_register(REG_HOURS),
+            day: read_register(REG_DAY),
+            month: read_register(REG_MONTH),
+            year: read_register(REG_YEAR),
+            century: if century_reg != 0 { read_register(century_reg) } else { 0 },
+        });
+    }
+    Err("RTC update in progress did not finish")
+}
+
+/// RTC から現在の日時を読む
+///
+/// ACPI の初期化前 (ExitBootServices 前を含む) は世紀レジスタを使わない。
+pub fn read() -> Result<DateTime, &'static str> {
+    let century_reg = crate::acpi::fadt().map_or(0, |fadt| fadt.century);
+    let (raw, status_b) = without_interrupts(|| {
+        let _cmos = CMOS.lock();
+        // 読んでいる途中で更新されていないよう、同じ値が続けて読めるまで繰り返す
+        let mut previous = read_raw(century_reg)?;
+        loop {
+            let current = read_raw(century_reg)?;
+            if current == previous {
+                return Ok((current, read_register(REG_STATUS_B)));
+            }
+            previous = current;
+        }
+    })?;
+    DateTime::from_raw(raw, status_b, century_reg != 0)
+}
+
+impl DateTime {
+    fn from_raw(raw: Raw, status_b: u8, has_century: bool) -> Result<DateTime, &'static str> {
+        let convert = |value: u8| if status_b & STATUS_B_BINARY != 0 { value } else { bcd_to_binary(value) };
+        let pm = raw.hour & HOUR_PM != 0;
+        let mut hour = convert(raw.hour & !HOUR_PM);
+        if status_b & STATUS_B_24HOUR == 0 {
+            // 12 時間表記: 午前 12 時は 0 時、午後は 12 を足す (午後 12 時は 12 時)
+            hour %= 12;
+            if pm {
+                hour += 12;
+            }
+        }
+        let year = convert(raw.year) as u16;
+        let year = if has_century {
+            convert(raw.century) as u16 * 100 + year
+        } else if year < 70 {
+            2000 + year
+        } else {
+            1900 + year
+        };
+        let datetime = DateTime {
+            year,
+            month: convert(raw.month),
+            day: convert(raw.day),
+            hour,
+            minute: convert(raw.minute),
+            second: convert(raw.second),
+        };
+        if !datetime.is_valid() {
+            return Err("RTC returned an invalid date");
+        }
+        Ok(datetime)
+    }
+
+    fn is_valid(&self) -> bool {
+        (1..=12).contains(&self.month)
+            && (1..=31).contains(&self.day)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+
+    /// UNIX 時間 (1970-01-01 00:00:00 UTC からの秒数) に変換する (1970 年より前なら `None`)
+    pub fn to_unix(self) -> Option<u64> {
+        let days = u64::try_from(days_from_civil(self.year as i64, self.month as i64, self.day as i64)).ok()?;
+        Some(days * 86_400 + self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64)
+    }
+
+    /// UNIX 時間から日時を求める
+    pub fn from_unix(secs: u64) -> DateTime {
+        let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+        let rem = secs % 86_400;
+        DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (rem / 3600) as u8,
+            minute: (rem / 60 % 60) as u8,
+            second: (rem % 60) as u8,
+        }
+    }
+
+    /// `YYYY-MM-DD HH:MM:SS` 形式の ASCII (ヒープ初期化前でも描画できるよう固定長で返す)
+    pub fn to_ascii(self) -> [u8; 19] {
+        let mut buf = *b"0000-00-00 00:00:00";
+        let mut put = |at: usize, value: u16, digits: usize| {
+            let mut value = value;
+            for i in (0..digits).rev() {
+                buf[at + i] = b'0' + (value % 10) as u8;
+                value /= 10;
+            }
+        };
+        put(0, self.year, 4);
+        put(5, self.month as u16, 2);
+        put(8, self.day as u16, 2);
+        put(11, self.hour as u16, 2);
+        put(14, self.minute as u16, 2);
+        put(17, self.second as u16, 2);
+        buf
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // to_ascii は ASCII のみを返す
+        f.write_str(core::str::from_utf8(&self.to_ascii()).unwrap_or(""))
+    }
+}
+
+/// 1970-01-01 からの日数 (グレゴリオ暦)
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// `days_from_civil` の逆変換
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// 現在時刻を読み、以後の `unix_time` の基準にする (ACPI の初期化後に呼ぶ)
+pub fn init() -> Result<DateTime, &'static str> {
+    let now = read()?;
+    let unix = now.to_unix().ok_or("RTC date is before 1970")?;
+    BASE.call_once(|| (unix, Instant::now()));
+    Ok(now)
+}
+
+/// 現在の UNIX 時間 (`init` 前は RTC を直接読み、読めなければ 0)
+pub fn unix_time() -> u64 {
+    match BASE.get() {
+        Some(&(unix, at)) => unix + at.elapsed().as_secs(),
+        None => read().ok().and_then(DateTime::to_unix).unwrap_or(0),
+    }
+}
+
+/// 周期割り込みのレート `rate` (3〜15) の周波数 (Hz)
+pub const fn periodic_frequency(rate: u8) -> u32 {
+    32_768 >> (rate - 1)
+}
+
+/// 周期割り込みを `periodic_frequency(rate)` Hz で発生させ、そのたびに `callback` を呼ぶ
+pub fn start_periodic(rate: u8, callback: fn()) -> Result<Irq, &'static str> {
+    if !(3..=15).contains(&rate) {
+        return Err("invalid RTC periodic rate");
+    }
+    let irq = irq::register_isa(8, move |_ctx: &InterruptContext| {
+        // ステータス C を読まないと次の割り込みが来ないので、ロックは必ず取る
+        let flags = {
+            let _cmos = CMOS.lock();
+            read_register(REG_STATUS_C)
+        };
+        if flags & STATUS_C_PF != 0 {
+            callback();
+        }
+    })?;
+    without_interrupts(|| {
+        let _cmos = CMOS.lock();
+        let a = read_register(REG_STATUS_A);
+        write_register(REG_STATUS_A, (a & !STATUS_A_RATE_MASK) | rate);
+        let b = read_register(REG_STATUS_B);
+        write_register(REG_STATUS_B, b | STATUS_B_PIE);
+        // 保留中のフラグを消して割り込みを受け付けられる状態にする
+        read_register(REG_STATUS_C);
+    });
+    Ok(irq)
+}
+
+/// 周期割り込みを止める
+pub fn stop_periodic(irq: Irq) {
+    without_interrupts(|| {
+        let _cmos = CMOS.lock();
+        let b = read_register(REG_STATUS_B);
+        write_register(REG_STATUS_B, b & !STATUS_B_PIE);
+        read_register(REG_STATUS_C);
+    });
+    irq::unregister(irq);
+}
@@ -0,0 +1,143 @@
+//! TSC (Time Stamp Counter)
+//!
+//! - 周波数は CPUID leaf 0x15 (クリスタル比) から正確に求まればそれを使い、
+//!   無ければ基準クロックソース (HPET / ACPI PM) で一定時間を測って較正する。
+//!   どちらも使えない場合は APIC タイマ較正時に PIT で測った値を使う。
+//! - Invariant TSC (電源状態・周波数変更の影響を受けない) なら最優先のクロックソースにし、
+//!   そうでなければ評価値を下げて他のタイマを優先させる。
+//! - 周波数が決まる前でも `read` は使えるので、起動直後から `Instant` で時刻を記録できる。
+
+use core::arch::x86_64::{__cpuid, _rdtsc};
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Once;
+use super::clocksource::{self, ClockSource};
+
+/// 基準クロックソースで較正する時間 (マイクロ秒)
+const CALIBRATION_US: u64 = 50_000;
+
+/// TSC の周波数 (Hz、未較正なら 0)
+static FREQUENCY: AtomicU64 = AtomicU64::new(0);
+static TSC: Once<Tsc> = Once::new();
+
+/// 周波数の求め方
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrequencySource {
+    /// CPUID leaf 0x15 (TSC / クリスタル比)
+    Cpuid15,
+    /// CPUID leaf 0x16 (プロセッサの基本周波数、概算)
+    Cpuid16,
+    /// 基準クロックソースで測定
+    Measured(&'static str),
+    /// APIC タイマ較正時の PIT による測定値
+    Pit,
+}
+
+pub struct Tsc {
+    frequency: u64,
+    pub invariant: bool,
+    pub source: FrequencySource,
+}
+
+/// TSC の現在値
+#[inline]
+pub fn read() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// Invariant TSC に対応しているか (CPUID 0x8000_0007 EDX bit 8)
+pub fn invariant() -> bool {
+    __cpuid(0x8000_0000).eax >= 0x8000_0007 && __cpuid(0x8000_0007).edx & (1 << 8) != 0
+}
+
+/// CPUID leaf 0x15 / 0x16 から TSC の周波数を求める
+fn frequency_from_cpuid() -> Option<(u64, FrequencySource)> {
+    let max_leaf = __cpuid(0).eax;
+    if max_leaf >= 0x15 {
+        let leaf = __cpuid(0x15);
+        let (denominator, numerator, crystal_hz) = (leaf.eax as u64, leaf.ebx as u64, leaf.ecx as u64);
+        if denominator != 0 && numerator != 0 && crystal_hz != 0 {
+            return Some((crystal_hz * numerator / denominator, FrequencySource::Cpuid15));
+        }
+    }
+    if max_leaf >= 0x16 {
+        let base_mhz = __cpuid(0x16).eax as u64 & 0xFFFF;
+        if base_mhz != 0 {
+            return Some((base_mhz * 1_000_000, FrequencySource::Cpuid16));
+        }
+    }
+    None
+}
+
+/// 基準クロックソースで `CALIBRATION_US` の間の TSC の増分を測る
+fn measure(reference: &dyn ClockSource) -> u64 {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let start = read();
+        clocksource::busy_wait_us(reference, CALIBRATION_US);
+        let end = read();
+        (end - start) * 1_000_000 / CALIBRATION_US
+    })
+}
+
+/// TSC の周波数を決めてクロックソースを作る
+///
+/// `pit_tsc_per_ms` は APIC タイマ較正時に測った 1ms あたりの増分 (最後の手段)。
+pub fn init(reference: Option<&dyn ClockSource>, pit_tsc_per_ms: u64) -> Result<&'static Tsc, &'static str> {
+    // leaf 0x15 は正確なのでそのまま使い、概算の leaf 0x16 より実測を優先する
+    let (frequency, source) = match (frequency_from_cpuid(), reference) {
+        (Some(exact @ (_, FrequencySource::Cpuid15)), _) => exact,
+        (_, Some(reference)) => (measure(reference), FrequencySource::Measured(reference.name())),
+        (Some(estimate), None) => estimate,
+        (None, None) => (pit_tsc_per_ms * 1000, FrequencySource::Pit),
+    };
+    if frequency == 0 {
+        return Err("TSC frequency unknown");
+    }
+    FREQUENCY.store(frequency, Ordering::Relaxed);
+    Ok(TSC.call_once(|| Tsc { frequency, invariant: invariant(), source }))
+}
+
+/// 較正済みの TSC クロックソース
+pub fn get() -> Option<&'static Tsc> {
+    TSC.get()
+}
+
+/// TSC の周波数 (Hz、未較正なら 0)
+pub fn frequency() -> u64 {
+    FREQUENCY.load(Ordering::Relaxed)
+}
+
+/// TSC の増分をナノ秒に変換する (未較正なら 0)
+pub fn cycles_to_ns(cycles: u64) -> u64 {
+    match frequency() {
+        0 => 0,
+        freq => (cycles as u128 * 1_000_000_000 / freq as u128) as u64,
+    }
+}
+
+/// ナノ秒を TSC の増分に変換する (未較正なら 0)
+pub fn ns_to_cycles(ns: u64) -> u64 {
+    (ns as u128 * frequency() as u128 / 1_000_000_000) as u64
+}
+
+impl ClockSource for Tsc {
+    fn name(&self) -> &'static str {
+        "tsc"
+    }
+
+    fn rating(&self) -> u32 {
+        // 周波数が変わりうる TSC は ACPI PM タイマより下にする
+        if self.invariant { 300 } else { 100 }
+    }
+
+    fn frequency(&self) -> u64 {
+        self.frequency
+    }
+
+    fn read(&self) -> u64 {
+        read()
+    }
+
+    fn mask(&self) -> u64 {
+        u64::MAX
+    }
+}